@@ -10,6 +10,10 @@ const val LOCAL_REPO_NAME = "git-adc83b19e"
 
 const val AUTO_LOCK_SECONDS: Long = 120
 
+// Number of commits to fetch when cloning, the full history is rarely needed
+// on a phone
+const val GIT_CLONE_DEPTH = 100
+
 const val TITLE_FONT_SIZE = 22
 const val TITLE2_FONT_SIZE = 18
 const val BODY_FONT_SIZE = 14
@@ -5,7 +5,6 @@ import kotlinx.coroutines.flow.MutableStateFlow
 import kotlinx.coroutines.flow.StateFlow
 import one.kafva.kage.AUTO_LOCK_SECONDS
 import one.kafva.kage.Log
-import java.io.File
 import java.time.Instant
 import javax.inject.Inject
 import javax.inject.Singleton
@@ -40,13 +39,7 @@ class AgeDataSource
 
         @Throws(AgeException::class)
         fun unlockIdentity(password: String) {
-            val encryptedIdentityPath =
-                File("${appDataSource.localRepo.toPath()}/.age-identities")
-            val encryptedIdentity =
-                encryptedIdentityPath.readText(
-                    Charsets.UTF_8,
-                )
-            val r = Jni.unlockIdentity(encryptedIdentity, password)
+            val r = Jni.unlockIdentity(appDataSource.store, password)
             if (r != 0) {
                 raiseError()
             } else {
@@ -74,7 +67,7 @@ class AgeDataSource
         }
 
         fun lockIdentity() {
-            val r = Jni.lockIdentity()
+            val r = Jni.lockIdentity(appDataSource.store)
             if (r == 0) {
                 _identityUnlockedAt.value = null
             }
@@ -87,6 +80,7 @@ class AgeDataSource
             }
             val value =
                 Jni.decrypt(
+                    appDataSource.store,
                     "${appDataSource.filesDir.toPath()}/$nodePath",
                 )
             if (value == null) {
@@ -98,7 +92,7 @@ class AgeDataSource
 
         @Throws(AgeException::class)
         private fun raiseError() {
-            val message = Jni.strerror()
+            val message = Jni.strerror(appDataSource.store)
             if (message != null) {
                 throw AgeException(message)
             } else {
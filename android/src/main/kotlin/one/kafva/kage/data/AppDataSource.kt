@@ -3,6 +3,7 @@ package one.kafva.kage.data
 import one.kafva.kage.LOCAL_REPO_NAME
 import java.io.File
 import javax.inject.Singleton
+import one.kafva.kage.jni.Store as Jni
 
 @Singleton
 class AppDataSource(
@@ -10,4 +11,25 @@ class AppDataSource(
     val filesDir: File,
 ) {
     val localRepo: File = File("$filesDir/${LOCAL_REPO_NAME}")
+
+    private var storeHandle: Int? = null
+
+    /** Handle for the local repository, registered on first use */
+    val store: Int
+        get() = storeHandle ?: registerStore()
+
+    /** (Re-)register the local repository, the handle stays the same when
+     * the store is registered again with a new remote.
+     */
+    fun registerStore(remoteUrl: String = ""): Int {
+        val r =
+            Jni.register(
+                localRepo.toPath().toString(),
+                remoteUrl,
+                "",
+                "${localRepo.toPath()}/.age-identities",
+            )
+        storeHandle = r
+        return r
+    }
 }
@@ -2,6 +2,7 @@ package one.kafva.kage.data
 
 import kotlinx.coroutines.flow.MutableStateFlow
 import kotlinx.coroutines.flow.StateFlow
+import one.kafva.kage.GIT_CLONE_DEPTH
 import one.kafva.kage.Log
 import one.kafva.kage.types.CommitInfo
 import one.kafva.kage.types.PwNode
@@ -21,7 +22,6 @@ class GitDataSource
     constructor(
         private val appDataSource: AppDataSource,
     ) {
-        private val repoStr = appDataSource.localRepo.toPath().toString()
         private val rootNode = MutableStateFlow<PwNode?>(null)
 
         private val _query = MutableStateFlow("")
@@ -65,9 +65,10 @@ class GitDataSource
                         throw GitException(e.message ?: "Unknown error")
                     })
             } else {
-                val r = Jni.clone(url, repoStr)
+                val store = appDataSource.registerStore(url)
+                val r = Jni.clone(store, GIT_CLONE_DEPTH, "")
                 if (r != 0) {
-                    raiseError()
+                    raiseError(store)
                 }
             }
 
@@ -75,8 +76,7 @@ class GitDataSource
 
             // Use the repo name as the username
             val username = appDataSource.localRepo.nameWithoutExtension
-            val repoPath = appDataSource.localRepo.toPath().toString()
-            Jni.setUser(repoPath, username)
+            Jni.setUser(appDataSource.store, username)
             Log.d("Set user.name=$username")
 
             setup()
@@ -88,31 +88,29 @@ class GitDataSource
         @Throws(GitException::class)
         fun remove(node: PwNode) {
             var r: Int
-            val path =
-                appDataSource.localRepo.toPath()
-                    ?: throw GitException("No root node set")
-            val repoPath = path.toString()
+            val store = appDataSource.store
 
             if (!node.delete()) {
-                Jni.reset(repoPath)
+                Jni.reset(store)
                 throw GitException("Failed to remove: '${node.name}'")
             }
 
-            r = Jni.stage(repoPath, node.name)
+            r = Jni.stage(store, node.name)
             if (r != 0) {
-                Jni.reset(repoPath)
-                raiseError()
+                Jni.reset(store)
+                raiseError(store)
             }
 
-            r = Jni.commit(repoPath, "Removed ${node.name}")
+            r = Jni.commit(store, "Removed ${node.name}")
             if (r != 0) {
-                Jni.reset(repoPath)
-                raiseError()
+                Jni.reset(store)
+                raiseError(store)
             }
         }
 
         fun log(): List<CommitInfo> =
-            Jni.log(repoStr)?.map { logStr -> CommitInfo(logStr) } ?: listOf()
+            Jni.log(appDataSource.store)?.map { logStr -> CommitInfo(logStr) }
+                ?: listOf()
 
         fun updateMatches(text: String) {
             _query.value = text.lowercase()
@@ -121,8 +119,8 @@ class GitDataSource
         }
 
         @Throws(GitException::class)
-        private fun raiseError() {
-            val message = Jni.strerror()
+        private fun raiseError(store: Int) {
+            val message = Jni.strerror(store)
             if (message != null) {
                 throw GitException(message)
             } else {
@@ -2,13 +2,16 @@ package one.kafva.kage.jni
 
 object Age {
     external fun unlockIdentity(
-        encryptedIdentity: String,
+        store: Int,
         password: String,
     ): Int
 
-    external fun lockIdentity(): Int
+    external fun lockIdentity(store: Int): Int
 
-    external fun decrypt(encryptedPath: String): String?
+    external fun decrypt(
+        store: Int,
+        encryptedPath: String,
+    ): String?
 
-    external fun strerror(): String?
+    external fun strerror(store: Int): String?
 }
@@ -1,29 +1,115 @@
 package one.kafva.kage.jni
 
 object Git {
+    external fun setup(
+        remote: String,
+        defaultBranch: String,
+        emailDomain: String,
+        timeoutMs: Int,
+        connectTimeoutMs: Int,
+    ): Int
+
+    external fun config(): Array<String>?
+
     external fun clone(
-        url: String,
-        into: String,
+        store: Int,
+        depth: Int,
+        sparseDirs: String,
+    ): Int
+
+    external fun deepen(
+        store: Int,
+        depth: Int,
+    ): Int
+
+    external fun branchList(store: Int): Array<String>?
+
+    external fun branchCreate(
+        store: Int,
+        name: String,
+    ): Int
+
+    external fun branchSwitch(
+        store: Int,
+        name: String,
     ): Int
 
     external fun setUser(
-        repoPath: String,
+        store: Int,
         username: String,
     ): Int
 
+    external fun setPrivateMessages(
+        store: Int,
+        enabled: Boolean,
+    ): Int
+
     external fun stage(
-        repoPath: String,
+        store: Int,
         relativePath: String,
     ): Int
 
-    external fun reset(repoPath: String): Int
+    external fun reset(store: Int): Int
+
+    external fun discard(
+        store: Int,
+        dryRun: Boolean,
+    ): Array<String>?
 
     external fun commit(
-        repoPath: String,
+        store: Int,
+        message: String,
+    ): Int
+
+    external fun squash(
+        store: Int,
         message: String,
     ): Int
 
-    external fun log(localRepoPath: String): Array<String>?
+    external fun amend(
+        store: Int,
+        message: String,
+    ): Int
+
+    external fun log(store: Int): Array<String>?
+
+    external fun entries(
+        store: Int,
+        query: String,
+    ): Array<String>?
+
+    external fun entryPath(
+        store: Int,
+        name: String,
+    ): Array<String>?
+
+    external fun entryStage(
+        store: Int,
+        name: String,
+    ): Int
+
+    external fun entryRename(
+        store: Int,
+        oldName: String,
+        newName: String,
+    ): Array<String>?
+
+    external fun entryRemove(
+        store: Int,
+        name: String,
+    ): Array<String>?
+
+    external fun lastChanged(
+        store: Int,
+        path: String,
+    ): Array<String>?
+
+    external fun stats(store: Int): Array<String>?
+
+    external fun maintenance(
+        store: Int,
+        gracePeriodS: Int,
+    ): Array<String>?
 
-    external fun strerror(): String?
+    external fun strerror(store: Int): String?
 }
@@ -0,0 +1,12 @@
+package one.kafva.kage.jni
+
+object Store {
+    external fun register(
+        repoPath: String,
+        remoteUrl: String,
+        branch: String,
+        identityPath: String,
+    ): Int
+
+    external fun unregister(store: Int): Int
+}
@@ -5,7 +5,6 @@
 //!                  This should be in the ascii-armored format, i.e. created with `age -a`
 
 use std::io::Read; // For .read_to_end()
-#[cfg(test)]
 use std::io::Write; // For .write_all()
 
 use crate::age_error::AgeError;
@@ -13,16 +12,18 @@ use crate::age_error::AgeError;
 #[cfg(not(target_os = "android"))]
 use crate::{error, level_to_color, log, log_prefix};
 
+use crate::store::{store_get, StoreHandle, StoreLock, StoreStates};
+
 use age;
+use age::secrecy::ExposeSecret;
 use age::secrecy::SecretString;
 use std::sync::LazyLock;
-use std::sync::{Mutex, MutexGuard};
 use zeroize::Zeroize;
 
-/// Persistent library state, the lock on this global state must
-/// be acquired before it is used in a multithreaded context.
-pub static AGE_STATE: LazyLock<Mutex<AgeState>> =
-    LazyLock::new(|| Mutex::new(AgeState::default()));
+/// Persistent library state for each store, the state of a store must be
+/// locked with `age_try_lock()` before it is used in a multithreaded context.
+static AGE_STATES: LazyLock<StoreStates<AgeState>> =
+    LazyLock::new(StoreStates::new);
 
 pub struct AgeState {
     /// Identity to use for decryption (public during tests)
@@ -85,12 +86,15 @@ impl AgeState {
         self.identity = None;
     }
 
+    /// Decrypt `ciphertext`, both the binary and the ascii-armored format
+    /// are accepted.
     pub fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, AgeError> {
         let Some(ref identity) = self.identity else {
             return Err(AgeError::NoIdentity);
         };
 
-        let Ok(decryptor) = age::Decryptor::new(ciphertext) else {
+        let reader = age::armor::ArmoredReader::new(ciphertext);
+        let Ok(decryptor) = age::Decryptor::new(reader) else {
             return Err(AgeError::BadCipherInput)
         };
 
@@ -122,7 +126,7 @@ impl AgeState {
         }
     }
 
-    #[cfg(test)]
+    #[cfg(any(test, not(target_os = "android")))]
     pub fn encrypt_passphrase_armored(
         &self,
         plaintext: &[u8],
@@ -143,12 +147,107 @@ impl AgeState {
     }
 }
 
-/// The lock is released once the returned `MutexGuard` is dropped, i.e. goes
-/// out of scope.
-pub fn age_try_lock() -> Option<MutexGuard<'static, AgeState>> {
-    let Ok(age_state) = AGE_STATE.try_lock() else {
-        error!("Mutex lock already taken");
-        return None;
-    };
-    Some(age_state)
+/// Lock the state of the store identified by `handle`, other stores can be
+/// used concurrently. The lock is released once the returned `StoreLock` is
+/// dropped, i.e. goes out of scope.
+pub fn age_try_lock(handle: StoreHandle) -> Option<StoreLock<AgeState>> {
+    AGE_STATES.try_lock(handle)
+}
+
+/// Returns the state for the store identified by `handle`, the state is
+/// created on first use. Returns `None` if there is no such store.
+pub fn age_state(
+    age_lock: &mut StoreLock<AgeState>,
+    handle: StoreHandle,
+) -> Option<&mut AgeState> {
+    store_get(handle)?;
+    Some(age_lock.get_or_insert_with(AgeState::default))
+}
+
+/// Encrypt `plaintext` to every recipient in `recipients`, one public key
+/// per line, empty lines and comments are ignored.
+#[cfg(not(target_os = "android"))]
+pub fn age_encrypt_to_recipients(
+    plaintext: &[u8],
+    recipients: &str,
+) -> Result<Vec<u8>, AgeError> {
+    let parsed = parse_recipients(recipients)?;
+    let encryptor = age::Encryptor::with_recipients(
+        parsed.iter().map(|r| r as &dyn age::Recipient),
+    )?;
+
+    let mut encrypted = vec![];
+    let mut writer = encryptor.wrap_output(&mut encrypted)?;
+    writer.write_all(plaintext)?;
+    writer.finish()?;
+
+    Ok(encrypted)
+}
+
+/// Same as `age_encrypt_to_recipients()` but the output is ascii-armored
+pub fn age_encrypt_to_recipients_armored(
+    plaintext: &[u8],
+    recipients: &str,
+) -> Result<String, AgeError> {
+    let parsed = parse_recipients(recipients)?;
+    let encryptor = age::Encryptor::with_recipients(
+        parsed.iter().map(|r| r as &dyn age::Recipient),
+    )?;
+
+    let mut encrypted = vec![];
+    let mut writer =
+        encryptor.wrap_output(age::armor::ArmoredWriter::wrap_output(
+            &mut encrypted,
+            age::armor::Format::AsciiArmor,
+        )?)?;
+    writer.write_all(plaintext)?;
+    writer.finish().and_then(|armor| armor.finish())?;
+
+    Ok(String::from_utf8(encrypted)?)
+}
+
+/// Returns a new random secret, e.g. to use as a key for hashing
+pub fn age_random_secret() -> String {
+    let identity = age::x25519::Identity::generate();
+    identity.to_string().expose_secret().to_string()
+}
+
+fn parse_recipients(
+    recipients: &str,
+) -> Result<Vec<age::x25519::Recipient>, AgeError> {
+    let mut parsed = vec![];
+    for line in recipients.lines().map(|l| l.trim()) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Ok(recipient) = line.parse::<age::x25519::Recipient>() else {
+            error!("Bad recepient: '{}'", line);
+            return Err(AgeError::BadRecepient);
+        };
+        parsed.push(recipient);
+    }
+    Ok(parsed)
+}
+
+/// Generate a new x25519 identity, returns the identity encrypted with
+/// `passphrase` in the ascii-armored format together with the public key.
+#[cfg(not(target_os = "android"))]
+pub fn age_generate_identity(
+    passphrase: &str,
+) -> Result<(Vec<u8>, String), AgeError> {
+    let identity = age::x25519::Identity::generate();
+    let recipient = identity.to_public().to_string();
+
+    // Same layout as the output from `age-keygen`
+    let mut age_key = format!(
+        "# public key: {}\n{}\n",
+        recipient,
+        identity.to_string().expose_secret()
+    );
+    let passphrase = SecretString::from(passphrase.to_owned());
+    let encrypted = AgeState::default()
+        .encrypt_passphrase_armored(age_key.as_bytes(), passphrase);
+    age_key.zeroize();
+
+    Ok((encrypted?, recipient))
 }
@@ -6,8 +6,7 @@
 #[derive(Debug)]
 pub enum AgeError {
     GenericError,
-    #[allow(dead_code)]
-    BadRecepient, // Unused on Android
+    BadRecepient,
     BadCipherInput,
     BadKey,
     NoIdentity,
@@ -5,31 +5,45 @@ use std::ptr::null;
 use totp::calculate_totp_now;
 
 use crate::*;
-use crate::age::age_try_lock;
+use crate::age::{age_state, age_try_lock};
 use crate::age_error::AgeError;
+use crate::store::store_get;
 use crate::util::path_to_filename;
 
+/// Unlock the passphrase encrypted identity of `store`
 #[no_mangle]
 pub extern "C" fn ffi_age_unlock_identity(
-    encrypted_identity: *const c_char,
+    store: c_int,
     passphrase: *const c_char,
 ) -> c_int {
-    let Some(mut age_state) = age_try_lock() else {
+    let Some(mut age_lock) = age_try_lock(store) else {
         return KAGE_ERROR_LOCK_TAKEN as c_int;
     };
+    let Some(age_state) = age_state(&mut age_lock, store) else {
+        return -1;
+    };
+    let Some(store) = store_get(store) else {
+        return -1;
+    };
 
-    let encrypted_identity =
-        unsafe { CStr::from_ptr(encrypted_identity).to_str() };
     let passphrase = unsafe { CStr::from_ptr(passphrase).to_str() };
 
-    let (Ok(encrypted_identity), Ok(passphrase)) =
-        (encrypted_identity, passphrase)
-    else {
+    let Ok(passphrase) = passphrase else {
         age_state.last_error = Some(AgeError::GenericError);
         return -1;
     };
 
-    match age_state.unlock_identity(encrypted_identity, passphrase) {
+    let identity_path = &store.identity_path;
+    let encrypted_identity = match std::fs::read_to_string(identity_path) {
+        Ok(encrypted_identity) => encrypted_identity,
+        Err(err) => {
+            error!("{}: {}", identity_path, err);
+            age_state.last_error = Some(AgeError::IoError(err));
+            return -1;
+        }
+    };
+
+    match age_state.unlock_identity(&encrypted_identity, passphrase) {
         Err(err) => {
             error!("{}", err);
             age_state.last_error = Some(err);
@@ -40,10 +54,13 @@ pub extern "C" fn ffi_age_unlock_identity(
 }
 
 #[no_mangle]
-pub extern "C" fn ffi_age_lock_identity() -> c_int {
-    let Some(mut age_state) = age_try_lock() else {
+pub extern "C" fn ffi_age_lock_identity(store: c_int) -> c_int {
+    let Some(mut age_lock) = age_try_lock(store) else {
         return KAGE_ERROR_LOCK_TAKEN as c_int;
     };
+    let Some(age_state) = age_state(&mut age_lock, store) else {
+        return -1;
+    };
     age_state.lock_identity();
     0
 }
@@ -51,13 +68,17 @@ pub extern "C" fn ffi_age_lock_identity() -> c_int {
 /// Encrypt `plaintext` for `recipient`, writing the ciphertext to `outpath`.
 #[no_mangle]
 pub extern "C" fn ffi_age_encrypt(
+    store: c_int,
     plaintext: *const c_char,
     recipient: *const c_char,
     outpath: *const c_char,
 ) -> c_int {
-    let Some(mut age_state) = age_try_lock() else {
+    let Some(mut age_lock) = age_try_lock(store) else {
         return KAGE_ERROR_LOCK_TAKEN as c_int;
     };
+    let Some(age_state) = age_state(&mut age_lock, store) else {
+        return -1;
+    };
 
     let plaintext = unsafe { CStr::from_ptr(plaintext).to_str() };
     let recipient = unsafe { CStr::from_ptr(recipient).to_str() };
@@ -100,9 +121,13 @@ pub extern "C" fn ffi_age_encrypt(
 /// URL, these will be resolved into TOTP codes before being returned.
 #[no_mangle]
 pub extern "C" fn ffi_age_decrypt(
+    store: c_int,
     encrypted_path: *const c_char,
 ) -> *const c_char {
-    let Some(mut age_state) = age_try_lock() else {
+    let Some(mut age_lock) = age_try_lock(store) else {
+        return null();
+    };
+    let Some(age_state) = age_state(&mut age_lock, store) else {
         return null();
     };
 
@@ -163,8 +188,11 @@ pub extern "C" fn ffi_age_decrypt(
 /// occurred if any. The string must be passed back to rust and freed!
 /// The internal `last_error` is cleared after being retrieved!
 #[no_mangle]
-pub extern "C" fn ffi_age_strerror() -> *const c_char {
-    let Some(mut age_state) = age_try_lock() else {
+pub extern "C" fn ffi_age_strerror(store: c_int) -> *const c_char {
+    let Some(mut age_lock) = age_try_lock(store) else {
+        return std::ptr::null();
+    };
+    let Some(age_state) = age_state(&mut age_lock, store) else {
         return std::ptr::null();
     };
     let Some(ref err) = age_state.last_error else {
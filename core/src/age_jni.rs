@@ -4,8 +4,9 @@ use jni::JNIEnv;
 
 use totp::calculate_totp_now;
 
-use crate::age::age_try_lock;
+use crate::age::{age_state, age_try_lock};
 use crate::age_error::AgeError;
+use crate::store::store_get;
 use crate::util::path_to_filename;
 use crate::KAGE_ERROR_LOCK_TAKEN;
 
@@ -26,17 +27,32 @@ macro_rules! jni_get_string {
 pub extern "system" fn Java_one_kafva_kage_jni_Age_unlockIdentity<'local>(
     mut env: JNIEnv<'local>,
     _class: JClass<'local>,
-    encrypted_identity: JString<'local>,
+    store: jint,
     passphrase: JString<'local>,
 ) -> jint {
-    let Some(mut age_state) = age_try_lock() else {
+    let Some(mut age_lock) = age_try_lock(store) else {
         return KAGE_ERROR_LOCK_TAKEN as jint;
     };
+    let Some(age_state) = age_state(&mut age_lock, store) else {
+        return -1 as jint;
+    };
+    let Some(store) = store_get(store) else {
+        return -1 as jint;
+    };
 
-    jni_get_string!(encrypted_identity, env, age_state, -1 as jint);
     jni_get_string!(passphrase, env, age_state, -1 as jint);
 
-    match age_state.unlock_identity(encrypted_identity, passphrase) {
+    let identity_path = &store.identity_path;
+    let encrypted_identity = match std::fs::read_to_string(identity_path) {
+        Ok(encrypted_identity) => encrypted_identity,
+        Err(err) => {
+            error!("{}: {}", identity_path, err);
+            age_state.last_error = Some(AgeError::IoError(err));
+            return -1 as jint;
+        }
+    };
+
+    match age_state.unlock_identity(&encrypted_identity, passphrase) {
         Err(err) => {
             error!("{}", err);
             age_state.last_error = Some(err);
@@ -50,10 +66,14 @@ pub extern "system" fn Java_one_kafva_kage_jni_Age_unlockIdentity<'local>(
 pub extern "system" fn Java_one_kafva_kage_jni_Age_lockIdentity<'local>(
     _env: JNIEnv<'local>,
     _class: JClass<'local>,
+    store: jint,
 ) -> jint {
-    let Some(mut age_state) = age_try_lock() else {
+    let Some(mut age_lock) = age_try_lock(store) else {
         return KAGE_ERROR_LOCK_TAKEN as jint;
     };
+    let Some(age_state) = age_state(&mut age_lock, store) else {
+        return -1 as jint;
+    };
     age_state.lock_identity();
     0
 }
@@ -62,9 +82,13 @@ pub extern "system" fn Java_one_kafva_kage_jni_Age_lockIdentity<'local>(
 pub extern "system" fn Java_one_kafva_kage_jni_Age_decrypt<'local>(
     mut env: JNIEnv<'local>,
     _class: JClass<'local>,
+    store: jint,
     encrypted_path: JString<'local>,
 ) -> JString<'local> {
-    let Some(mut age_state) = age_try_lock() else {
+    let Some(mut age_lock) = age_try_lock(store) else {
+        return JString::default();
+    };
+    let Some(age_state) = age_state(&mut age_lock, store) else {
         return JString::default();
     };
 
@@ -120,8 +144,12 @@ pub extern "system" fn Java_one_kafva_kage_jni_Age_decrypt<'local>(
 pub extern "system" fn Java_one_kafva_kage_jni_Age_strerror<'local>(
     env: JNIEnv<'local>,
     _class: JClass<'local>,
+    store: jint,
 ) -> JString<'local> {
-    let Some(mut age_state) = age_try_lock() else {
+    let Some(mut age_lock) = age_try_lock(store) else {
+        return JString::default();
+    };
+    let Some(age_state) = age_state(&mut age_lock, store) else {
         return JString::default();
     };
     let Some(ref err) = age_state.last_error else {
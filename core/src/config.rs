@@ -0,0 +1,99 @@
+//! Runtime configuration shared by all stores, passed in during setup.
+
+use std::sync::LazyLock;
+use std::sync::Mutex;
+
+static CONFIG: LazyLock<Mutex<Config>> =
+    LazyLock::new(|| Mutex::new(Config::default()));
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    /// Name of the remote to pull from and push to
+    pub remote: String,
+    /// Branch used for stores that are registered without one
+    pub default_branch: String,
+    /// Domain of the email address in commits, "<username>@<email_domain>"
+    pub email_domain: String,
+    /// Read/write timeout for remote operations
+    pub timeout_ms: i32,
+    /// Connection timeout for remote operations
+    pub connect_timeout_ms: i32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            remote: "origin".to_string(),
+            default_branch: "main".to_string(),
+            email_domain: "kafva.one".to_string(),
+            timeout_ms: 5000,
+            connect_timeout_ms: 5000,
+        }
+    }
+}
+
+impl Config {
+    /// Create a configuration, empty strings and non-positive timeouts are
+    /// replaced with their default values.
+    pub fn new(
+        remote: &str,
+        default_branch: &str,
+        email_domain: &str,
+        timeout_ms: i32,
+        connect_timeout_ms: i32,
+    ) -> Self {
+        let default = Self::default();
+        let or_default = |value: &str, default: String| {
+            if value.is_empty() {
+                default
+            } else {
+                value.to_string()
+            }
+        };
+        Self {
+            remote: or_default(remote, default.remote),
+            default_branch: or_default(default_branch, default.default_branch),
+            email_domain: or_default(email_domain, default.email_domain),
+            timeout_ms: if timeout_ms > 0 {
+                timeout_ms
+            } else {
+                default.timeout_ms
+            },
+            connect_timeout_ms: if connect_timeout_ms > 0 {
+                connect_timeout_ms
+            } else {
+                default.connect_timeout_ms
+            },
+        }
+    }
+
+    /// Serialize into an array of "<key>\n<value>" strings
+    pub fn to_vec(&self) -> Vec<String> {
+        vec![
+            format!("remote\n{}", self.remote),
+            format!("default_branch\n{}", self.default_branch),
+            format!("email_domain\n{}", self.email_domain),
+            format!("timeout_ms\n{}", self.timeout_ms),
+            format!("connect_timeout_ms\n{}", self.connect_timeout_ms),
+        ]
+    }
+}
+
+/// Returns a copy of the current configuration
+pub fn config_get() -> Config {
+    let Ok(config) = CONFIG.lock() else {
+        error!("Configuration unavailable");
+        return Config::default();
+    };
+    config.clone()
+}
+
+/// Replace the current configuration, see `git_configure()` for applying
+/// the timeouts to the underlying library.
+pub fn config_set(config: Config) {
+    let Ok(mut current) = CONFIG.lock() else {
+        error!("Configuration unavailable");
+        return;
+    };
+    *current = config;
+}
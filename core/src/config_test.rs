@@ -0,0 +1,20 @@
+use crate::config::*;
+
+#[test]
+/// Test that unset values fall back to the defaults
+fn config_new_test() {
+    let default = Config::default();
+    assert_eq!(Config::new("", "", "", 0, -1), default);
+
+    let config = Config::new("upstream", "staging", "example.com", 200, 100);
+    assert_eq!(config.remote, "upstream");
+    assert_eq!(config.default_branch, "staging");
+    assert_eq!(config.email_domain, "example.com");
+    assert_eq!(config.timeout_ms, 200);
+    assert_eq!(config.connect_timeout_ms, 100);
+
+    let arr = config.to_vec();
+    assert_eq!(arr.len(), 5);
+    assert_eq!(arr[0], "remote\nupstream");
+    assert_eq!(arr[4], "connect_timeout_ms\n100");
+}
@@ -1,7 +1,12 @@
-use std::sync::LazyLock;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+#[cfg(not(target_os = "android"))]
+use std::io::Write; // For .write_all()
 use std::path::Path;
+use std::sync::Arc;
+use std::sync::LazyLock;
 use std::sync::Mutex;
-use std::sync::MutexGuard;
 use std::sync::Once;
 
 use git2::build::RepoBuilder;
@@ -14,30 +19,72 @@ use git2::opts::{
 
 use git2::{FetchOptions, RemoteCallbacks, Repository};
 
-#[cfg(not(target_os = "android"))]
 use git2::build::CheckoutBuilder;
 
+use crate::age::AgeState;
+#[cfg(not(target_os = "android"))]
+use crate::age::{age_encrypt_to_recipients, age_generate_identity};
+use crate::age::{age_encrypt_to_recipients_armored, age_random_secret};
+use crate::age_error::AgeError;
+use crate::config::{config_get, config_set, Config};
+use crate::store::{Store, StoreHandle, StoreLock, StoreStates};
 use crate::*;
 
 const TRANSFER_STAGES: usize = 4;
-const GIT_EMAIL: &'static str = env!("KAGE_GIT_EMAIL");
+/// Same as GIT_FETCH_DEPTH_UNSHALLOW in libgit2
+const GIT_DEPTH_UNSHALLOW: i32 = i32::MAX;
+/// First line of a version 2 bundle file
+#[cfg(not(target_os = "android"))]
+const GIT_BUNDLE_SIGNATURE: &'static str = "# v2 git bundle";
+/// Multivar in the repository config with the directories to checkout
+const GIT_CONFIG_SPARSE_DIR: &'static str = "kage.sparsedir";
+/// Boolean in the repository config, commit messages only describe the number
+/// of changed entries and the real message is encrypted in the commit body.
+const GIT_CONFIG_PRIVATE_MESSAGES: &'static str = "kage.privatemessages";
+/// First line of the encrypted message in the body of a private commit
+const GIT_PRIVATE_MESSAGE_HEADER: &'static str =
+    "-----BEGIN AGE ENCRYPTED FILE-----";
+/// Age-encrypted index with the real name of each entry in stores with
+/// encrypted filenames, see `git_entries_encrypt()`.
+const GIT_NAME_INDEX: &'static str = ".age-index";
+/// Files that are never removed from a store by `git_discard()`
+const GIT_PROTECTED_FILES: [&'static str; 2] =
+    [".age-identities", ".age-recipients"];
+/// Key in the remote section of the repository config that marks a mirror
+#[cfg(not(target_os = "android"))]
+const GIT_CONFIG_MIRROR: &'static str = "kagemirror";
 
-#[cfg(not(test))]
-const GIT_REMOTE: &'static str = env!("KAGE_GIT_REMOTE");
-#[cfg(test)]
-pub const GIT_REMOTE: &'static str = env!("KAGE_GIT_REMOTE");
+/// The last commit that was exchanged through a sync directory
+#[cfg(not(target_os = "android"))]
+const GIT_SYNC_REF: &'static str = "refs/kage/sync";
+/// Namespace for the commits saved by `git_recover()` and `git_repair()`
+#[cfg(not(target_os = "android"))]
+const GIT_BACKUP_REF_PREFIX: &'static str = "refs/kage/backup/";
 
-#[cfg(not(test))]
-const GIT_BRANCH: &'static str = env!("KAGE_GIT_BRANCH");
-#[cfg(test)]
-pub const GIT_BRANCH: &'static str = env!("KAGE_GIT_BRANCH");
+/// Names of the files in a sync directory that have been applied locally
+#[cfg(not(target_os = "android"))]
+const GIT_CONFIG_SYNC_APPLIED: &'static str = "kage.syncapplied";
+/// File in the git directory with the commit mapping from the last purge
+#[cfg(not(target_os = "android"))]
+const GIT_PURGE_MAP: &'static str = "kage-purge-map";
+/// Trailer in the root commit created by `git_compact()`
+#[cfg(not(target_os = "android"))]
+const GIT_COMPACTED_TRAILER: &'static str = "Kage-Compacted";
 
 /// Persistent library state for last error that occurred
 /// The git2::Error::last_error() method does not fit our needs, the error
 /// message we want to show tends to be overwritten from later successful
-/// invocations of git functions before we can retrieve it.
-static GIT_LAST_ERROR: LazyLock<Mutex<Option<git2::Error>>> =
-    LazyLock::new(|| Mutex::new(None));
+/// invocations of git functions before we can retrieve it. The last error is
+/// kept separately for each store.
+static GIT_LAST_ERROR: LazyLock<StoreStates<git2::Error>> =
+    LazyLock::new(StoreStates::new);
+
+/// Results from `git_last_changed()` for each repository path, valid as long
+/// as the branch points to the same commit. Entries are removed when the
+/// store is unregistered, see `git_last_changed_evict()`.
+static GIT_LAST_CHANGED: LazyLock<
+    Mutex<HashMap<String, (git2::Oid, Arc<Vec<GitLastChange>>)>>,
+> = LazyLock::new(|| Mutex::new(HashMap::new()));
 
 static ONCE: Once = Once::new();
 
@@ -67,312 +114,4105 @@ macro_rules! internal_error {
 
 /// One-time initialization of the underlying library
 pub fn git_setup() {
-    ONCE.call_once(|| git_init_opts().expect("Error initializing libgit2"));
+    ONCE.call_once(|| {
+        git_init_opts(&config_get()).expect("Error initializing libgit2")
+    });
 }
 
-#[cfg(not(target_os = "android"))]
-pub fn git_pull(repo_path: &str) -> Result<(), git2::Error> {
-    let repo = Repository::open(repo_path)?;
-    let mut remote = repo.find_remote(GIT_REMOTE)?;
-
-    // Fetch remote changes
-    let mut cb = git2::RemoteCallbacks::new();
-    let mut fopts = git2::FetchOptions::new();
-    cb.transfer_progress(|progress| transfer_progress(progress, "Fetching"));
-    fopts.remote_callbacks(cb);
-
-    remote.fetch(&[GIT_BRANCH], Some(&mut fopts), None)?;
-
-    // Update the local checkout to use the remote head (fast-forward)
-    let remote_origin_head = remote_branch_oid(&repo)?;
-    let remote_origin_head = repo.find_annotated_commit(remote_origin_head)?;
-
-    let analysis = repo.merge_analysis(&[&remote_origin_head])?;
-
-    if analysis.0.is_up_to_date() {
-        debug!("Already up to date.");
-    } else if analysis.0.is_fast_forward() {
-        let head_ref_name = format!("refs/heads/{}", GIT_BRANCH);
-        let mut head_reference = repo.find_reference(&head_ref_name)?;
-
-        let reflog_message = format!(
-            "Fast-Forward: {} -> {}",
-            head_ref_name,
-            remote_origin_head.id()
-        );
-        debug!("{}", reflog_message);
-        head_reference.set_target(remote_origin_head.id(), &reflog_message)?;
-        repo.set_head(&head_ref_name)?;
-        repo.checkout_head(Some(CheckoutBuilder::default().force()))?;
-    } else {
-        debug!("Cannot fast-forward");
-        return Err(git2::Error::new(
-            git2::ErrorCode::NotFastForward,
-            git2::ErrorClass::None,
-            "Cannot fast-forward",
-        ));
-    }
+/// Replace the runtime configuration and apply it to the underlying library
+pub fn git_configure(config: Config) -> Result<(), git2::Error> {
+    git_init_opts(&config)?;
+    config_set(config);
     Ok(())
 }
 
+/// Entry level changes between two commits
 #[cfg(not(target_os = "android"))]
-pub fn git_push(repo_path: &str) -> Result<(), git2::Error> {
-    let repo = Repository::open(repo_path)?;
-    let mut remote = repo.find_remote(GIT_REMOTE)?;
+#[derive(Debug)]
+pub struct GitDiffSummary {
+    pub old_head: git2::Oid,
+    pub new_head: git2::Oid,
+    pub added: Vec<String>,
+    pub modified: Vec<String>,
+    pub deleted: Vec<String>,
+    /// Pairs of (old path, new path)
+    pub renamed: Vec<(String, String)>,
+    /// Sync bundles that diverged from the local branch, see
+    /// `git_sync_import()`
+    pub skipped: Vec<String>,
+}
 
-    let mut push_options = git2::PushOptions::new();
-    let mut remote_callbacks = git2::RemoteCallbacks::new();
+#[cfg(not(target_os = "android"))]
+impl GitDiffSummary {
+    /// Serialize into an array of newline separated strings:
+    ///     "H\n<old head>\n<new head>"
+    ///     "A\n<path>", "M\n<path>", "D\n<path>"
+    ///     "R\n<old path>\n<new path>"
+    ///     "S\n<bundle name>"
+    pub fn to_vec(&self) -> Vec<String> {
+        let mut arr = vec![format!("H\n{}\n{}", self.old_head, self.new_head)];
+        arr.extend(self.added.iter().map(|p| format!("A\n{}", p)));
+        arr.extend(self.modified.iter().map(|p| format!("M\n{}", p)));
+        arr.extend(self.deleted.iter().map(|p| format!("D\n{}", p)));
+        arr.extend(
+            self.renamed
+                .iter()
+                .map(|(from, to)| format!("R\n{}\n{}", from, to)),
+        );
+        arr.extend(self.skipped.iter().map(|name| format!("S\n{}", name)));
+        arr
+    }
 
-    remote_callbacks.push_transfer_progress(|current, total, _| {
-        if current == total {
-            debug!("Pushing: [{:4} / {:4}] Done", current, total);
-            return;
+    /// Show the real names of entries in stores with encrypted filenames
+    fn translate(&mut self, names: &NameIndex) {
+        let paths = self.added.iter_mut();
+        let paths = paths.chain(self.modified.iter_mut());
+        for path in paths.chain(self.deleted.iter_mut()) {
+            *path = names.translate_path(path);
         }
-
-        if total <= TRANSFER_STAGES {
-            return;
+        for (from, to) in self.renamed.iter_mut() {
+            *from = names.translate_path(from);
+            *to = names.translate_path(to);
         }
+    }
+}
 
-        let increments = total / TRANSFER_STAGES;
-        if current % increments == 0 {
-            debug!("Pushing: [{:4} / {:4}]", current, total);
+/// Commit metadata for the history view
+#[derive(Debug, Clone)]
+pub struct GitCommitInfo {
+    pub oid: git2::Oid,
+    pub time: i64,
+    pub author: String,
+    pub summary: String,
+}
+
+impl std::fmt::Display for GitCommitInfo {
+    /// "<timestamp>\n<oid>\n<author>\n<summary>"
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}\n{}\n{}\n{}",
+            self.time, self.oid, self.author, self.summary
+        )
+    }
+}
+
+impl GitCommitInfo {
+    fn new(commit: &git2::Commit) -> Self {
+        Self {
+            oid: commit.id(),
+            time: commit.time().seconds(),
+            author: commit.author().name().unwrap_or_default().to_string(),
+            summary: commit.summary().unwrap_or_default().to_string(),
         }
-    });
-    push_options.remote_callbacks(remote_callbacks);
+    }
+}
 
-    let mut refspecs = [format!("refs/heads/{}", GIT_BRANCH)];
-    remote.push(&mut refspecs, Some(&mut push_options))?;
+/// Incoming changes from the remote that have not been pulled yet
+#[cfg(not(target_os = "android"))]
+#[derive(Debug)]
+pub struct GitFetchPreview {
+    /// Commits on the remote branch that are not reachable from the local
+    /// branch, newest first.
+    pub commits: Vec<GitCommitInfo>,
+    /// Entry level changes between the common ancestor of the local and
+    /// remote branch and the remote branch.
+    pub changes: GitDiffSummary,
+}
 
-    Ok(())
+#[cfg(not(target_os = "android"))]
+impl GitFetchPreview {
+    /// Serialize into an array of newline separated strings, each commit is
+    /// given as "C\n<timestamp>\n<oid>\n<author>\n<summary>" followed by
+    /// the output from `GitDiffSummary::to_vec()`.
+    pub fn to_vec(&self) -> Vec<String> {
+        let mut arr: Vec<String> =
+            self.commits.iter().map(|c| format!("C\n{}", c)).collect();
+        arr.extend(self.changes.to_vec());
+        arr
+    }
 }
 
-pub fn git_stage(
-    repo_path: &str,
-    relative_path: &str,
-) -> Result<(), git2::Error> {
-    let repo = Repository::open(repo_path)?;
-    let mut index = repo.index()?;
+/// Outcome of `git_recover()`
+#[cfg(not(target_os = "android"))]
+#[derive(Debug)]
+pub struct GitRecovery {
+    /// Reference to the local branch as it was before the recovery
+    pub backup_ref: String,
+    /// Local commits that were replayed onto the rewritten remote branch
+    pub replayed: Vec<GitCommitInfo>,
+    /// Local commits that could not be replayed, these are only reachable
+    /// from the backup reference.
+    pub failed: Vec<GitCommitInfo>,
+}
 
-    let cb = &mut |path: &Path, _matched_spec: &[u8]| -> i32 {
-        let Some(path_str) = path.to_str() else {
-            error!("Empty path encountered in '{}'", relative_path);
-            return 1;
-        };
+#[cfg(not(target_os = "android"))]
+impl GitRecovery {
+    /// Serialize into an array of newline separated strings, the backup
+    /// reference is given as "B\n<ref>" followed by each replayed commit as
+    /// "R\n<commit>" and each failed commit as "F\n<commit>", see
+    /// `GitCommitInfo`.
+    pub fn to_vec(&self) -> Vec<String> {
+        let mut arr = vec![format!("B\n{}", self.backup_ref)];
+        arr.extend(self.replayed.iter().map(|c| format!("R\n{}", c)));
+        arr.extend(self.failed.iter().map(|c| format!("F\n{}", c)));
+        arr
+    }
+}
 
-        let Ok(status) = repo.status_file(path) else {
-            warn!("Unknown status: '{}'", path_str);
-            return 1;
-        };
+/// Outcome of `git_repair()`
+#[cfg(not(target_os = "android"))]
+#[derive(Debug)]
+pub struct GitRepair {
+    /// Problems that were detected, nothing is changed if this is empty
+    pub problems: Vec<String>,
+    /// Path that the broken repository was moved to
+    pub backup_path: String,
+    /// Reference to the unpushed commits that were saved from the broken
+    /// repository, empty if there were none
+    pub saved_ref: String,
+    /// Number of saved commits that were re-applied onto the new clone
+    pub reapplied: usize,
+}
 
-        match status {
-            git2::Status::WT_MODIFIED => {
-                debug!("Modified: '{}'", path_str);
-                0
-            }
-            git2::Status::WT_NEW => {
-                debug!("Added: '{}'", path_str);
-                0
-            }
-            git2::Status::WT_DELETED => {
-                debug!("Deleted: '{}'", path_str);
-                0
-            }
-            git2::Status::WT_RENAMED => {
-                debug!("Renamed: '{:?}'", path_str);
-                0
-            }
-            _ => 1,
+#[cfg(not(target_os = "android"))]
+impl GitRepair {
+    /// Serialize into an array of newline separated strings, each problem
+    /// is given as "P\n<problem>" followed by "B\n<backup_path>",
+    /// "S\n<saved_ref>" and "R\n<reapplied>" if a repair was made.
+    pub fn to_vec(&self) -> Vec<String> {
+        let mut arr: Vec<String> =
+            self.problems.iter().map(|p| format!("P\n{}", p)).collect();
+        if !self.problems.is_empty() {
+            arr.push(format!("B\n{}", self.backup_path));
+            arr.push(format!("S\n{}", self.saved_ref));
+            arr.push(format!("R\n{}", self.reapplied));
         }
-    };
+        arr
+    }
+}
 
-    // The callback is only invoked for filepaths (leafs), not directories.
-    // An empty directory will not give any errors
-    index.add_all(
-        Some(relative_path),
-        git2::IndexAddOption::DEFAULT,
-        Some(cb),
-    )?;
-    index.write()
+/// Outcome of `git_purge()`
+#[cfg(not(target_os = "android"))]
+#[derive(Debug)]
+pub struct GitPurge {
+    /// Each commit on the branch and the commit that replaced it, commits
+    /// that only contained purged changes map to their new parent.
+    pub mapping: Vec<(git2::Oid, git2::Oid)>,
+    /// Number of commits that were changed or dropped
+    pub rewritten: usize,
+    /// Set if the rewritten branch was force-pushed
+    pub pushed: bool,
+    /// References that still point to the original history, e.g. the remote
+    /// tracking branch if the result was not force-pushed. The purged
+    /// objects are kept by `git_maintenance()` as long as these exist.
+    pub retained: Vec<String>,
 }
 
-pub fn git_commit(repo_path: &str, message: &str) -> Result<(), git2::Error> {
-    let repo = Repository::open(repo_path)?;
-    // Retrieve the commit that HEAD points to so that we can replace
-    // it with our new tree state.
-    let head = repo.head()?;
-    let mut index = repo.index()?;
-    let statuses = repo.statuses(None)?;
+#[cfg(not(target_os = "android"))]
+impl GitPurge {
+    /// Serialize the mapping into an array of "<old oid>\n<new oid>" strings
+    /// followed by a "retained\n<reference>" string for each retained
+    /// reference
+    pub fn to_vec(&self) -> Vec<String> {
+        let mut arr: Vec<String> = self
+            .mapping
+            .iter()
+            .map(|(old, new)| format!("{}\n{}", old, new))
+            .collect();
+        arr.extend(self.retained.iter().map(|r| format!("retained\n{}", r)));
+        arr
+    }
+}
 
-    let is_clean = statuses.iter().all(|entry| {
-        let status = entry.status();
-        status.is_empty()
-    });
+/// Outcome of `git_compact()`
+#[cfg(not(target_os = "android"))]
+#[derive(Debug)]
+pub struct GitCompact {
+    /// The new root commit with the snapshot of the discarded history
+    pub root: git2::Oid,
+    /// Number of commits that were squashed into the root commit
+    pub squashed: usize,
+    /// Number of newer commits that were kept on top of the root commit
+    pub kept: usize,
+    /// Set if the compacted branch was force-pushed
+    pub pushed: bool,
+    /// References that still point to the original history, see
+    /// `GitPurge::retained`
+    pub retained: Vec<String>,
+}
 
-    if is_clean {
-        error!("Refusing to create empty commit");
-        return Err(internal_error!());
+#[cfg(not(target_os = "android"))]
+impl GitCompact {
+    /// Serialize into an array of "<key>\n<value>" strings
+    pub fn to_vec(&self) -> Vec<String> {
+        vec![
+            format!("root\n{}", self.root),
+            format!("squashed\n{}", self.squashed),
+            format!("kept\n{}", self.kept),
+            format!("pushed\n{}", self.pushed),
+        ]
+        .into_iter()
+        .chain(self.retained.iter().map(|r| format!("retained\n{}", r)))
+        .collect()
     }
+}
 
-    let sig = repo.signature()?;
-    let tree_id = index.write_tree()?;
-    let tree = repo.find_tree(tree_id)?;
+/// Update the remote tracking branch without modifying the local branch or
+/// the working tree, returns the changes that a `git_pull()` would apply.
+/// Entries are shown with their real names in stores with encrypted
+/// filenames if the identity in `age_state` is unlocked.
+#[cfg(not(target_os = "android"))]
+pub fn git_fetch(
+    store: &Store,
+    age_state: &AgeState,
+) -> Result<GitFetchPreview, git2::Error> {
+    let repo = Repository::open(&store.repo_path)?;
+    fetch_remote(&repo, &store.branch, 0)?;
 
-    let Some(oid) = head.target() else {
-        error!("HEAD unwrap error");
-        return Err(internal_error!());
+    let local_oid = repo.revparse_single(&store.branch)?.id();
+    let remote_oid = match remote_branch_oid(&repo, &store.branch) {
+        Ok(oid) => oid,
+        // Branches that have not been pushed yet have nothing to fetch
+        Err(err) if err.code() == git2::ErrorCode::NotFound => {
+            debug!("No remote branch for '{}'", store.branch);
+            let changes = diff_summary(&repo, local_oid, local_oid)?;
+            return Ok(GitFetchPreview {
+                commits: vec![],
+                changes,
+            });
+        }
+        Err(err) => return Err(err),
     };
-    let parent_commit = repo.find_commit(oid)?;
-
-    let _oid = repo.commit(
-        Some("HEAD"),
-        &sig,
-        &sig,
-        message,
-        &tree,
-        &[&parent_commit],
-    )?;
-
-    debug!("[{} {:.7}] {}", GIT_BRANCH, _oid, message);
-    Ok(())
-}
+    let base_oid = repo.merge_base(local_oid, remote_oid)?;
 
-/// Hard reset to the local HEAD, discarding all uncommited (and staged) local
-/// changes. Note: untracked files and directories that have not been staged
-/// need to be manually removed.
-pub fn git_reset(repo_path: &str) -> Result<(), git2::Error> {
-    let repo = Repository::open(repo_path)?;
-    let local_head = repo.revparse_single(GIT_BRANCH)?.id();
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(remote_oid)?;
+    revwalk.hide(local_oid)?;
 
-    debug!("Resetting HEAD to {} {}", GIT_BRANCH, local_head);
+    let mut commits = vec![];
+    for oid in revwalk {
+        let commit = repo.find_commit(oid?)?;
+        commits.push(GitCommitInfo::new(&commit));
+    }
+    debug!("{} incoming commit(s)", commits.len());
 
-    let obj = repo.find_object(local_head, None)?;
-    repo.reset(&obj, git2::ResetType::Hard, None)
+    let mut changes = diff_summary(&repo, base_oid, remote_oid)?;
+    // The index from the remote branch also knows the incoming entries
+    if let Some(names) = name_index_load_commit(&repo, remote_oid, age_state) {
+        changes.translate(&names);
+    }
+    Ok(GitFetchPreview { commits, changes })
 }
 
-pub fn git_clone(url: &str, into: &str) -> Result<(), git2::Error> {
-    let mut cb = RemoteCallbacks::new();
-    cb.transfer_progress(|progress| transfer_progress(progress, "Cloning"));
+/// Fetch and fast-forward to the remote branch, returns a summary of the
+/// entries that changed in the local checkout. Entries are shown with their
+/// real names in stores with encrypted filenames if the identity in
+/// `age_state` is unlocked.
+#[cfg(not(target_os = "android"))]
+pub fn git_pull(
+    store: &Store,
+    age_state: &AgeState,
+) -> Result<GitDiffSummary, git2::Error> {
+    let repo = Repository::open(&store.repo_path)?;
 
-    let mut fopts = FetchOptions::new();
-    fopts.remote_callbacks(cb);
+    // Fetch remote changes
+    fetch_remote(&repo, &store.branch, 0)?;
 
-    RepoBuilder::new()
-        .fetch_options(fopts)
-        .clone(url, Path::new(into))?;
-    Ok(())
+    let remote_oid = match remote_branch_oid(&repo, &store.branch) {
+        Ok(oid) => oid,
+        // Branches that have not been pushed yet have nothing to pull
+        Err(err) if err.code() == git2::ErrorCode::NotFound => {
+            debug!("No remote branch for '{}'", store.branch);
+            let head = repo.revparse_single(&store.branch)?.id();
+            return diff_summary(&repo, head, head);
+        }
+        Err(err) => return Err(err),
+    };
+    let mut summary = match fast_forward(&repo, &store.branch, remote_oid) {
+        Err(err)
+            if err.code() == git2::ErrorCode::NotFastForward
+                && remote_compacted(&repo, &store.branch, remote_oid)? =>
+        {
+            Err(compacted_error())
+        }
+        Err(err)
+            if err.code() == git2::ErrorCode::NotFastForward
+                && remote_rewritten(&repo, &store.branch)?.is_some() =>
+        {
+            Err(git2::Error::new(
+                git2::ErrorCode::NotFastForward,
+                git2::ErrorClass::Reference,
+                "Remote history was rewritten",
+            ))
+        }
+        result => result,
+    }?;
+
+    if let Some(names) = name_index_load_commit(&repo, remote_oid, age_state) {
+        summary.translate(&names);
+    }
+    Ok(summary)
 }
 
-/// Returns true if remote and local HEAD are equal
+/// Recover after the history of the remote branch was rewritten, e.g. with a
+/// force-push, which makes `git_pull()` fail. The local branch is saved to a
+/// backup reference and reset to the remote branch, commits that were never
+/// on the remote are then replayed on top of it. Commits that can not be
+/// replayed cleanly are skipped and reported. Uncommitted changes must be
+/// committed or discarded first.
 #[cfg(not(target_os = "android"))]
-pub fn git_local_head_matches_remote(
-    repo_path: &str,
-) -> Result<bool, git2::Error> {
-    let repo = Repository::open(repo_path)?;
-    let head = repo.head()?;
+pub fn git_recover(store: &Store) -> Result<GitRecovery, git2::Error> {
+    let repo = Repository::open(&store.repo_path)?;
+    fetch_remote(&repo, &store.branch, 0)?;
 
-    let remote_oid = remote_branch_oid(&repo)?;
-    let Some(local_oid) = head.target() else {
-        warn!("Could not determine local HEAD");
-        return Ok(false);
+    let head = repo.revparse_single(&store.branch)?.peel_to_commit()?.id();
+    let remote_oid = remote_branch_oid(&repo, &store.branch)?;
+    // Replaying on top of a compacted history would keep the old history
+    if remote_compacted(&repo, &store.branch, remote_oid)? {
+        return Err(compacted_error());
+    }
+    let Some(old_remote_oid) = remote_rewritten(&repo, &store.branch)? else {
+        return Err(git2::Error::new(
+            git2::ErrorCode::GenericError,
+            git2::ErrorClass::Reference,
+            "Remote history has not been rewritten",
+        ));
     };
+    if head == remote_oid || repo.graph_descendant_of(head, remote_oid)? {
+        return Err(git2::Error::new(
+            git2::ErrorCode::GenericError,
+            git2::ErrorClass::Reference,
+            "Local branch is already based on the remote branch",
+        ));
+    }
 
-    Ok(local_oid == remote_oid)
-}
-
-pub fn git_config_set_user(
-    repo_path: &str,
-    username: &str,
-) -> Result<(), git2::Error> {
-    let config_path = Path::new(repo_path).join(".git").join("config");
-    let mut cfg = git2::Config::open(&config_path)?;
-
-    cfg.set_str("user.name", username)?;
-    cfg.set_str("user.email", &format!("{}@{}", username, GIT_EMAIL))?;
+    if has_uncommitted_changes(&repo)? {
+        error!("Refusing to recover with uncommitted changes");
+        return Err(internal_error!());
+    }
 
-    // Echoing back the config gives errors...
-    //  https://github.com/rust-lang/git-rs/issues/474
-    Ok(())
-}
+    let backup_ref = format!(
+        "{}{}-{}",
+        GIT_BACKUP_REF_PREFIX,
+        store.branch,
+        current_time_ms()
+    );
+    repo.reference(&backup_ref, head, false, "Backup before recovery")?;
+    debug!("Saved {:.7} to {}", head, backup_ref);
 
-/// Returns an array of "<timestamp>\n<oid>\n<summary>" strings for all commits.
-/// The first commit will be the last entry in the array
-pub fn git_log(repo_path: &str) -> Result<Vec<String>, git2::Error> {
-    let repo = Repository::open(repo_path)?;
+    // Local commits that were never part of the remote history, oldest first
     let mut revwalk = repo.revwalk()?;
-    let remote_oid = remote_branch_oid(&repo)?;
+    revwalk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::REVERSE)?;
+    revwalk.push(head)?;
+    revwalk.hide(old_remote_oid)?;
+    revwalk.hide(remote_oid)?;
 
-    let mut arr = vec![];
-    revwalk.push_head()?;
-    for c in revwalk {
-        let Ok(oid) = c else { break };
-        let Ok(commit) = repo.find_commit(oid) else {
-            break;
-        };
-        let Some(summary) = commit.summary() else {
-            break;
+    let sig = repo.signature()?;
+    let mut base = repo.find_commit(remote_oid)?;
+    let mut replayed = vec![];
+    let mut failed = vec![];
+    for oid in revwalk {
+        let commit = repo.find_commit(oid?)?;
+        let index = match repo.cherrypick_commit(&commit, &base, 0, None) {
+            Ok(index) if !index.has_conflicts() => Some(index),
+            _ => None,
         };
-
-        // Prettify the remote head
-        let revstr = if oid == remote_oid {
-            format!("{}/{}", GIT_REMOTE, GIT_BRANCH)
-        } else {
-            oid.to_string()
+        let Some(mut index) = index else {
+            debug!("Could not replay {:.7}", commit.id());
+            failed.push(GitCommitInfo::new(&commit));
+            continue;
         };
 
-        let commit_info = format!(
-            "{}\n{}\n{}",
-            commit.time().seconds(),
-            revstr,
-            summary.to_string(),
-        );
-        arr.push(commit_info)
+        let tree_id = index.write_tree_to(&repo)?;
+        if tree_id == base.tree_id() {
+            debug!("Already applied {:.7}", commit.id());
+            continue;
+        }
+        let tree = repo.find_tree(tree_id)?;
+        let message = commit.message().unwrap_or_default();
+        let oid = repo.commit(
+            None,
+            &commit.author(),
+            &sig,
+            message,
+            &tree,
+            &[&base],
+        )?;
+        base = repo.find_commit(oid)?;
+        replayed.push(GitCommitInfo::new(&base));
     }
-    Ok(arr)
+
+    let head_ref_name = format!("refs/heads/{}", store.branch);
+    let reflog_message = format!("Recover: {} -> {}", remote_oid, base.id());
+    repo.reference(&head_ref_name, base.id(), true, &reflog_message)?;
+    repo.set_head(&head_ref_name)?;
+    repo.checkout_head(Some(CheckoutBuilder::default().force()))?;
+    apply_sparse_dirs(&repo)?;
+
+    Ok(GitRecovery {
+        backup_ref,
+        replayed,
+        failed,
+    })
 }
 
-/// Acquire the last error mutex, should be called before each method call in a
-/// multithreaded environment. The lock is released once the returned
-/// `MutexGuard` is dropped, i.e. goes out of scope.
-pub fn git_try_lock() -> Option<MutexGuard<'static, Option<git2::Error>>> {
-    let Ok(git_last_error) = GIT_LAST_ERROR.try_lock() else {
-        error!("Mutex lock already taken");
-        return None;
+/// Check the repository of the store for corruption and replace it with a
+/// new clone if any problems are found. Unpushed commits are saved as a
+/// bundle before the repository is replaced and re-applied onto the new
+/// clone. The broken repository is kept at `GitRepair::backup_path`.
+/// The swap is two renames, the broken repository is moved away before the
+/// new clone is moved into place. A swap that was interrupted in between is
+/// finished by the next call, unpushed commits are then only kept in the
+/// backup.
+#[cfg(not(target_os = "android"))]
+pub fn git_repair(store: &Store) -> Result<GitRepair, git2::Error> {
+    repair_resume(store)?;
+    let mut repair = GitRepair {
+        problems: repo_problems(&store.repo_path, &store.branch),
+        backup_path: String::new(),
+        saved_ref: String::new(),
+        reapplied: 0,
     };
-    Some(git_last_error)
-}
+    if repair.problems.is_empty() {
+        debug!("No problems found");
+        return Ok(repair);
+    }
+    for problem in repair.problems.iter() {
+        warn!("{}", problem);
+    }
 
-/// Initialize global options in the underlying library
-fn git_init_opts() -> Result<(), git2::Error> {
-    unsafe {
-        #[cfg(not(test))]
-        {
-            set_server_timeout_in_milliseconds(5000)?;
-            set_server_connect_timeout_in_milliseconds(5000)?;
+    // Save what we can from the broken repository
+    let mut saved = None;
+    let mut depth = 0;
+    let mut sparse = vec![];
+    let mut username = None;
+    if let Ok(repo) = Repository::open(&store.repo_path) {
+        let head = repo.revparse_single(&store.branch).map(|obj| obj.id());
+        let basis = remote_branch_oid(&repo, &store.branch).ok();
+        if head.is_ok() && head.ok() != basis {
+            match bundle_data(&repo, &store.branch, basis) {
+                Ok((data, count)) => {
+                    debug!("Saved {} unpushed commit(s)", count);
+                    saved = Some(data);
+                }
+                Err(err) => warn!("Could not save unpushed commits: {}", err),
+            }
+        }
+        if repo.is_shallow() {
+            depth = 1;
         }
+        sparse = sparse_dirs(&repo).unwrap_or_default();
+        username = git2::Config::open(&repo.path().join("config"))
+            .and_then(|cfg| cfg.get_string("user.name"))
+            .ok();
+    }
 
-        #[cfg(test)]
-        {
-            set_server_timeout_in_milliseconds(1000)?;
-            set_server_connect_timeout_in_milliseconds(1000)?;
+    // Clone next to the broken repository so that it can be renamed in place
+    let now = current_time_ms();
+    let tmp_path = format!("{}.repair-{}", store.repo_path, now);
+    let mut tmp_store = store.clone();
+    tmp_store.repo_path = tmp_path.clone();
+    let sparse: Vec<&str> = sparse.iter().map(|d| d.as_str()).collect();
+    git_clone(&tmp_store, depth, &sparse)?;
+    if let Some(ref username) = username {
+        git_config_set_user(&tmp_store, username)?;
+    }
+
+    // Keep key files that are not part of the repository
+    for name in GIT_PROTECTED_FILES {
+        let path = Path::new(&store.repo_path).join(name);
+        let tmp_file_path = Path::new(&tmp_path).join(name);
+        if path.exists() && !tmp_file_path.exists() {
+            if let Err(err) = std::fs::copy(&path, &tmp_file_path) {
+                return Err(os_error(&path, err));
+            }
         }
+    }
 
-        let timeout = get_server_timeout_in_milliseconds()?;
-        let connect_timeout = get_server_connect_timeout_in_milliseconds()?;
+    repair.backup_path = format!("{}.broken-{}", store.repo_path, now);
+    let repo_path = Path::new(&store.repo_path);
+    if repo_path.exists() {
+        if let Err(err) = std::fs::rename(repo_path, &repair.backup_path) {
+            return Err(os_error(repo_path, err));
+        }
+    }
+    if let Err(err) = std::fs::rename(&tmp_path, repo_path) {
+        let _ = std::fs::rename(&repair.backup_path, repo_path);
+        return Err(os_error(repo_path, err));
+    }
 
-        debug_safe!("Configured read/write timeout: {} ms", timeout);
-        debug_safe!("Configured connect timeout: {} ms", connect_timeout);
+    let Some(data) = saved else {
+        return Ok(repair);
     };
-    Ok(())
+    let repo = Repository::open(&store.repo_path)?;
+    let target = bundle_apply(&repo, &store.branch, &data, "Saved commits")?;
+    repair.saved_ref =
+        format!("{}{}-{}", GIT_BACKUP_REF_PREFIX, store.branch, now);
+    repo.reference(&repair.saved_ref, target, false, "Saved before repair")?;
+
+    let remote_oid = remote_branch_oid(&repo, &store.branch)?;
+    if target != remote_oid && repo.graph_descendant_of(target, remote_oid)? {
+        let mut revwalk = repo.revwalk()?;
+        revwalk.push(target)?;
+        revwalk.hide(remote_oid)?;
+        repair.reapplied = revwalk.count();
+        fast_forward(&repo, &store.branch, target)?;
+    } else if target != remote_oid {
+        warn!("Saved commits are not based on the remote branch");
+    }
+
+    Ok(repair)
+}
+
+/// Rewrite every commit on the branch without the given `paths`, directories
+/// are removed with all of their content, and without any blob with an id
+/// in `blob_ids`, similar to `git filter-repo`. Commits that become empty
+/// are dropped. The mapping from old to new commits is returned and saved
+/// to `.git/kage-purge-map`. The branch of the store is rewritten together
+/// with the backup and sync references, run `git_maintenance()` afterwards to
+/// remove the purged objects. Other references that still point to the
+/// original history are not changed and are returned as retained.
+/// The rewritten branch is only force-pushed to the remote and all mirrors
+/// if `confirm_force_push` is set, this replaces the remote history!
+#[cfg(not(target_os = "android"))]
+pub fn git_purge(
+    store: &Store,
+    paths: &[&str],
+    blob_ids: &[&str],
+    confirm_force_push: bool,
+) -> Result<GitPurge, git2::Error> {
+    let repo = Repository::open(&store.repo_path)?;
+    if has_uncommitted_changes(&repo)? {
+        error!("Refusing to purge with uncommitted changes");
+        return Err(internal_error!());
+    }
+    let paths: Vec<&str> = paths.iter().map(|p| p.trim_matches('/')).collect();
+    let mut blobs = HashSet::new();
+    for blob_id in blob_ids {
+        blobs.insert(git2::Oid::from_str(blob_id)?);
+    }
+
+    let head = repo.revparse_single(&store.branch)?.peel_to_commit()?.id();
+    let kage_refs = kage_refs(&repo)?;
+    let mut revwalk = repo.revwalk()?;
+    revwalk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::REVERSE)?;
+    revwalk.push(head)?;
+    for (_, oid) in kage_refs.iter() {
+        revwalk.push(*oid)?;
+    }
+
+    let mut trees = HashMap::new();
+    let mut mapped: HashMap<git2::Oid, git2::Oid> = HashMap::new();
+    let mut purge = GitPurge {
+        mapping: vec![],
+        rewritten: 0,
+        pushed: false,
+        retained: vec![],
+    };
+    for oid in revwalk {
+        let commit = repo.find_commit(oid?)?;
+        let tree = commit.tree()?;
+        let new_tree_id =
+            purge_tree(&repo, &tree, "", &paths, &blobs, &mut trees)?;
+        let mut parents = vec![];
+        for parent_id in commit.parent_ids() {
+            let parent_id = *mapped.get(&parent_id).unwrap_or(&parent_id);
+            parents.push(repo.find_commit(parent_id)?);
+        }
+
+        // Drop commits that only contained purged changes
+        let is_empty = parents.len() == 1
+            && parents[0].tree_id() == new_tree_id
+            && commit.parent(0)?.tree_id() != tree.id();
+
+        let new_oid = if is_empty {
+            parents[0].id()
+        } else if new_tree_id == tree.id()
+            && parents.iter().map(|p| p.id()).eq(commit.parent_ids())
+        {
+            commit.id()
+        } else {
+            let new_tree = repo.find_tree(new_tree_id)?;
+            let parents: Vec<&git2::Commit> = parents.iter().collect();
+            repo.commit(
+                None,
+                &commit.author(),
+                &commit.committer(),
+                commit.message().unwrap_or_default(),
+                &new_tree,
+                &parents,
+            )?
+        };
+        if new_oid != commit.id() {
+            purge.rewritten += 1;
+        }
+        mapped.insert(commit.id(), new_oid);
+        purge.mapping.push((commit.id(), new_oid));
+    }
+
+    let map_path = repo.path().join(GIT_PURGE_MAP);
+    let map: String = purge
+        .mapping
+        .iter()
+        .map(|(old, new)| format!("{} {}\n", old, new))
+        .collect();
+    if let Err(err) = std::fs::write(&map_path, map) {
+        return Err(os_error(&map_path, err));
+    }
+
+    let new_head = mapped[&head];
+    if new_head != head {
+        let reflog_message = format!("Purge: {} -> {}", head, new_head);
+        replace_branch(&repo, &store.branch, new_head, &reflog_message)?;
+    }
+    for (ref_name, oid) in kage_refs.iter() {
+        if mapped[oid] != *oid {
+            repo.reference(ref_name, mapped[oid], true, "Purge")?;
+        }
+    }
+    debug!("Rewrote {} commit(s)", purge.rewritten);
+
+    if confirm_force_push {
+        force_push(&repo, &store.branch)?;
+        purge.pushed = true;
+    }
+
+    let rewritten: HashSet<git2::Oid> = purge
+        .mapping
+        .iter()
+        .filter(|(old, new)| old != new)
+        .map(|(old, _)| *old)
+        .collect();
+    purge.retained = refs_reaching(&repo, &rewritten)?;
+    for ref_name in purge.retained.iter() {
+        warn!("{} still points to the original history", ref_name);
+    }
+
+    Ok(purge)
+}
+
+/// Replace the history up to and including `before` with a single root
+/// commit that has the same tree, newer commits are kept on top of it.
+/// `before` is a revision, if it is empty the newest commit that is not newer
+/// than the unix timestamp `before_time` is used instead. The commit is
+/// searched on all parents of merges, older commits that are not ancestors
+/// of it are kept on top of the root commit like newer ones. The full
+/// history is first written as a bundle to `backup_path`. The backup and
+/// sync references are rewritten together with the branch, other references
+/// to the original history are returned as retained.
+/// The compacted branch is only force-pushed to the remote and all mirrors
+/// if `confirm_force_push` is set. Other clients will fail to pull with an
+/// error that tells them to clone the store again.
+#[cfg(not(target_os = "android"))]
+pub fn git_compact(
+    store: &Store,
+    before: &str,
+    before_time: i64,
+    backup_path: &str,
+    confirm_force_push: bool,
+) -> Result<GitCompact, git2::Error> {
+    let repo = Repository::open(&store.repo_path)?;
+    if has_uncommitted_changes(&repo)? {
+        error!("Refusing to compact with uncommitted changes");
+        return Err(internal_error!());
+    }
+    let head = repo.revparse_single(&store.branch)?.peel_to_commit()?;
+
+    let cutoff = if before.is_empty() {
+        let mut revwalk = repo.revwalk()?;
+        revwalk.set_sorting(git2::Sort::TIME | git2::Sort::TOPOLOGICAL)?;
+        revwalk.push(head.id())?;
+        let mut cutoff = None;
+        for oid in revwalk {
+            let commit = repo.find_commit(oid?)?;
+            if commit.time().seconds() <= before_time {
+                cutoff = Some(commit);
+                break;
+            }
+        }
+        let Some(cutoff) = cutoff else {
+            return Err(git2::Error::new(
+                git2::ErrorCode::NotFound,
+                git2::ErrorClass::Reference,
+                format!("No commit before {}", before_time),
+            ));
+        };
+        cutoff
+    } else {
+        repo.revparse_single(before)?.peel_to_commit()?
+    };
+    if cutoff.id() != head.id()
+        && !repo.graph_descendant_of(head.id(), cutoff.id())?
+    {
+        return Err(git2::Error::new(
+            git2::ErrorCode::NotFound,
+            git2::ErrorClass::Reference,
+            format!("{} is not on {}", cutoff.id(), store.branch),
+        ));
+    }
+
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(cutoff.id())?;
+    let squashed_commits = revwalk.collect::<Result<Vec<_>, _>>()?;
+    let squashed = squashed_commits.len();
+    if squashed < 2 {
+        return Err(git2::Error::new(
+            git2::ErrorCode::GenericError,
+            git2::ErrorClass::Invalid,
+            "Nothing to compact",
+        ));
+    }
+
+    let (data, _) = bundle_data(&repo, &store.branch, None)?;
+    if let Err(err) = std::fs::write(backup_path, &data) {
+        return Err(os_error(Path::new(backup_path), err));
+    }
+    debug!("Saved the full history to '{}'", backup_path);
+
+    let message = format!(
+        "Compacted history\n\n{}: {}\n",
+        GIT_COMPACTED_TRAILER,
+        cutoff.id()
+    );
+    let root = repo.commit(
+        None,
+        &cutoff.author(),
+        &repo.signature()?,
+        &message,
+        &cutoff.tree()?,
+        &[],
+    )?;
+
+    // Keep the newer commits, parents from the discarded history are
+    // replaced with the root commit
+    let mut revwalk = repo.revwalk()?;
+    revwalk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::REVERSE)?;
+    revwalk.push(head.id())?;
+    revwalk.hide(cutoff.id())?;
+
+    let mut mapped: HashMap<git2::Oid, git2::Oid> = HashMap::new();
+    let mut new_head = root;
+    for oid in revwalk {
+        let commit = repo.find_commit(oid?)?;
+        let mut parent_ids = vec![];
+        for parent_id in commit.parent_ids() {
+            let parent_id = *mapped.get(&parent_id).unwrap_or(&root);
+            if !parent_ids.contains(&parent_id) {
+                parent_ids.push(parent_id);
+            }
+        }
+        let mut parents = vec![];
+        for parent_id in parent_ids {
+            parents.push(repo.find_commit(parent_id)?);
+        }
+        let parents: Vec<&git2::Commit> = parents.iter().collect();
+        new_head = repo.commit(
+            None,
+            &commit.author(),
+            &commit.committer(),
+            commit.message().unwrap_or_default(),
+            &commit.tree()?,
+            &parents,
+        )?;
+        mapped.insert(commit.id(), new_head);
+    }
+
+    let reflog_message = format!("Compact: {} -> {}", head.id(), new_head);
+    replace_branch(&repo, &store.branch, new_head, &reflog_message)?;
+
+    mapped.insert(cutoff.id(), root);
+    for (ref_name, oid) in kage_refs(&repo)? {
+        if let Some(new_oid) = mapped.get(&oid) {
+            repo.reference(&ref_name, *new_oid, true, "Compact")?;
+        }
+    }
+
+    let mut compact = GitCompact {
+        root,
+        squashed,
+        kept: mapped.len() - 1,
+        pushed: false,
+        retained: vec![],
+    };
+    debug!("Compacted {} commit(s) into {:.7}", squashed, root);
+
+    if confirm_force_push {
+        force_push(&repo, &store.branch)?;
+        compact.pushed = true;
+    }
+
+    let mut replaced: HashSet<git2::Oid> = mapped.into_keys().collect();
+    replaced.extend(squashed_commits);
+    compact.retained = refs_reaching(&repo, &replaced)?;
+    for ref_name in compact.retained.iter() {
+        warn!("{} still points to the original history", ref_name);
+    }
+
+    Ok(compact)
+}
+
+/// Write the commits on the local branch that are not reachable from `basis`
+/// into a bundle file at `path`. An empty `basis` exports the full history.
+/// The bundle can be imported with `git_bundle_import()` or `git fetch`.
+/// Returns the number of exported commits.
+#[cfg(not(target_os = "android"))]
+pub fn git_bundle_export(
+    store: &Store,
+    path: &str,
+    basis: &str,
+) -> Result<usize, git2::Error> {
+    let repo = Repository::open(&store.repo_path)?;
+    let basis = if basis.is_empty() {
+        None
+    } else {
+        Some(repo.revparse_single(basis)?.peel_to_commit()?.id())
+    };
+    let (data, count) = bundle_data(&repo, &store.branch, basis)?;
+
+    if let Err(err) = std::fs::write(path, &data) {
+        return Err(os_error(Path::new(path), err));
+    }
+    debug!("Exported {} commit(s) to '{}'", count, path);
+    Ok(count)
+}
+
+/// Import the branch of the store from a bundle file, this behaves like a
+/// `git_pull()` where the remote tracking branch is updated from the bundle
+/// instead of from the remote. The remote tracking branch is only updated
+/// after a successful fast-forward.
+#[cfg(not(target_os = "android"))]
+pub fn git_bundle_import(
+    store: &Store,
+    path: &str,
+) -> Result<GitDiffSummary, git2::Error> {
+    let repo = Repository::open(&store.repo_path)?;
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(err) => return Err(os_error(Path::new(path), err)),
+    };
+    let target = bundle_apply(&repo, &store.branch, &data, path)?;
+    let summary = fast_forward(&repo, &store.branch, target)?;
+
+    // Update the remote tracking branch, same as a fetch, unless the bundle
+    // is older than what the tracking branch already points to
+    let remote_ref_name =
+        format!("refs/remotes/{}/{}", config_get().remote, store.branch);
+    let descends = match repo.refname_to_id(&remote_ref_name) {
+        Ok(tracked) => {
+            tracked == target || repo.graph_descendant_of(target, tracked)?
+        }
+        Err(_) => true,
+    };
+    if descends {
+        let reflog_message = format!("Bundle import: {}", path);
+        repo.reference(&remote_ref_name, target, true, &reflog_message)?;
+    }
+
+    Ok(summary)
+}
+
+/// Write the local commits that have not been exchanged yet as a bundle into
+/// the sync directory `dir`, e.g. a mounted network share. The bundle is
+/// encrypted to the recipients in `.age-recipients` and the file name only
+/// contains a timestamp and a hash of the ciphertext, no commit metadata
+/// is readable without an identity. Returns the number of exported commits.
+#[cfg(not(target_os = "android"))]
+pub fn git_sync_export(store: &Store, dir: &str) -> Result<usize, git2::Error> {
+    let repo = Repository::open(&store.repo_path)?;
+    let head = repo.revparse_single(&store.branch)?.peel_to_commit()?.id();
+    let basis = sync_basis(&repo)?;
+    if basis == Some(head) {
+        debug!("Nothing to export");
+        return Ok(0);
+    }
+
+    let (data, count) = bundle_data(&repo, &store.branch, basis)?;
+
+    let recipients = store_recipients(store)?;
+    let ciphertext = match age_encrypt_to_recipients(&data, &recipients) {
+        Ok(ciphertext) => ciphertext,
+        Err(err) => return Err(age_error("Encryption", err)),
+    };
+
+    let oid = git2::Oid::hash_object(git2::ObjectType::Blob, &ciphertext)?;
+    let name = format!("{:013}-{}.age", current_time_ms(), oid);
+
+    // Write to a hidden file first so that readers never see partial bundles
+    let path = Path::new(dir).join(&name);
+    let tmp_path = Path::new(dir).join(format!(".{}", name));
+    if let Err(err) = std::fs::write(&tmp_path, &ciphertext) {
+        return Err(os_error(&tmp_path, err));
+    }
+    if let Err(err) = std::fs::rename(&tmp_path, &path) {
+        return Err(os_error(&path, err));
+    }
+
+    // Our own bundles should never be imported again
+    set_sync_applied(&repo, &name)?;
+    repo.reference(GIT_SYNC_REF, head, true, "Sync export")?;
+
+    debug!("Exported {} commit(s) to '{}'", count, path.display());
+    Ok(count)
+}
+
+/// Decrypt and apply the bundles in the sync directory `dir` that were
+/// written by other devices, in the order that they were created. Bundles
+/// that diverged from the local branch are recorded as skipped and are not
+/// retried, the import stops at the first other bundle that can not be
+/// applied. Returns a summary of the entries that changed in the local
+/// checkout.
+#[cfg(not(target_os = "android"))]
+pub fn git_sync_import(
+    store: &Store,
+    age_state: &AgeState,
+    dir: &str,
+) -> Result<GitDiffSummary, git2::Error> {
+    let repo = Repository::open(&store.repo_path)?;
+    let old_head = repo.revparse_single(&store.branch)?.peel_to_commit()?.id();
+
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => return Err(os_error(Path::new(dir), err)),
+    };
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .filter(|name| !name.starts_with('.') && name.ends_with(".age"))
+        .collect();
+    names.sort();
+
+    let applied = sync_applied(&repo)?;
+    sync_applied_prune(&repo, &applied, &names)?;
+
+    let mut skipped = vec![];
+    for name in names.iter().filter(|name| !applied.contains(*name)) {
+        let path = Path::new(dir).join(name);
+        let ciphertext = match std::fs::read(&path) {
+            Ok(ciphertext) => ciphertext,
+            Err(err) => return Err(os_error(&path, err)),
+        };
+        let data = match age_state.decrypt(&ciphertext) {
+            Ok(data) => data,
+            Err(err) => {
+                return Err(git2::Error::new(
+                    git2::ErrorCode::GenericError,
+                    git2::ErrorClass::Invalid,
+                    format!("{}: {}", name, err),
+                ))
+            }
+        };
+
+        let target = bundle_apply(&repo, &store.branch, &data, name)?;
+        match fast_forward(&repo, &store.branch, target) {
+            Ok(_) => {}
+            Err(err) if err.code() == git2::ErrorCode::NotFastForward => {
+                warn!("Skipping diverged bundle '{}'", name);
+                set_sync_applied(&repo, name)?;
+                skipped.push(name.to_string());
+                continue;
+            }
+            Err(err) => return Err(err),
+        }
+
+        let head = repo.head()?.peel_to_commit()?.id();
+        set_sync_applied(&repo, name)?;
+        repo.reference(GIT_SYNC_REF, head, true, "Sync import")?;
+        debug!("Applied '{}'", name);
+    }
+
+    let new_head = repo.revparse_single(&store.branch)?.peel_to_commit()?.id();
+    let mut summary = diff_summary(&repo, old_head, new_head)?;
+    summary.skipped = skipped;
+    Ok(summary)
+}
+
+/// A remote of the store, the primary remote is authoritative for
+/// `git_pull()` while mirrors only receive pushes.
+#[cfg(not(target_os = "android"))]
+#[derive(Debug, PartialEq)]
+pub struct GitRemote {
+    pub name: String,
+    pub url: String,
+    pub primary: bool,
+}
+
+#[cfg(not(target_os = "android"))]
+impl std::fmt::Display for GitRemote {
+    /// "<P|M>\n<name>\n<url>"
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = if self.primary { 'P' } else { 'M' };
+        write!(f, "{}\n{}\n{}", kind, self.name, self.url)
+    }
+}
+
+/// Outcome of pushing to one remote, see `git_push()`
+#[cfg(not(target_os = "android"))]
+#[derive(Debug)]
+pub struct GitPushResult {
+    pub remote: String,
+    pub error: Option<git2::Error>,
+}
+
+#[cfg(not(target_os = "android"))]
+impl std::fmt::Display for GitPushResult {
+    /// "<remote>\n" on success, "<remote>\n<error message>" on failure
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.error {
+            Some(ref err) => write!(f, "{}\n{}", self.remote, err.message()),
+            None => write!(f, "{}\n", self.remote),
+        }
+    }
+}
+
+/// Push to the primary remote and then to each mirror. A failure to push to
+/// the primary remote is returned as an error, failures for mirrors are only
+/// reported in the returned results.
+#[cfg(not(target_os = "android"))]
+pub fn git_push(store: &Store) -> Result<Vec<GitPushResult>, git2::Error> {
+    let repo = Repository::open(&store.repo_path)?;
+    let primary = config_get().remote;
+    let refspec = format!("refs/heads/{}", store.branch);
+
+    push_remote(&repo, &primary, &refspec)?;
+    let mut results = vec![GitPushResult {
+        remote: primary,
+        error: None,
+    }];
+
+    for name in mirror_names(&repo)? {
+        let error = push_remote(&repo, &name, &refspec).err();
+        if let Some(ref err) = error {
+            warn!("Push to mirror '{}' failed: {}", name, err);
+        }
+        results.push(GitPushResult {
+            remote: name,
+            error,
+        });
+    }
+
+    Ok(results)
+}
+
+/// Returns the primary remote followed by all mirrors
+#[cfg(not(target_os = "android"))]
+pub fn git_remote_list(store: &Store) -> Result<Vec<GitRemote>, git2::Error> {
+    let repo = Repository::open(&store.repo_path)?;
+    let primary = config_get().remote;
+
+    let mut names = vec![(primary, true)];
+    names.extend(mirror_names(&repo)?.into_iter().map(|name| (name, false)));
+
+    let mut remotes = vec![];
+    for (name, is_primary) in names {
+        let remote = repo.find_remote(&name)?;
+        remotes.push(GitRemote {
+            url: remote.url().unwrap_or_default().to_string(),
+            name,
+            primary: is_primary,
+        });
+    }
+    Ok(remotes)
+}
+
+/// Add a mirror remote that `git_push()` also pushes to
+#[cfg(not(target_os = "android"))]
+pub fn git_mirror_add(
+    store: &Store,
+    name: &str,
+    url: &str,
+) -> Result<(), git2::Error> {
+    let repo = Repository::open(&store.repo_path)?;
+    repo.remote(name, url)?;
+
+    let mut cfg = git2::Config::open(&repo.path().join("config"))?;
+    cfg.set_bool(&mirror_key(name), true)?;
+    debug!("Added mirror '{}': {}", name, url);
+    Ok(())
+}
+
+/// Remove a mirror remote, the primary remote can not be removed
+#[cfg(not(target_os = "android"))]
+pub fn git_mirror_remove(store: &Store, name: &str) -> Result<(), git2::Error> {
+    let repo = Repository::open(&store.repo_path)?;
+    if !mirror_names(&repo)?.iter().any(|n| n == name) {
+        return Err(git2::Error::new(
+            git2::ErrorCode::NotFound,
+            git2::ErrorClass::Config,
+            format!("No such mirror: '{}'", name),
+        ));
+    }
+    repo.remote_delete(name)
+}
+
+/// Create a new store at the repository path of `store`. A new identity is
+/// generated and protected with `passphrase`, it is written to the identity
+/// path of the store and the public key is written to `.age-recipients`.
+/// Both files are included in the initial commit, if the store has a remote
+/// url it is added as the remote and the initial commit is pushed to it.
+/// Nothing is created if the store has no identity path. Returns the public
+/// key of the new identity.
+#[cfg(not(target_os = "android"))]
+pub fn git_init(
+    store: &Store,
+    passphrase: &str,
+    username: &str,
+) -> Result<String, git2::Error> {
+    if Repository::open(&store.repo_path).is_ok() {
+        return Err(git2::Error::new(
+            git2::ErrorCode::Exists,
+            git2::ErrorClass::Repository,
+            format!("{}: Repository already exists", store.repo_path),
+        ));
+    }
+    if store.identity_path.is_empty() {
+        return Err(git2::Error::new(
+            git2::ErrorCode::Invalid,
+            git2::ErrorClass::Invalid,
+            "No identity path for the store",
+        ));
+    }
+    let mut opts = git2::RepositoryInitOptions::new();
+    opts.initial_head(&store.branch);
+    let repo = Repository::init_opts(&store.repo_path, &opts)?;
+    git_config_set_user(store, username)?;
+
+    let (encrypted_identity, recipient) =
+        match age_generate_identity(passphrase) {
+            Ok(generated) => generated,
+            Err(err) => return Err(age_error("Key generation", err)),
+        };
+
+    let identity_path = Path::new(&store.identity_path);
+    let recipients_path = Path::new(&store.repo_path).join(".age-recipients");
+    if let Err(err) = std::fs::write(identity_path, &encrypted_identity) {
+        return Err(os_error(identity_path, err));
+    }
+    if let Err(err) =
+        std::fs::write(&recipients_path, format!("{}\n", recipient))
+    {
+        return Err(os_error(&recipients_path, err));
+    }
+
+    let mut index = repo.index()?;
+    index.add_path(Path::new(".age-recipients"))?;
+    // The identity can be stored outside of the repository
+    if let Ok(relative_path) = identity_path.strip_prefix(&store.repo_path) {
+        index.add_path(relative_path)?;
+    }
+    index.write()?;
+    git_commit(store, "Initial commit")?;
+
+    if !store.remote_url.is_empty() {
+        let remote = config_get().remote;
+        repo.remote(&remote, &store.remote_url)?;
+        let refspec = format!("refs/heads/{}", store.branch);
+        push_remote(&repo, &remote, &refspec)?;
+    }
+
+    debug!("Initialized '{}'", store.repo_path);
+    Ok(recipient)
+}
+
+pub fn git_stage(
+    store: &Store,
+    relative_path: &str,
+) -> Result<(), git2::Error> {
+    let repo = Repository::open(&store.repo_path)?;
+    let mut index = repo.index()?;
+    let excluded = sparse_excluded_paths(&index);
+
+    let cb = &mut |path: &Path, _matched_spec: &[u8]| -> i32 {
+        let Some(path_str) = path.to_str() else {
+            error!("Empty path encountered in '{}'", relative_path);
+            return 1;
+        };
+
+        // Never stage the removal of entries outside of a sparse checkout
+        if excluded.contains(path_str) {
+            return 1;
+        }
+
+        let Ok(status) = repo.status_file(path) else {
+            warn!("Unknown status: '{}'", path_str);
+            return 1;
+        };
+
+        match status {
+            git2::Status::WT_MODIFIED => {
+                debug!("Modified: '{}'", path_str);
+                0
+            }
+            git2::Status::WT_NEW => {
+                debug!("Added: '{}'", path_str);
+                0
+            }
+            git2::Status::WT_DELETED => {
+                debug!("Deleted: '{}'", path_str);
+                0
+            }
+            git2::Status::WT_RENAMED => {
+                debug!("Renamed: '{:?}'", path_str);
+                0
+            }
+            _ => 1,
+        }
+    };
+
+    // The callback is only invoked for filepaths (leafs), not directories.
+    // An empty directory will not give any errors
+    index.add_all(
+        Some(relative_path),
+        git2::IndexAddOption::DEFAULT,
+        Some(cb),
+    )?;
+
+    // Entries can not be added or removed without updating the name index
+    if Path::new(&store.repo_path).join(GIT_NAME_INDEX).exists() {
+        index.add_all(
+            Some(GIT_NAME_INDEX),
+            git2::IndexAddOption::DEFAULT,
+            Some(cb),
+        )?;
+    }
+    index.write()
+}
+
+/// Commit the staged changes, an empty `message` generates a message from the
+/// staged diff, e.g. "Add 'web/github'" or "Update 3 entries" followed by a
+/// body that lists every changed entry. With private messages enabled, see
+/// `git_config_set_private_messages()`, the message is encrypted to the
+/// recipients of the store.
+pub fn git_commit(store: &Store, message: &str) -> Result<(), git2::Error> {
+    let repo = Repository::open(&store.repo_path)?;
+    let mut index = repo.index()?;
+    let statuses = repo.statuses(None)?;
+
+    let excluded = sparse_excluded_paths(&index);
+
+    let is_clean = statuses.iter().all(|entry| {
+        let status = entry.status();
+        status.is_empty() || is_sparse_excluded(&excluded, &entry)
+    });
+
+    if is_clean {
+        error!("Refusing to create empty commit");
+        return Err(internal_error!());
+    }
+
+    let sig = repo.signature()?;
+    let tree_id = index.write_tree()?;
+    let tree = repo.find_tree(tree_id)?;
+
+    // Retrieve the commit that HEAD points to so that we can replace
+    // it with our new tree state, there is no parent for the first commit.
+    let parent_commit = match repo.head() {
+        Ok(head) => {
+            let Some(oid) = head.target() else {
+                error!("HEAD unwrap error");
+                return Err(internal_error!());
+            };
+            Some(repo.find_commit(oid)?)
+        }
+        Err(err) if err.code() == git2::ErrorCode::UnbornBranch => None,
+        Err(err) => return Err(err),
+    };
+    let parents: Vec<&git2::Commit> = parent_commit.iter().collect();
+
+    let parent_tree = match &parent_commit {
+        Some(commit) => Some(commit.tree()?),
+        None => None,
+    };
+    let message =
+        commit_message(store, &repo, parent_tree.as_ref(), &tree, message)?;
+
+    let _oid =
+        repo.commit(Some("HEAD"), &sig, &sig, &message, &tree, &parents)?;
+
+    debug!("[{} {:.7}] {}", store.branch, _oid, message);
+    Ok(())
+}
+
+/// Replace all local commits that have not been pushed to the remote with a
+/// single commit. An empty `message` combines the messages of the squashed
+/// commits, the message is generated from the changes instead if any of them
+/// is private. Private messages are respected like in `git_commit()`.
+/// Returns the number of commits that were squashed.
+pub fn git_squash(store: &Store, message: &str) -> Result<usize, git2::Error> {
+    let repo = Repository::open(&store.repo_path)?;
+    let (remote_oid, unpushed) = unpushed_commits(&repo, &store.branch)?;
+    if unpushed.is_empty() {
+        error!("No unpushed commits to squash");
+        return Err(internal_error!());
+    }
+
+    let head = repo.find_commit(unpushed[0])?;
+    let mut messages = vec![];
+    if message.is_empty() {
+        for oid in unpushed.iter().rev() {
+            let commit = repo.find_commit(*oid)?;
+            let message = commit.message().unwrap_or_default();
+            if message.contains(GIT_PRIVATE_MESSAGE_HEADER) {
+                // Encrypted bodies can not be combined
+                messages.clear();
+                break;
+            }
+            messages.push(message.trim().to_string());
+        }
+    }
+    let message = if messages.is_empty() {
+        message.to_string()
+    } else {
+        messages.join("\n\n")
+    };
+
+    let sig = repo.signature()?;
+    let parent_commit = repo.find_commit(remote_oid)?;
+    let message = commit_message(
+        store,
+        &repo,
+        Some(&parent_commit.tree()?),
+        &head.tree()?,
+        &message,
+    )?;
+    let oid = repo.commit(
+        None,
+        &sig,
+        &sig,
+        &message,
+        &head.tree()?,
+        &[&parent_commit],
+    )?;
+
+    let head_ref_name = format!("refs/heads/{}", store.branch);
+    let reflog_message = format!("Squash: {} commit(s)", unpushed.len());
+    repo.reference(&head_ref_name, oid, true, &reflog_message)?;
+
+    debug!("[{} {:.7}] {}", store.branch, oid, reflog_message);
+    Ok(unpushed.len())
+}
+
+/// Replace the latest commit with the current index, the message is kept if
+/// `message` is empty. With private messages enabled the message is
+/// encrypted like in `git_commit()`, an empty `message` then generates a new
+/// message if the latest commit is already private. Refuses to amend a
+/// commit that exists on the remote.
+pub fn git_amend(store: &Store, message: &str) -> Result<(), git2::Error> {
+    let repo = Repository::open(&store.repo_path)?;
+    let (_, unpushed) = unpushed_commits(&repo, &store.branch)?;
+    if unpushed.is_empty() {
+        error!("Refusing to amend a pushed commit");
+        return Err(internal_error!());
+    }
+
+    let head = repo.find_commit(unpushed[0])?;
+    let tree_id = repo.index()?.write_tree()?;
+    let tree = repo.find_tree(tree_id)?;
+    let message = if private_messages_enabled(&repo)? {
+        let head_message = head.message().unwrap_or_default();
+        let message = if !message.is_empty() {
+            message
+        } else if head_message.contains(GIT_PRIVATE_MESSAGE_HEADER) {
+            ""
+        } else {
+            head_message
+        };
+        let parent_tree = match head.parent(0) {
+            Ok(parent) => Some(parent.tree()?),
+            Err(_) => None,
+        };
+        Some(commit_message(
+            store,
+            &repo,
+            parent_tree.as_ref(),
+            &tree,
+            message,
+        )?)
+    } else if message.is_empty() {
+        None
+    } else {
+        Some(message.to_string())
+    };
+
+    let _oid = head.amend(
+        Some("HEAD"),
+        None,
+        None,
+        None,
+        message.as_deref(),
+        Some(&tree),
+    )?;
+
+    debug!("[{} {:.7}] Amended {:.7}", store.branch, _oid, head.id());
+    Ok(())
+}
+
+/// Hard reset to the local HEAD and remove all untracked and ignored files and
+/// directories, like `git reset --hard && git clean -fdx`. The identity and
+/// recipient files of the store are never removed. Nothing is changed when
+/// `dry_run` is set. Returns the paths that were (or would be) removed,
+/// directories end with a '/'.
+pub fn git_discard(
+    store: &Store,
+    dry_run: bool,
+) -> Result<Vec<String>, git2::Error> {
+    let repo = Repository::open(&store.repo_path)?;
+    let Some(workdir) = repo.workdir() else {
+        error!("Bare repository");
+        return Err(internal_error!());
+    };
+
+    // Everything that remains after a hard reset
+    let mut tracked = HashSet::new();
+    let tree = repo.revparse_single(&store.branch)?.peel_to_tree()?;
+    tree.walk(git2::TreeWalkMode::PreOrder, |root, entry| {
+        if let Some(name) = entry.name() {
+            tracked.insert(format!("{}{}", root, name));
+        }
+        git2::TreeWalkResult::Ok
+    })?;
+
+    let mut untracked = vec![];
+    untracked_paths(workdir, workdir, &tracked, &mut untracked);
+    untracked.sort();
+
+    if dry_run {
+        return Ok(untracked);
+    }
+
+    git_reset(store)?;
+
+    for relative_path in untracked.iter() {
+        let path = workdir.join(relative_path);
+        let result = if relative_path.ends_with('/') {
+            std::fs::remove_dir_all(&path)
+        } else {
+            std::fs::remove_file(&path)
+        };
+        match result {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                return Err(os_error(&path, err))
+            }
+            _ => {}
+        }
+        debug!("Removed '{}'", relative_path);
+    }
+    Ok(untracked)
+}
+
+/// Hard reset to the local HEAD, discarding all uncommited (and staged) local
+/// changes. Note: untracked files and directories are kept, see
+/// `git_discard()` to remove them as well.
+pub fn git_reset(store: &Store) -> Result<(), git2::Error> {
+    let repo = Repository::open(&store.repo_path)?;
+    let local_head = repo.revparse_single(&store.branch)?.id();
+
+    debug!("Resetting HEAD to {} {}", store.branch, local_head);
+
+    let obj = repo.find_object(local_head, None)?;
+    repo.reset(&obj, git2::ResetType::Hard, None)?;
+    apply_sparse_dirs(&repo)
+}
+
+/// Create a new commit on top of HEAD that undoes the changes introduced by
+/// `rev`, this can be any revision string, e.g. an oid from `git_log()`.
+/// Fails without creating a commit if the revert results in conflicts or if
+/// there are uncommitted changes to tracked files.
+#[cfg(not(target_os = "android"))]
+pub fn git_revert(store: &Store, rev: &str) -> Result<(), git2::Error> {
+    let repo = Repository::open(&store.repo_path)?;
+    let head_commit = repo.head()?.peel_to_commit()?;
+    let commit = repo.revparse_single(rev)?.peel_to_commit()?;
+
+    let mut status_opts = git2::StatusOptions::new();
+    status_opts.include_untracked(false);
+    let statuses = repo.statuses(Some(&mut status_opts))?;
+    let excluded = sparse_excluded_paths(&repo.index()?);
+    if statuses.iter().any(|e| !is_sparse_excluded(&excluded, &e)) {
+        error!("Refusing to revert with uncommitted changes");
+        return Err(internal_error!());
+    }
+
+    // Merge commits are reverted relative to their first parent
+    let mainline = if commit.parent_count() > 1 { 1 } else { 0 };
+    let mut index =
+        repo.revert_commit(&commit, &head_commit, mainline, None)?;
+
+    if index.has_conflicts() {
+        let paths: Vec<String> = index
+            .conflicts()?
+            .filter_map(|c| c.ok())
+            .filter_map(|c| c.our.or(c.their).or(c.ancestor))
+            .map(|entry| String::from_utf8_lossy(&entry.path).to_string())
+            .collect();
+        let msg = format!(
+            "Cannot revert {:.7}, conflicting paths: {}",
+            commit.id(),
+            paths.join(", ")
+        );
+        debug!("{}", msg);
+        return Err(git2::Error::new(
+            git2::ErrorCode::Conflict,
+            git2::ErrorClass::None,
+            msg,
+        ));
+    }
+
+    let tree_id = index.write_tree_to(&repo)?;
+    let tree = repo.find_tree(tree_id)?;
+    let sig = repo.signature()?;
+    let message = format!(
+        "Revert \"{}\"\n\nThis reverts commit {}.",
+        commit.summary().unwrap_or_default(),
+        commit.id()
+    );
+
+    let _oid = repo.commit(
+        Some("HEAD"),
+        &sig,
+        &sig,
+        &message,
+        &tree,
+        &[&head_commit],
+    )?;
+    debug!("[{} {:.7}] {}", store.branch, _oid, message);
+
+    repo.checkout_head(Some(CheckoutBuilder::default().force()))?;
+    apply_sparse_dirs(&repo)
+}
+
+/// Clone the remote of `store` into its `repo_path`, the history is truncated
+/// to the last `depth` commits unless `depth` is zero. If `sparse_dirs` is
+/// non-empty, only entries at the top level or inside one of the given
+/// directories are checked out, see `git_sparse_set()`.
+/// Note: libgit2 has no support for partial clones, the objects of excluded
+/// directories are still downloaded and stay in `.git`. A sparse checkout
+/// keeps entries out of the working tree, not off the device.
+pub fn git_clone(
+    store: &Store,
+    depth: i32,
+    sparse_dirs: &[&str],
+) -> Result<(), git2::Error> {
+    let mut cb = RemoteCallbacks::new();
+    cb.transfer_progress(|progress| transfer_progress(progress, "Cloning"));
+
+    let mut fopts = FetchOptions::new();
+    fopts.remote_callbacks(cb);
+    fopts.depth(depth);
+
+    let repo = RepoBuilder::new()
+        .branch(&store.branch)
+        .fetch_options(fopts)
+        .clone(&store.remote_url, Path::new(&store.repo_path))?;
+
+    if !sparse_dirs.is_empty() {
+        set_sparse_dirs(&repo, sparse_dirs)?;
+        apply_sparse_dirs(&repo)?;
+    }
+    Ok(())
+}
+
+/// A branch of the store, `local` and `remote` tell if the branch exists
+/// locally and on the remote as of the last fetch.
+#[derive(Debug, PartialEq)]
+pub struct GitBranch {
+    pub name: String,
+    pub active: bool,
+    pub local: bool,
+    pub remote: bool,
+}
+
+impl std::fmt::Display for GitBranch {
+    /// "<*| ><L|-><R|->\n<name>"
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}{}{}\n{}",
+            if self.active { '*' } else { ' ' },
+            if self.local { 'L' } else { '-' },
+            if self.remote { 'R' } else { '-' },
+            self.name
+        )
+    }
+}
+
+/// Returns the local branches and the branches of the primary remote, sorted
+/// by name. The active branch is the branch of `store`.
+pub fn git_branch_list(store: &Store) -> Result<Vec<GitBranch>, git2::Error> {
+    let repo = Repository::open(&store.repo_path)?;
+    let remote_prefix = format!("{}/", config_get().remote);
+    let mut branches: BTreeMap<String, GitBranch> = BTreeMap::new();
+
+    for branch in repo.branches(None)? {
+        let (branch, branch_type) = branch?;
+        let Some(name) = branch.name()? else {
+            continue;
+        };
+        let (name, is_local) = match branch_type {
+            git2::BranchType::Local => (name, true),
+            git2::BranchType::Remote => match name.strip_prefix(&remote_prefix)
+            {
+                Some("HEAD") | None => continue,
+                Some(name) => (name, false),
+            },
+        };
+
+        let entry = branches.entry(name.to_string()).or_insert(GitBranch {
+            name: name.to_string(),
+            active: name == store.branch,
+            local: false,
+            remote: false,
+        });
+        if is_local {
+            entry.local = true;
+        } else {
+            entry.remote = true;
+        }
+    }
+    Ok(branches.into_values().collect())
+}
+
+/// Create a local branch called `name` from the head of the active branch,
+/// the branch is pushed once it has been switched to with
+/// `git_branch_switch()`. Pulling a branch that has not been pushed yet
+/// does nothing.
+pub fn git_branch_create(store: &Store, name: &str) -> Result<(), git2::Error> {
+    let repo = Repository::open(&store.repo_path)?;
+    if !git2::Branch::name_is_valid(name)? {
+        return Err(git2::Error::new(
+            git2::ErrorCode::InvalidSpec,
+            git2::ErrorClass::Reference,
+            format!("Bad branch name: '{}'", name),
+        ));
+    }
+    let exists = repo.find_branch(name, git2::BranchType::Local).is_ok()
+        || remote_branch_oid(&repo, name).is_ok();
+    if exists {
+        return Err(git2::Error::new(
+            git2::ErrorCode::Exists,
+            git2::ErrorClass::Reference,
+            format!("Branch already exists: '{}'", name),
+        ));
+    }
+
+    let head = repo.revparse_single(&store.branch)?.peel_to_commit()?;
+    repo.branch(name, &head, false)?;
+    debug!("Created '{}' at {:.7}", name, head.id());
+    Ok(())
+}
+
+/// Check out the branch `name`, a local branch that tracks the remote branch
+/// is created if needed. Refuses to switch with uncommitted changes. The
+/// caller is responsible for updating the branch of the store before the
+/// switch and for restoring it if the switch fails, pulls and pushes always
+/// use the branch of the store.
+pub fn git_branch_switch(store: &Store, name: &str) -> Result<(), git2::Error> {
+    let repo = Repository::open(&store.repo_path)?;
+    if has_uncommitted_changes(&repo)? {
+        return Err(git2::Error::new(
+            git2::ErrorCode::Uncommitted,
+            git2::ErrorClass::Repository,
+            "Uncommitted changes in the store",
+        ));
+    }
+
+    let branch = match repo.find_branch(name, git2::BranchType::Local) {
+        Ok(branch) => branch,
+        Err(err) if err.code() == git2::ErrorCode::NotFound => {
+            // The remote branch may not have been fetched yet
+            let target = match remote_branch_oid(&repo, name) {
+                Ok(oid) => oid,
+                Err(_) => {
+                    fetch_remote(&repo, name, 0)?;
+                    remote_branch_oid(&repo, name)?
+                }
+            };
+            let commit = repo.find_commit(target)?;
+            let mut branch = repo.branch(name, &commit, false)?;
+            let upstream = format!("{}/{}", config_get().remote, name);
+            branch.set_upstream(Some(&upstream))?;
+            branch
+        }
+        Err(err) => return Err(err),
+    };
+
+    let commit = branch.get().peel_to_commit()?;
+    let mut checkout = CheckoutBuilder::new();
+    checkout.safe();
+    repo.checkout_tree(commit.as_object(), Some(&mut checkout))?;
+    repo.set_head(&format!("refs/heads/{}", name))?;
+    apply_sparse_dirs(&repo)?;
+
+    debug!("Switched to '{}' at {:.7}", name, commit.id());
+    Ok(())
+}
+
+/// State of a changed path, see `git_status()`
+#[cfg(not(target_os = "android"))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GitEntryState {
+    New,
+    Modified,
+    Deleted,
+    Renamed,
+    Untracked,
+    Conflicted,
+}
+
+#[cfg(not(target_os = "android"))]
+impl GitEntryState {
+    /// Single letter representation, same as `git status --short`
+    pub fn as_char(&self) -> char {
+        match self {
+            GitEntryState::New => 'A',
+            GitEntryState::Modified => 'M',
+            GitEntryState::Deleted => 'D',
+            GitEntryState::Renamed => 'R',
+            GitEntryState::Untracked => '?',
+            GitEntryState::Conflicted => 'U',
+        }
+    }
+}
+
+/// A path with staged and/or unstaged changes
+#[cfg(not(target_os = "android"))]
+#[derive(Debug)]
+pub struct GitStatusEntry {
+    pub path: String,
+    /// Previous path for entries that were renamed in the index
+    pub old_path: Option<String>,
+    /// Staged changes, relative to HEAD
+    pub index: Option<GitEntryState>,
+    /// Unstaged changes, relative to the index
+    pub worktree: Option<GitEntryState>,
+}
+
+#[cfg(not(target_os = "android"))]
+impl std::fmt::Display for GitStatusEntry {
+    /// "<index><worktree>\n[<old path>\n]<path>"
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let worktree = self.worktree.map_or(' ', |s| s.as_char());
+        // Untracked files are shown as '??'
+        let index = match self.worktree {
+            Some(GitEntryState::Untracked) => '?',
+            _ => self.index.map_or(' ', |s| s.as_char()),
+        };
+        match self.old_path {
+            Some(ref old_path) => {
+                write!(f, "{}{}\n{}\n{}", index, worktree, old_path, self.path)
+            }
+            None => write!(f, "{}{}\n{}", index, worktree, self.path),
+        }
+    }
+}
+
+/// Returns all paths with changes in the index or the working tree, this
+/// includes untracked files. Entries are shown with their real names in
+/// stores with encrypted filenames if the identity in `age_state` is
+/// unlocked, new entries need to be staged with `git_entry_stage()` first.
+#[cfg(not(target_os = "android"))]
+pub fn git_status(
+    store: &Store,
+    age_state: &AgeState,
+) -> Result<Vec<GitStatusEntry>, git2::Error> {
+    let repo = Repository::open(&store.repo_path)?;
+    let mut opts = git2::StatusOptions::new();
+    opts.include_untracked(true)
+        .recurse_untracked_dirs(true)
+        .renames_head_to_index(true);
+
+    let statuses = repo.statuses(Some(&mut opts))?;
+    let excluded = sparse_excluded_paths(&repo.index()?);
+    let mut arr = vec![];
+
+    for entry in statuses.iter() {
+        if is_sparse_excluded(&excluded, &entry) {
+            continue;
+        }
+        let status = entry.status();
+        let Some(path) = entry.path() else {
+            warn!("Skipping non utf-8 path");
+            continue;
+        };
+
+        if status.is_conflicted() {
+            arr.push(GitStatusEntry {
+                path: path.to_string(),
+                old_path: None,
+                index: Some(GitEntryState::Conflicted),
+                worktree: Some(GitEntryState::Conflicted),
+            });
+            continue;
+        }
+
+        let index = if status.is_index_new() {
+            Some(GitEntryState::New)
+        } else if status.is_index_modified() || status.is_index_typechange() {
+            Some(GitEntryState::Modified)
+        } else if status.is_index_deleted() {
+            Some(GitEntryState::Deleted)
+        } else if status.is_index_renamed() {
+            Some(GitEntryState::Renamed)
+        } else {
+            None
+        };
+
+        let worktree = if status.is_wt_new() {
+            Some(GitEntryState::Untracked)
+        } else if status.is_wt_modified() || status.is_wt_typechange() {
+            Some(GitEntryState::Modified)
+        } else if status.is_wt_deleted() {
+            Some(GitEntryState::Deleted)
+        } else if status.is_wt_renamed() {
+            Some(GitEntryState::Renamed)
+        } else {
+            None
+        };
+
+        if index.is_none() && worktree.is_none() {
+            continue;
+        }
+
+        // The entry path is the old path for renamed entries
+        let (path, old_path) = match entry.head_to_index() {
+            Some(delta) if index == Some(GitEntryState::Renamed) => (
+                delta_path(delta.new_file()),
+                Some(delta_path(delta.old_file())),
+            ),
+            _ => (path.to_string(), None),
+        };
+
+        arr.push(GitStatusEntry {
+            path,
+            old_path,
+            index,
+            worktree,
+        });
+    }
+
+    if let Some(names) = name_index_load(store, age_state).ok().flatten() {
+        for entry in arr.iter_mut() {
+            entry.path = names.translate_path(&entry.path);
+            entry.old_path =
+                entry.old_path.as_ref().map(|p| names.translate_path(p));
+        }
+    }
+    Ok(arr)
+}
+
+/// Only keep entries at the top level and inside `dirs` in the working tree,
+/// other entries are still part of the index and are kept intact in new
+/// commits. An empty list restores a full checkout.
+/// Note: the objects for all entries remain in the local object database.
+#[cfg(not(target_os = "android"))]
+pub fn git_sparse_set(store: &Store, dirs: &[&str]) -> Result<(), git2::Error> {
+    let repo = Repository::open(&store.repo_path)?;
+    set_sparse_dirs(&repo, dirs)?;
+    apply_sparse_dirs(&repo)
+}
+
+/// Returns the directories included in a sparse checkout, an empty list means
+/// that all entries are checked out.
+#[cfg(not(target_os = "android"))]
+pub fn git_sparse_list(store: &Store) -> Result<Vec<String>, git2::Error> {
+    let repo = Repository::open(&store.repo_path)?;
+    sparse_dirs(&repo)
+}
+
+/// Fetch more history into a shallow clone, `depth` is the number of commits
+/// to have from the tip of the remote branch, zero fetches the full history.
+pub fn git_deepen(store: &Store, depth: i32) -> Result<(), git2::Error> {
+    let repo = Repository::open(&store.repo_path)?;
+    if !repo.is_shallow() {
+        debug!("Already have the complete history");
+        return Ok(());
+    }
+
+    let depth = if depth <= 0 {
+        GIT_DEPTH_UNSHALLOW
+    } else {
+        depth
+    };
+    fetch_remote(&repo, &store.branch, depth)
+}
+
+/// Returns true if remote and local HEAD are equal
+#[cfg(not(target_os = "android"))]
+pub fn git_local_head_matches_remote(
+    store: &Store,
+) -> Result<bool, git2::Error> {
+    let repo = Repository::open(&store.repo_path)?;
+    let head = repo.head()?;
+
+    let remote_oid = remote_branch_oid(&repo, &store.branch)?;
+    let Some(local_oid) = head.target() else {
+        warn!("Could not determine local HEAD");
+        return Ok(false);
+    };
+
+    Ok(local_oid == remote_oid)
+}
+
+/// Number of commits that the local branch and the remote branch have that
+/// the other one does not have.
+#[cfg(not(target_os = "android"))]
+#[derive(Debug, PartialEq)]
+pub struct GitAheadBehind {
+    /// Local commits that have not been pushed
+    pub ahead: usize,
+    /// Remote commits that have not been pulled
+    pub behind: usize,
+}
+
+#[cfg(not(target_os = "android"))]
+impl GitAheadBehind {
+    /// Both branches have unique commits, a fast-forward is not possible
+    pub fn diverged(&self) -> bool {
+        self.ahead > 0 && self.behind > 0
+    }
+}
+
+/// Count how far the local branch is ahead of and behind the remote branch,
+/// optionally fetching the remote branch first.
+#[cfg(not(target_os = "android"))]
+pub fn git_ahead_behind(
+    store: &Store,
+    fetch: bool,
+) -> Result<GitAheadBehind, git2::Error> {
+    let repo = Repository::open(&store.repo_path)?;
+    if fetch {
+        fetch_remote(&repo, &store.branch, 0)?;
+    }
+
+    let local_oid = repo.revparse_single(&store.branch)?.id();
+    let remote_oid = remote_branch_oid(&repo, &store.branch)?;
+    let (ahead, behind) = repo.graph_ahead_behind(local_oid, remote_oid)?;
+
+    debug!("{} is ahead {}, behind {}", store.branch, ahead, behind);
+    Ok(GitAheadBehind { ahead, behind })
+}
+
+pub fn git_config_set_user(
+    store: &Store,
+    username: &str,
+) -> Result<(), git2::Error> {
+    let config_path = Path::new(&store.repo_path).join(".git").join("config");
+    let mut cfg = git2::Config::open(&config_path)?;
+
+    cfg.set_str("user.name", username)?;
+    let email = format!("{}@{}", username, config_get().email_domain);
+    cfg.set_str("user.email", &email)?;
+
+    // Echoing back the config gives errors...
+    //  https://github.com/rust-lang/git-rs/issues/474
+    Ok(())
+}
+
+/// Only describe the number of changed entries in the summary of new commits,
+/// the real message is age-encrypted to the store recipients in the body.
+pub fn git_config_set_private_messages(
+    store: &Store,
+    enabled: bool,
+) -> Result<(), git2::Error> {
+    let config_path = Path::new(&store.repo_path).join(".git").join("config");
+    let mut cfg = git2::Config::open(&config_path)?;
+    cfg.set_bool(GIT_CONFIG_PRIVATE_MESSAGES, enabled)
+}
+
+/// Size statistics for the repository of a store
+#[derive(Debug)]
+pub struct GitStats {
+    pub loose_objects: usize,
+    /// Total size of all loose objects in bytes
+    pub loose_size: u64,
+    pub packs: usize,
+    /// Number of objects in all packs, objects that exist in several packs
+    /// are counted once for each pack.
+    pub packed_objects: usize,
+    /// Total size of all packs and their indices in bytes
+    pub pack_size: u64,
+    /// Total size of all files in the working tree in bytes
+    pub worktree_size: u64,
+    /// Number of '*.age' entries on the current branch
+    pub age_entries: usize,
+}
+
+impl GitStats {
+    /// Serialize into an array of "<key>\n<value>" strings
+    pub fn to_vec(&self) -> Vec<String> {
+        vec![
+            format!("loose_objects\n{}", self.loose_objects),
+            format!("loose_size\n{}", self.loose_size),
+            format!("packs\n{}", self.packs),
+            format!("packed_objects\n{}", self.packed_objects),
+            format!("pack_size\n{}", self.pack_size),
+            format!("worktree_size\n{}", self.worktree_size),
+            format!("age_entries\n{}", self.age_entries),
+        ]
+    }
+}
+
+/// Returns size statistics for the repository of the store
+pub fn git_stats(store: &Store) -> Result<GitStats, git2::Error> {
+    let repo = Repository::open(&store.repo_path)?;
+    let objects_dir = repo.path().join("objects");
+
+    let mut stats = GitStats {
+        loose_objects: 0,
+        loose_size: 0,
+        packs: 0,
+        packed_objects: 0,
+        pack_size: 0,
+        worktree_size: 0,
+        age_entries: 0,
+    };
+
+    for (_, path) in loose_objects(&objects_dir) {
+        stats.loose_objects += 1;
+        stats.loose_size += file_size(&path);
+    }
+
+    for path in dir_files(&objects_dir.join("pack")) {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("pack") => stats.packs += 1,
+            Some("idx") => stats.packed_objects += pack_index_oids(&path).len(),
+            _ => continue,
+        }
+        stats.pack_size += file_size(&path);
+    }
+
+    if let Some(workdir) = repo.workdir() {
+        stats.worktree_size = dir_size(workdir);
+    }
+
+    let tree = repo.revparse_single(&store.branch)?.peel_to_tree()?;
+    tree.walk(git2::TreeWalkMode::PreOrder, |_, entry| {
+        let is_age = entry.name().is_some_and(|name| name.ends_with(".age"));
+        if is_age && entry.kind() == Some(git2::ObjectType::Blob) {
+            stats.age_entries += 1;
+        }
+        git2::TreeWalkResult::Ok
+    })?;
+
+    Ok(stats)
+}
+
+/// Pack all objects that are reachable from a reference, a reflog entry or
+/// the index into a single pack and remove the loose objects and packs that
+/// are no longer needed. Packs with a '.keep' file are never removed.
+/// Unreachable objects are only removed once they are older than
+/// `grace_period_s` seconds. Returns the statistics after the maintenance.
+pub fn git_maintenance(
+    store: &Store,
+    grace_period_s: i64,
+) -> Result<GitStats, git2::Error> {
+    let repo = Repository::open(&store.repo_path)?;
+    let objects_dir = repo.path().join("objects");
+    let pack_dir = objects_dir.join("pack");
+    let grace_period =
+        std::time::Duration::from_secs(grace_period_s.max(0) as u64);
+
+    // Everything that is reachable from a reference or a reflog entry
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push_glob("*")?;
+    if let Ok(head) = repo.head() {
+        if let Some(oid) = head.target() {
+            revwalk.push(oid)?;
+        }
+    }
+    let mut reachable = HashSet::new();
+    let mut ref_names = vec!["HEAD".to_string()];
+    for reference in repo.references()? {
+        let reference = reference?;
+        if let Some(name) = reference.name() {
+            ref_names.push(name.to_string());
+        }
+
+        // Annotated tags and the trees or blobs that references point to
+        // directly are not part of the revwalk
+        let Some(mut oid) = reference.target() else {
+            continue;
+        };
+        while let Ok(tag) = repo.find_tag(oid) {
+            reachable.insert(oid);
+            oid = tag.target_id();
+        }
+        match repo.find_object(oid, None).ok().and_then(|obj| obj.kind()) {
+            Some(git2::ObjectType::Tree) => {
+                tree_objects(&repo.find_tree(oid)?, &mut reachable)?
+            }
+            Some(git2::ObjectType::Blob) => {
+                reachable.insert(oid);
+            }
+            _ => (),
+        }
+    }
+    for ref_name in ref_names.iter() {
+        let Ok(reflog) = repo.reflog(ref_name) else {
+            continue;
+        };
+        for entry in reflog.iter() {
+            for oid in [entry.id_old(), entry.id_new()] {
+                if !oid.is_zero() && repo.find_commit(oid).is_ok() {
+                    revwalk.push(oid)?;
+                }
+            }
+        }
+    }
+
+    // Staged content that has not been committed yet
+    for entry in repo.index()?.iter() {
+        if entry.mode != u32::from(git2::FileMode::Commit) {
+            reachable.insert(entry.id);
+        }
+    }
+
+    for oid in revwalk {
+        let commit = repo.find_commit(oid?)?;
+        reachable.insert(commit.id());
+        tree_objects(&commit.tree()?, &mut reachable)?;
+    }
+
+    let mut packbuilder = repo.packbuilder()?;
+    for oid in reachable.iter() {
+        packbuilder.insert_object(*oid, None)?;
+    }
+    packbuilder.write(&pack_dir, 0)?;
+    let Some(name) = packbuilder.name().map(|name| format!("pack-{}", name))
+    else {
+        error!("Missing pack name");
+        return Err(internal_error!());
+    };
+    debug!("Packed {} object(s) into {}", reachable.len(), name);
+
+    // Old packs with unreachable objects are kept during the grace period
+    for idx_path in dir_files(&pack_dir) {
+        let stem = idx_path.file_stem().unwrap_or_default().to_string_lossy();
+        if stem == name
+            || idx_path.extension().is_none_or(|ext| ext != "idx")
+            || idx_path.with_extension("keep").exists()
+        {
+            continue;
+        }
+        let oids = pack_index_oids(&idx_path);
+        if oids.iter().any(|oid| !reachable.contains(oid))
+            && file_age(&idx_path) < grace_period
+        {
+            continue;
+        }
+        for extension in ["pack", "rev", "mtimes", "promisor", "idx"] {
+            remove_object_file(&idx_path.with_extension(extension))?;
+        }
+    }
+
+    // Both can refer to objects and packs that were removed, they are only
+    // used to speed up lookups and are not rewritten
+    remove_object_file(&pack_dir.join("multi-pack-index"))?;
+    remove_object_file(&objects_dir.join("info").join("commit-graph"))?;
+    let graphs_dir = objects_dir.join("info").join("commit-graphs");
+    for path in dir_files(&graphs_dir) {
+        remove_object_file(&path)?;
+    }
+
+    for (oid, path) in loose_objects(&objects_dir) {
+        if reachable.contains(&oid) || file_age(&path) >= grace_period {
+            remove_object_file(&path)?;
+        }
+    }
+
+    git_stats(store)
+}
+
+/// The last commit that modified an entry
+#[derive(Debug, Clone)]
+pub struct GitLastChange {
+    pub path: String,
+    pub commit: GitCommitInfo,
+}
+
+impl std::fmt::Display for GitLastChange {
+    /// "<path>\n<timestamp>\n<oid>\n<author>\n<summary>"
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}\n{}", self.path, self.commit)
+    }
+}
+
+/// Returns the last commit that modified each entry at or below `path`, an
+/// empty `path` returns all entries. The results for the whole tree are
+/// computed in one pass over the history and cached until HEAD changes.
+/// The cache is not locked during the computation, other stores are not
+/// blocked. In stores with encrypted filenames, `path` and the returned
+/// paths refer to the real names if the identity in `age_state` is unlocked.
+pub fn git_last_changed(
+    store: &Store,
+    age_state: &AgeState,
+    path: &str,
+) -> Result<Vec<GitLastChange>, git2::Error> {
+    let repo = Repository::open(&store.repo_path)?;
+    let head = repo.revparse_single(&store.branch)?.peel_to_commit()?;
+
+    let cached = match GIT_LAST_CHANGED.lock() {
+        Ok(cache) => cache
+            .get(&store.repo_path)
+            .filter(|(oid, _)| *oid == head.id())
+            .map(|(_, changes)| Arc::clone(changes)),
+        Err(_) => {
+            error!("Cache unavailable");
+            return Err(internal_error!());
+        }
+    };
+    let changes = match cached {
+        Some(changes) => changes,
+        None => {
+            let changes = Arc::new(last_changes(&repo, &head)?);
+            let Ok(mut cache) = GIT_LAST_CHANGED.lock() else {
+                error!("Cache unavailable");
+                return Err(internal_error!());
+            };
+            cache.insert(
+                store.repo_path.clone(),
+                (head.id(), Arc::clone(&changes)),
+            );
+            changes
+        }
+    };
+
+    let names = name_index_load(store, age_state).ok().flatten();
+    let path = path.trim_matches('/');
+    let dir_prefix = format!("{}/", path);
+    Ok(changes
+        .iter()
+        .map(|change| match &names {
+            Some(names) => GitLastChange {
+                path: names.translate_path(&change.path),
+                commit: change.commit.clone(),
+            },
+            None => change.clone(),
+        })
+        .filter(|change| {
+            path.is_empty()
+                || change.path == path
+                || change.path.starts_with(&dir_prefix)
+        })
+        .collect())
+}
+
+/// Drop the cached results of `git_last_changed()` for the store at
+/// `repo_path`
+pub fn git_last_changed_evict(repo_path: &str) {
+    let Ok(mut cache) = GIT_LAST_CHANGED.lock() else {
+        error!("Cache unavailable");
+        return;
+    };
+    cache.remove(repo_path);
+}
+
+/// Returns an array of "<timestamp>\n<oid>\n<summary>" strings for all commits.
+/// The first commit will be the last entry in the array. The summary of
+/// private commits is decrypted if the identity in `age_state` is unlocked.
+pub fn git_log(
+    store: &Store,
+    age_state: &AgeState,
+) -> Result<Vec<String>, git2::Error> {
+    let repo = Repository::open(&store.repo_path)?;
+    let mut revwalk = repo.revwalk()?;
+    let remote_oid = remote_branch_oid(&repo, &store.branch)?;
+
+    // Generated messages refer to the paths of entries, show the real names
+    // instead in stores with encrypted filenames.
+    let names = name_index_load(store, age_state).ok().flatten();
+
+    let mut arr = vec![];
+    revwalk.push_head()?;
+    for c in revwalk {
+        let Ok(oid) = c else { break };
+        let Ok(commit) = repo.find_commit(oid) else {
+            break;
+        };
+        let Some(summary) = commit.summary() else {
+            break;
+        };
+        let message = commit.message().unwrap_or_default();
+        let mut summary = match private_message_decrypt(message, age_state) {
+            Some(plaintext) => {
+                plaintext.lines().next().unwrap_or_default().to_string()
+            }
+            None => summary.to_string(),
+        };
+        if let Some(names) = &names {
+            summary = names.translate(&summary);
+        }
+
+        // Prettify the remote head
+        let revstr = if oid == remote_oid {
+            format!("{}/{}", config_get().remote, store.branch)
+        } else {
+            oid.to_string()
+        };
+
+        let commit_info =
+            format!("{}\n{}\n{}", commit.time().seconds(), revstr, summary,);
+        arr.push(commit_info)
+    }
+    Ok(arr)
+}
+
+/// An entry in the store, `path` is the location of the encrypted file relative
+/// to the repository root.
+#[derive(Debug, PartialEq)]
+pub struct GitEntry {
+    pub name: String,
+    pub path: String,
+}
+
+impl std::fmt::Display for GitEntry {
+    /// "<name>\n<path>"
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}\n{}", self.name, self.path)
+    }
+}
+
+/// Returns the entries with a name that contains `query`, ignoring case, an
+/// empty `query` returns all entries. The names are read from the name index
+/// in stores with encrypted filenames, this requires an unlocked identity.
+pub fn git_entries(
+    store: &Store,
+    age_state: &AgeState,
+    query: &str,
+) -> Result<Vec<GitEntry>, git2::Error> {
+    let entries: Vec<GitEntry> = match name_index_load(store, age_state)? {
+        Some(names) => names
+            .paths
+            .into_iter()
+            .map(|(name, path)| GitEntry { name, path })
+            .collect(),
+        None => {
+            let mut paths = vec![];
+            workdir_entry_paths(Path::new(&store.repo_path), "", &mut paths);
+            paths.sort();
+            paths
+                .into_iter()
+                .map(|path| GitEntry {
+                    name: path.trim_end_matches(".age").to_string(),
+                    path,
+                })
+                .collect()
+        }
+    };
+
+    let query = query.to_lowercase();
+    Ok(entries
+        .into_iter()
+        .filter(|entry| entry.name.to_lowercase().contains(&query))
+        .collect())
+}
+
+/// Returns the path of the encrypted file for the entry `name`. New names are
+/// only added to the name index once the entry is staged with
+/// `git_entry_stage()`, an entry that is never written is not kept.
+pub fn git_entry_path(
+    store: &Store,
+    age_state: &AgeState,
+    name: &str,
+) -> Result<String, git2::Error> {
+    let name = entry_name(name)?;
+    let Some(mut names) = name_index_load(store, age_state)? else {
+        return Ok(format!("{}.age", name));
+    };
+    match names.paths.get(name) {
+        Some(path) => Ok(path.clone()),
+        None => names.insert(name),
+    }
+}
+
+/// Stage the file written to `git_entry_path()` for the entry `name`, new
+/// names are added to the name index which is staged together with the
+/// entry.
+pub fn git_entry_stage(
+    store: &Store,
+    age_state: &AgeState,
+    name: &str,
+) -> Result<(), git2::Error> {
+    let name = entry_name(name)?;
+    let path = match name_index_load(store, age_state)? {
+        Some(mut names) => match names.paths.get(name) {
+            Some(path) => path.clone(),
+            None => {
+                let path = names.insert(name)?;
+                let workdir = Path::new(&store.repo_path);
+                if !workdir.join(&path).is_file() {
+                    return Err(entry_not_found(name));
+                }
+                name_index_save(store, &names)?;
+                path
+            }
+        },
+        None => format!("{}.age", name),
+    };
+    git_stage(store, &path)
+}
+
+/// Move the entry `old_name` to `new_name` in the working tree, returns the
+/// old and the new path, both need to be staged.
+pub fn git_entry_rename(
+    store: &Store,
+    age_state: &AgeState,
+    old_name: &str,
+    new_name: &str,
+) -> Result<(String, String), git2::Error> {
+    let workdir = Path::new(&store.repo_path);
+    let old_name = entry_name(old_name)?;
+    let new_name = entry_name(new_name)?;
+    let mut names = name_index_load(store, age_state)?;
+
+    let (old_path, new_path) = match &mut names {
+        Some(names) => {
+            let Some(old_path) = names.remove(old_name) else {
+                return Err(entry_not_found(old_name));
+            };
+            if names.paths.contains_key(new_name) {
+                return Err(entry_exists(new_name));
+            }
+            (old_path, names.insert(new_name)?)
+        }
+        None => (format!("{}.age", old_name), format!("{}.age", new_name)),
+    };
+
+    if !workdir.join(&old_path).is_file() {
+        return Err(entry_not_found(old_name));
+    }
+    if workdir.join(&new_path).exists() {
+        return Err(entry_exists(new_name));
+    }
+    move_in_workdir(workdir, &old_path, &new_path)?;
+
+    if let Some(names) = &names {
+        name_index_save(store, names)?;
+    }
+    Ok((old_path, new_path))
+}
+
+/// Remove the entry `name` from the working tree, returns the path that needs
+/// to be staged.
+pub fn git_entry_remove(
+    store: &Store,
+    age_state: &AgeState,
+    name: &str,
+) -> Result<String, git2::Error> {
+    let workdir = Path::new(&store.repo_path);
+    let name = entry_name(name)?;
+    let mut names = name_index_load(store, age_state)?;
+
+    let path = match &mut names {
+        Some(names) => match names.remove(name) {
+            Some(path) => path,
+            None => return Err(entry_not_found(name)),
+        },
+        None => format!("{}.age", name),
+    };
+    if !workdir.join(&path).is_file() {
+        return Err(entry_not_found(name));
+    }
+    remove_from_workdir(workdir, &path);
+
+    if let Some(names) = &names {
+        name_index_save(store, names)?;
+    }
+    Ok(path)
+}
+
+/// Convert the store to encrypted filenames in one commit. Every entry is
+/// moved to a path derived from a keyed hash of its name and the real names
+/// are kept in an index that is encrypted to the store recipients. Entries
+/// named 'otp' keep the suffix of their filename so that they are still
+/// resolved into TOTP codes. Returns the number of entries that were moved.
+#[cfg(not(target_os = "android"))]
+pub fn git_entries_encrypt(store: &Store) -> Result<usize, git2::Error> {
+    let repo = Repository::open(&store.repo_path)?;
+    let workdir = Path::new(&store.repo_path);
+    if workdir.join(GIT_NAME_INDEX).exists() {
+        return Err(git2::Error::new(
+            git2::ErrorCode::Exists,
+            git2::ErrorClass::Repository,
+            "Entry names are already encrypted",
+        ));
+    }
+    if has_uncommitted_changes(&repo)? {
+        return Err(git2::Error::new(
+            git2::ErrorCode::Uncommitted,
+            git2::ErrorClass::Repository,
+            "Uncommitted changes in the store",
+        ));
+    }
+
+    let mut names = NameIndex::new();
+    let mut index = repo.index()?;
+    let entries: Vec<git2::IndexEntry> = index.iter().collect();
+    for entry in entries {
+        let path = String::from_utf8_lossy(&entry.path).to_string();
+        if !is_entry_path(&path) {
+            continue;
+        }
+        let name = path.trim_end_matches(".age");
+        if names.paths.contains_key(name) {
+            continue;
+        }
+        let new_path = names.insert(name)?;
+
+        // Entries outside of a sparse checkout only exist in the index
+        if workdir.join(&path).is_file() {
+            move_in_workdir(workdir, &path, &new_path)?;
+        }
+        index.remove_path(Path::new(&path))?;
+        let moved = git2::IndexEntry {
+            path: new_path.into_bytes(),
+            ..entry
+        };
+        index.add(&moved)?;
+    }
+
+    if names.paths.is_empty() {
+        error!("No entries to encrypt");
+        return Err(internal_error!());
+    }
+
+    name_index_save(store, &names)?;
+    index.add_path(Path::new(GIT_NAME_INDEX))?;
+    index.write()?;
+
+    git_commit(store, "Encrypt entry names")?;
+    debug!("Moved {} entries", names.paths.len());
+    Ok(names.paths.len())
+}
+
+/// Acquire the last error of the store identified by `handle`, should be
+/// called before each method call in a multithreaded environment. Calls for
+/// other stores are not blocked. The lock is released once the returned
+/// `StoreLock` is dropped, i.e. goes out of scope.
+pub fn git_try_lock(handle: StoreHandle) -> Option<StoreLock<git2::Error>> {
+    GIT_LAST_ERROR.try_lock(handle)
+}
+
+/// Initialize global options in the underlying library
+fn git_init_opts(config: &Config) -> Result<(), git2::Error> {
+    unsafe {
+        set_server_timeout_in_milliseconds(config.timeout_ms)?;
+        set_server_connect_timeout_in_milliseconds(config.connect_timeout_ms)?;
+
+        let timeout = get_server_timeout_in_milliseconds()?;
+        let connect_timeout = get_server_connect_timeout_in_milliseconds()?;
+
+        debug_safe!("Configured read/write timeout: {} ms", timeout);
+        debug_safe!("Configured connect timeout: {} ms", connect_timeout);
+    };
+    Ok(())
+}
+
+/// Update the remote tracking branch, the local branch is not modified.
+/// A shallow clone stays shallow unless a `depth` is given.
+fn fetch_remote(
+    repo: &git2::Repository,
+    branch: &str,
+    depth: i32,
+) -> Result<(), git2::Error> {
+    let mut remote = repo.find_remote(&config_get().remote)?;
+
+    let mut cb = git2::RemoteCallbacks::new();
+    let mut fopts = git2::FetchOptions::new();
+    cb.transfer_progress(|progress| transfer_progress(progress, "Fetching"));
+    fopts.remote_callbacks(cb);
+    fopts.depth(depth);
+
+    remote.fetch(&[branch], Some(&mut fopts), None)
+}
+
+/// Push `refspec` to the remote called `name`
+#[cfg(not(target_os = "android"))]
+fn push_remote(
+    repo: &git2::Repository,
+    name: &str,
+    refspec: &str,
+) -> Result<(), git2::Error> {
+    let mut remote = repo.find_remote(name)?;
+
+    let mut push_options = git2::PushOptions::new();
+    let mut remote_callbacks = git2::RemoteCallbacks::new();
+
+    remote_callbacks.push_transfer_progress(|current, total, _| {
+        if current == total {
+            debug!("Pushing: [{:4} / {:4}] Done", current, total);
+            return;
+        }
+
+        if total <= TRANSFER_STAGES {
+            return;
+        }
+
+        let increments = total / TRANSFER_STAGES;
+        if current % increments == 0 {
+            debug!("Pushing: [{:4} / {:4}]", current, total);
+        }
+    });
+    push_options.remote_callbacks(remote_callbacks);
+
+    remote.push(&[refspec], Some(&mut push_options))
+}
+
+/// Config key that marks the remote called `name` as a mirror
+#[cfg(not(target_os = "android"))]
+fn mirror_key(name: &str) -> String {
+    format!("remote.{}.{}", name, GIT_CONFIG_MIRROR)
+}
+
+/// Names of all remotes that are marked as mirrors
+#[cfg(not(target_os = "android"))]
+fn mirror_names(repo: &git2::Repository) -> Result<Vec<String>, git2::Error> {
+    let cfg = git2::Config::open(&repo.path().join("config"))?;
+    let mut names = vec![];
+    for name in repo.remotes()?.iter().flatten() {
+        if cfg.get_bool(&mirror_key(name)).unwrap_or(false) {
+            names.push(name.to_string());
+        }
+    }
+    Ok(names)
+}
+
+fn sparse_dirs(repo: &git2::Repository) -> Result<Vec<String>, git2::Error> {
+    let cfg = git2::Config::open(&repo.path().join("config"))?;
+    let mut dirs = vec![];
+    cfg.multivar(GIT_CONFIG_SPARSE_DIR, None)?
+        .for_each(|entry| {
+            if let Some(dir) = entry.value() {
+                dirs.push(dir.to_string());
+            }
+        })?;
+    Ok(dirs)
+}
+
+fn set_sparse_dirs(
+    repo: &git2::Repository,
+    dirs: &[&str],
+) -> Result<(), git2::Error> {
+    let mut cfg = git2::Config::open(&repo.path().join("config"))?;
+
+    match cfg.remove_multivar(GIT_CONFIG_SPARSE_DIR, ".*") {
+        Err(err) if err.code() != git2::ErrorCode::NotFound => return Err(err),
+        _ => {}
+    }
+
+    for dir in dirs {
+        let dir = dir.trim_matches('/');
+        if dir.is_empty() {
+            continue;
+        }
+        // A regexp that never matches, always append a new value
+        cfg.set_multivar(GIT_CONFIG_SPARSE_DIR, "^$", dir)?;
+    }
+    Ok(())
+}
+
+/// Update the working tree to match the configured sparse directories. Entries
+/// outside of the sparse directories are flagged with 'skip-worktree' in the
+/// index and removed from the working tree, libgit2 will then treat them as
+/// unmodified during `git_stage()` and `git_commit()`.
+fn apply_sparse_dirs(repo: &git2::Repository) -> Result<(), git2::Error> {
+    let dirs = sparse_dirs(repo)?;
+    let Some(workdir) = repo.workdir() else {
+        return Err(internal_error!());
+    };
+    let mut index = repo.index()?;
+    let skip_worktree = git2::IndexEntryExtendedFlag::SKIP_WORKTREE.bits();
+    let entries: Vec<git2::IndexEntry> = index.iter().collect();
+    let mut restored = vec![];
+
+    for mut entry in entries {
+        let path = String::from_utf8_lossy(&entry.path).to_string();
+        let included = dirs.is_empty()
+            || !path.contains('/')
+            || dirs
+                .iter()
+                .any(|dir| path.starts_with(&format!("{}/", dir)));
+        let skipped = entry.flags_extended & skip_worktree != 0;
+
+        if included && skipped {
+            entry.flags_extended &= !skip_worktree;
+            index.add(&entry)?;
+            restored.push(path);
+        } else if !included {
+            if !skipped {
+                entry.flags_extended |= skip_worktree;
+                index.add(&entry)?;
+            }
+            remove_from_workdir(workdir, &path);
+        }
+    }
+    index.write()?;
+
+    if !restored.is_empty() {
+        debug!("Restoring {} entries", restored.len());
+        let mut cb = CheckoutBuilder::default();
+        cb.force();
+        for path in restored {
+            cb.path(path);
+        }
+        repo.checkout_index(Some(&mut index), Some(&mut cb))?;
+    }
+    Ok(())
+}
+
+/// Paths that are excluded from a sparse checkout
+fn sparse_excluded_paths(index: &git2::Index) -> HashSet<String> {
+    let skip_worktree = git2::IndexEntryExtendedFlag::SKIP_WORKTREE.bits();
+    index
+        .iter()
+        .filter(|entry| entry.flags_extended & skip_worktree != 0)
+        .map(|entry| String::from_utf8_lossy(&entry.path).to_string())
+        .collect()
+}
+
+/// libgit2 only respects the 'skip-worktree' flag for files that exist in the
+/// working tree, entries outside of a sparse checkout are therefore reported
+/// as deleted and need to be filtered out.
+fn is_sparse_excluded(
+    excluded: &HashSet<String>,
+    entry: &git2::StatusEntry,
+) -> bool {
+    entry.status() == git2::Status::WT_DELETED
+        && entry.path().is_some_and(|path| excluded.contains(path))
+}
+
+/// Collect the paths under `dir` that are not in `tracked`, directories
+/// without any tracked content are collected as a whole.
+fn untracked_paths(
+    workdir: &Path,
+    dir: &Path,
+    tracked: &HashSet<String>,
+    untracked: &mut Vec<String>,
+) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => {
+            warn!("{}: {}", dir.display(), err);
+            return;
+        }
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().to_string();
+        let Ok(relative_path) = path.strip_prefix(workdir) else {
+            continue;
+        };
+        let relative_path = relative_path.to_string_lossy().to_string();
+
+        if name == ".git" || GIT_PROTECTED_FILES.contains(&name.as_str()) {
+            continue;
+        }
+        if tracked.contains(&relative_path) {
+            if path.is_dir() && !path.is_symlink() {
+                untracked_paths(workdir, &path, tracked, untracked);
+            }
+            continue;
+        }
+
+        if path.is_dir() && !path.is_symlink() {
+            // Keep untracked directories that contain protected files
+            let mut nested = vec![];
+            untracked_paths(workdir, &path, tracked, &mut nested);
+            if has_protected_file(&path) {
+                untracked.extend(nested);
+            } else {
+                untracked.push(format!("{}/", relative_path));
+            }
+        } else {
+            untracked.push(relative_path);
+        }
+    }
+}
+
+fn has_protected_file(dir: &Path) -> bool {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return false;
+    };
+    entries.filter_map(|entry| entry.ok()).any(|entry| {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let path = entry.path();
+        GIT_PROTECTED_FILES.contains(&name.as_str())
+            || (path.is_dir()
+                && !path.is_symlink()
+                && has_protected_file(&path))
+    })
+}
+
+/// Remove a file and any parent directories that become empty
+fn remove_from_workdir(workdir: &Path, relative_path: &str) {
+    let path = workdir.join(relative_path);
+    if let Err(err) = std::fs::remove_file(&path) {
+        if err.kind() != std::io::ErrorKind::NotFound {
+            warn!("{}: {}", relative_path, err);
+        }
+        return;
+    }
+    remove_empty_parents(workdir, &path);
+}
+
+fn remove_empty_parents(workdir: &Path, path: &Path) {
+    let mut parent = path.parent();
+    while let Some(dir) = parent {
+        if dir == workdir || std::fs::remove_dir(dir).is_err() {
+            break;
+        }
+        parent = dir.parent();
+    }
+}
+
+/// Update the local checkout to `target` if it is a fast-forward of the local
+/// branch, returns a summary of the entries that changed.
+#[cfg(not(target_os = "android"))]
+fn fast_forward(
+    repo: &git2::Repository,
+    branch: &str,
+    target: git2::Oid,
+) -> Result<GitDiffSummary, git2::Error> {
+    // Update the local checkout to use the target (fast-forward)
+    let old_head = repo.head()?.peel_to_commit()?.id();
+    let target = repo.find_annotated_commit(target)?;
+
+    let analysis = repo.merge_analysis(&[&target])?;
+
+    if analysis.0.is_up_to_date() {
+        debug!("Already up to date.");
+        return diff_summary(repo, old_head, old_head);
+    } else if analysis.0.is_fast_forward() {
+        let head_ref_name = format!("refs/heads/{}", branch);
+        let mut head_reference = repo.find_reference(&head_ref_name)?;
+
+        let reflog_message =
+            format!("Fast-Forward: {} -> {}", head_ref_name, target.id());
+        debug!("{}", reflog_message);
+        head_reference.set_target(target.id(), &reflog_message)?;
+        repo.set_head(&head_ref_name)?;
+        repo.checkout_head(Some(CheckoutBuilder::default().force()))?;
+        apply_sparse_dirs(repo)?;
+    } else {
+        debug!("Cannot fast-forward");
+        return Err(git2::Error::new(
+            git2::ErrorCode::NotFastForward,
+            git2::ErrorClass::None,
+            "Cannot fast-forward",
+        ));
+    }
+
+    diff_summary(repo, old_head, target.id())
+}
+
+/// Create the content of a bundle with the commits on `branch` that are not
+/// reachable from `basis`, returns the data and the number of commits.
+#[cfg(not(target_os = "android"))]
+fn bundle_data(
+    repo: &git2::Repository,
+    branch: &str,
+    basis: Option<git2::Oid>,
+) -> Result<(Vec<u8>, usize), git2::Error> {
+    let head = repo.revparse_single(branch)?.peel_to_commit()?.id();
+
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(head)?;
+    if let Some(basis) = basis {
+        revwalk.hide(basis)?;
+    }
+
+    // Parents outside of the bundle must already exist for the receiver
+    let mut included = HashSet::new();
+    let mut prerequisites = vec![];
+    for oid in revwalk {
+        included.insert(oid?);
+    }
+    for oid in included.iter() {
+        for parent in repo.find_commit(*oid)?.parents() {
+            if !included.contains(&parent.id())
+                && !prerequisites.contains(&parent.id())
+            {
+                prerequisites.push(parent.id());
+            }
+        }
+    }
+
+    if included.is_empty() {
+        return Err(git2::Error::new(
+            git2::ErrorCode::GenericError,
+            git2::ErrorClass::Invalid,
+            "Refusing to create an empty bundle",
+        ));
+    }
+
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(head)?;
+    for oid in prerequisites.iter() {
+        revwalk.hide(*oid)?;
+    }
+    let mut packbuilder = repo.packbuilder()?;
+    packbuilder.insert_walk(&mut revwalk)?;
+    let mut pack = git2::Buf::new();
+    packbuilder.write_buf(&mut pack)?;
+
+    let mut data = format!("{}\n", GIT_BUNDLE_SIGNATURE).into_bytes();
+    for oid in prerequisites.iter() {
+        data.extend(format!("-{}\n", oid).into_bytes());
+    }
+    data.extend(format!("{} refs/heads/{}\n\n", head, branch).into_bytes());
+    data.extend_from_slice(&pack);
+
+    Ok((data, included.len()))
+}
+
+/// Validate the bundle in `data` and write its packfile into the object
+/// database, returns the commit that `branch` points to in the bundle.
+#[cfg(not(target_os = "android"))]
+fn bundle_apply(
+    repo: &git2::Repository,
+    branch: &str,
+    data: &[u8],
+    label: &str,
+) -> Result<git2::Oid, git2::Error> {
+    let bad_bundle = |message: &str| {
+        git2::Error::new(
+            git2::ErrorCode::GenericError,
+            git2::ErrorClass::Invalid,
+            format!("{}: {}", label, message),
+        )
+    };
+
+    // The header is terminated by an empty line, the packfile follows
+    let Some(header_len) = data.windows(2).position(|w| w == b"\n\n") else {
+        return Err(bad_bundle("Missing bundle header"));
+    };
+    let Ok(header) = std::str::from_utf8(&data[..header_len]) else {
+        return Err(bad_bundle("Invalid bundle header"));
+    };
+    let pack = &data[header_len + 2..];
+
+    let mut lines = header.lines();
+    if lines.next() != Some(GIT_BUNDLE_SIGNATURE) {
+        return Err(bad_bundle("Unsupported bundle version"));
+    }
+
+    let ref_name = format!("refs/heads/{}", branch);
+    let mut target = None;
+    for line in lines {
+        if let Some(prerequisite) = line.strip_prefix('-') {
+            let oid = prerequisite.split(' ').next().unwrap_or_default();
+            let Ok(oid) = git2::Oid::from_str(oid) else {
+                return Err(bad_bundle("Invalid prerequisite"));
+            };
+            if repo.find_commit(oid).is_err() {
+                return Err(bad_bundle(&format!(
+                    "Missing prerequisite commit {}",
+                    oid
+                )));
+            }
+        } else if let Some((oid, name)) = line.split_once(' ') {
+            if name == ref_name {
+                let Ok(oid) = git2::Oid::from_str(oid) else {
+                    return Err(bad_bundle("Invalid reference"));
+                };
+                target = Some(oid);
+            }
+        }
+    }
+    let Some(target) = target else {
+        return Err(bad_bundle(&format!("No reference for {}", ref_name)));
+    };
+
+    let odb = repo.odb()?;
+    let mut packwriter = odb.packwriter()?;
+    if packwriter.write_all(pack).is_err() {
+        return Err(bad_bundle("Failed to write packfile"));
+    }
+    packwriter.commit()?;
+
+    Ok(target)
+}
+
+/// The last commit that was exchanged through a sync directory
+#[cfg(not(target_os = "android"))]
+fn sync_basis(
+    repo: &git2::Repository,
+) -> Result<Option<git2::Oid>, git2::Error> {
+    match repo.find_reference(GIT_SYNC_REF) {
+        Ok(reference) => Ok(Some(reference.peel_to_commit()?.id())),
+        Err(err) if err.code() == git2::ErrorCode::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(not(target_os = "android"))]
+fn sync_applied(
+    repo: &git2::Repository,
+) -> Result<HashSet<String>, git2::Error> {
+    let cfg = git2::Config::open(&repo.path().join("config"))?;
+    let mut applied = HashSet::new();
+    cfg.multivar(GIT_CONFIG_SYNC_APPLIED, None)?
+        .for_each(|entry| {
+            if let Some(name) = entry.value() {
+                applied.insert(name.to_string());
+            }
+        })?;
+    Ok(applied)
+}
+
+#[cfg(not(target_os = "android"))]
+fn set_sync_applied(
+    repo: &git2::Repository,
+    name: &str,
+) -> Result<(), git2::Error> {
+    let mut cfg = git2::Config::open(&repo.path().join("config"))?;
+    // A regexp that never matches, always append a new value
+    cfg.set_multivar(GIT_CONFIG_SYNC_APPLIED, "^$", name)
+}
+
+/// Forget applied bundles that are no longer in the sync directory, `names`
+/// are the bundles that currently exist.
+#[cfg(not(target_os = "android"))]
+fn sync_applied_prune(
+    repo: &git2::Repository,
+    applied: &HashSet<String>,
+    names: &[String],
+) -> Result<(), git2::Error> {
+    let keep: Vec<&String> = names
+        .iter()
+        .filter(|name| applied.contains(*name))
+        .collect();
+    if keep.len() == applied.len() {
+        return Ok(());
+    }
+    debug!("Pruning {} applied bundle(s)", applied.len() - keep.len());
+
+    let mut cfg = git2::Config::open(&repo.path().join("config"))?;
+    cfg.remove_multivar(GIT_CONFIG_SYNC_APPLIED, ".*")?;
+    for name in keep {
+        cfg.set_multivar(GIT_CONFIG_SYNC_APPLIED, "^$", name)?;
+    }
+    Ok(())
+}
+
+fn os_error(path: &Path, err: std::io::Error) -> git2::Error {
+    git2::Error::new(
+        git2::ErrorCode::GenericError,
+        git2::ErrorClass::Os,
+        format!("{}: {}", path.display(), err),
+    )
+}
+
+#[cfg(not(target_os = "android"))]
+fn current_time_ms() -> u128 {
+    match std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH) {
+        Ok(duration) => duration.as_millis(),
+        Err(_) => 0,
+    }
+}
+
+/// Returns the remote branch and the local commits on top of it, newest
+/// first. Fails if the local branch does not contain the remote branch.
+fn unpushed_commits(
+    repo: &git2::Repository,
+    branch: &str,
+) -> Result<(git2::Oid, Vec<git2::Oid>), git2::Error> {
+    let remote_oid = remote_branch_oid(repo, branch)?;
+    let head = repo.revparse_single(branch)?.peel_to_commit()?.id();
+    if head != remote_oid && !repo.graph_descendant_of(head, remote_oid)? {
+        return Err(git2::Error::new(
+            git2::ErrorCode::NotFastForward,
+            git2::ErrorClass::Reference,
+            "Local branch has diverged from the remote",
+        ));
+    }
+
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(head)?;
+    revwalk.hide(remote_oid)?;
+    let unpushed = revwalk.collect::<Result<Vec<_>, _>>()?;
+    Ok((remote_oid, unpushed))
+}
+
+/// Returns the commit of the remote tracking branch that the local branch is
+/// based on if it is no longer part of the remote history, i.e. if the remote
+/// history was rewritten. Every commit that the tracking branch has pointed
+/// to is considered, so later fetches on top of the rewritten history do not
+/// hide the rewrite.
+#[cfg(not(target_os = "android"))]
+fn remote_rewritten(
+    repo: &git2::Repository,
+    branch: &str,
+) -> Result<Option<git2::Oid>, git2::Error> {
+    let ref_name = format!("refs/remotes/{}/{}", config_get().remote, branch);
+    let remote_oid = repo.refname_to_id(&ref_name)?;
+    let head = repo.revparse_single(branch)?.peel_to_commit()?.id();
+    let reflog = repo.reflog(&ref_name)?;
+
+    // Newest first, stop at the latest remote commit that we are based on
+    for entry in reflog.iter() {
+        for oid in [entry.id_new(), entry.id_old()] {
+            if oid.is_zero() || repo.find_commit(oid).is_err() {
+                continue;
+            }
+            if oid != head && !repo.graph_descendant_of(head, oid)? {
+                continue;
+            }
+            if oid == remote_oid || repo.graph_descendant_of(remote_oid, oid)? {
+                return Ok(None);
+            }
+            return Ok(Some(oid));
+        }
+    }
+    Ok(None)
+}
+
+/// Finish a swap from `git_repair()` that was interrupted after the broken
+/// repository was moved away, clones from repairs that never got that far
+/// are removed.
+#[cfg(not(target_os = "android"))]
+fn repair_resume(store: &Store) -> Result<(), git2::Error> {
+    let repo_path = Path::new(&store.repo_path);
+    let (Some(parent), Some(name)) =
+        (repo_path.parent(), repo_path.file_name())
+    else {
+        return Ok(());
+    };
+    let Ok(entries) = std::fs::read_dir(parent) else {
+        return Ok(());
+    };
+    let name = name.to_string_lossy();
+    let repair_prefix = format!("{}.repair-", name);
+    let mut stamps: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            file_name
+                .strip_prefix(&repair_prefix)
+                .map(|s| s.to_string())
+        })
+        .collect();
+    stamps.sort();
+
+    // The clone is complete once the broken repository has been moved away
+    for stamp in stamps.iter().rev() {
+        let tmp_path = parent.join(format!("{}{}", repair_prefix, stamp));
+        let backup_path = parent.join(format!("{}.broken-{}", name, stamp));
+        if !repo_path.exists() && backup_path.exists() {
+            warn!("Finishing interrupted repair: '{}'", tmp_path.display());
+            if let Err(err) = std::fs::rename(&tmp_path, repo_path) {
+                return Err(os_error(repo_path, err));
+            }
+            continue;
+        }
+        debug!("Removing incomplete clone: '{}'", tmp_path.display());
+        if let Err(err) = std::fs::remove_dir_all(&tmp_path) {
+            return Err(os_error(&tmp_path, err));
+        }
+    }
+    Ok(())
+}
+
+/// Check that the repository can be opened, that HEAD and the index are
+/// readable and that all objects reachable from the branch can be read.
+/// Returns a description of each problem.
+#[cfg(not(target_os = "android"))]
+fn repo_problems(repo_path: &str, branch: &str) -> Vec<String> {
+    let repo = match Repository::open(repo_path) {
+        Ok(repo) => repo,
+        Err(err) => return vec![format!("Repository: {}", err.message())],
+    };
+    let mut problems = vec![];
+
+    if let Err(err) = repo.head().and_then(|head| head.peel_to_commit()) {
+        problems.push(format!("HEAD: {}", err.message()));
+    }
+    if let Err(err) = repo.index() {
+        problems.push(format!("Index: {}", err.message()));
+    }
+
+    let odb = match repo.odb() {
+        Ok(odb) => odb,
+        Err(err) => {
+            problems.push(format!("Objects: {}", err.message()));
+            return problems;
+        }
+    };
+    let mut revwalk = match repo.revwalk() {
+        Ok(revwalk) => revwalk,
+        Err(err) => {
+            problems.push(format!("Objects: {}", err.message()));
+            return problems;
+        }
+    };
+    let branch_ref = format!("refs/heads/{}", branch);
+    if let Err(err) = revwalk.push_ref(&branch_ref) {
+        problems.push(format!("{}: {}", branch_ref, err.message()));
+        return problems;
+    }
+
+    // Trees are shared between commits, only check each object once
+    let mut checked = HashSet::new();
+    for oid in revwalk {
+        let tree = match oid.and_then(|oid| repo.find_commit(oid)?.tree()) {
+            Ok(tree) => tree,
+            Err(err) => {
+                problems.push(format!("Commit: {}", err.message()));
+                continue;
+            }
+        };
+        if !checked.insert(tree.id()) {
+            continue;
+        }
+        let result = tree.walk(git2::TreeWalkMode::PreOrder, |_, entry| {
+            if !checked.insert(entry.id()) {
+                return git2::TreeWalkResult::Skip;
+            }
+            if let Err(err) = odb.read(entry.id()) {
+                problems.push(format!(
+                    "Object {}: {}",
+                    entry.id(),
+                    err.message()
+                ));
+            }
+            git2::TreeWalkResult::Ok
+        });
+        if let Err(err) = result {
+            problems.push(format!("Tree {}: {}", tree.id(), err.message()));
+        }
+    }
+
+    problems
+}
+
+/// Add `tree` and everything below it to `objects`, subtrees that are
+/// already in `objects` are skipped
+#[cfg(not(target_os = "android"))]
+fn tree_objects(
+    tree: &git2::Tree,
+    objects: &mut HashSet<git2::Oid>,
+) -> Result<(), git2::Error> {
+    if !objects.insert(tree.id()) {
+        return Ok(());
+    }
+    tree.walk(git2::TreeWalkMode::PreOrder, |_, entry| {
+        if objects.insert(entry.id()) {
+            git2::TreeWalkResult::Ok
+        } else {
+            git2::TreeWalkResult::Skip
+        }
+    })
+}
+
+/// Returns all loose objects in the object directory `objects_dir`
+fn loose_objects(objects_dir: &Path) -> Vec<(git2::Oid, std::path::PathBuf)> {
+    let mut objects = vec![];
+    for dir in std::fs::read_dir(objects_dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+    {
+        let prefix = dir.file_name().to_string_lossy().to_string();
+        if prefix.len() != 2 {
+            continue;
+        }
+        for path in dir_files(&dir.path()) {
+            let suffix = path.file_name().unwrap_or_default().to_string_lossy();
+            if let Ok(oid) =
+                git2::Oid::from_str(&format!("{}{}", prefix, suffix))
+            {
+                objects.push((oid, path));
+            }
+        }
+    }
+    objects
+}
+
+/// Returns the objects in a version 2 pack index, the number of objects is
+/// given by the last entry of the fanout table after the 8 byte header and
+/// is followed by a sorted table with the id of each object.
+fn pack_index_oids(path: &Path) -> Vec<git2::Oid> {
+    let Ok(data) = std::fs::read(path) else {
+        return vec![];
+    };
+    let offset = 8 + 255 * 4;
+    if data.len() < offset + 4 || &data[..4] != b"\xfftOc" {
+        warn!("Unsupported pack index: {}", path.display());
+        return vec![];
+    }
+    let mut count = [0u8; 4];
+    count.copy_from_slice(&data[offset..offset + 4]);
+    let count = u32::from_be_bytes(count) as usize;
+
+    let ids = &data[offset + 4..];
+    if ids.len() < count * 20 {
+        warn!("Truncated pack index: {}", path.display());
+        return vec![];
+    }
+    ids.chunks(20)
+        .take(count)
+        .filter_map(|id| git2::Oid::from_bytes(id).ok())
+        .collect()
+}
+
+fn remove_object_file(path: &Path) -> Result<(), git2::Error> {
+    match std::fs::remove_file(path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+            Err(os_error(path, err))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn dir_files(dir: &Path) -> Vec<std::path::PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return vec![];
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .collect()
+}
+
+/// Total size of all files under `dir`, the '.git' directory is excluded
+fn dir_size(dir: &Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return 0;
+    };
+    let mut size = 0;
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let Ok(metadata) = entry.path().symlink_metadata() else {
+            continue;
+        };
+        if metadata.is_dir() {
+            if entry.file_name() != ".git" {
+                size += dir_size(&entry.path());
+            }
+        } else {
+            size += metadata.len();
+        }
+    }
+    size
+}
+
+fn file_size(path: &Path) -> u64 {
+    std::fs::metadata(path).map(|m| m.len()).unwrap_or_default()
+}
+
+fn file_age(path: &Path) -> std::time::Duration {
+    std::fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .unwrap_or_default()
+}
+
+/// Returns the id of `tree` without the entries that should be purged, see
+/// `git_purge()`. Results are cached in `trees` by path and tree id.
+#[cfg(not(target_os = "android"))]
+fn purge_tree(
+    repo: &git2::Repository,
+    tree: &git2::Tree,
+    prefix: &str,
+    paths: &[&str],
+    blobs: &HashSet<git2::Oid>,
+    trees: &mut HashMap<(String, git2::Oid), git2::Oid>,
+) -> Result<git2::Oid, git2::Error> {
+    let key = (prefix.to_string(), tree.id());
+    if let Some(oid) = trees.get(&key) {
+        return Ok(*oid);
+    }
+
+    let mut builder = repo.treebuilder(Some(tree))?;
+    for entry in tree.iter() {
+        let Some(name) = entry.name() else {
+            continue;
+        };
+        let path = format!("{}{}", prefix, name);
+        if paths.contains(&path.as_str()) || blobs.contains(&entry.id()) {
+            builder.remove(name)?;
+            continue;
+        }
+        if entry.kind() != Some(git2::ObjectType::Tree) {
+            continue;
+        }
+
+        let subtree = repo.find_tree(entry.id())?;
+        let subprefix = format!("{}/", path);
+        let oid = purge_tree(repo, &subtree, &subprefix, paths, blobs, trees)?;
+        if oid == entry.id() {
+            continue;
+        }
+        if repo.find_tree(oid)?.is_empty() {
+            builder.remove(name)?;
+        } else {
+            builder.insert(name, oid, entry.filemode())?;
+        }
+    }
+
+    let oid = builder.write()?;
+    trees.insert(key, oid);
+    Ok(oid)
+}
+
+/// Point the branch at a rewritten history and update the working tree, the
+/// reflogs are removed since they would keep the old history reachable.
+#[cfg(not(target_os = "android"))]
+fn replace_branch(
+    repo: &git2::Repository,
+    branch: &str,
+    new_head: git2::Oid,
+    reflog_message: &str,
+) -> Result<(), git2::Error> {
+    let head_ref_name = format!("refs/heads/{}", branch);
+    repo.reference(&head_ref_name, new_head, true, reflog_message)?;
+    repo.set_head(&head_ref_name)?;
+    repo.checkout_head(Some(CheckoutBuilder::default().force()))?;
+    apply_sparse_dirs(repo)?;
+
+    repo.reflog_delete(&head_ref_name)?;
+    repo.reflog_delete("HEAD")
+}
+
+/// The backup references from `git_recover()` and `git_repair()` and the
+/// sync reference, these are rewritten together with the branch.
+#[cfg(not(target_os = "android"))]
+fn kage_refs(
+    repo: &git2::Repository,
+) -> Result<Vec<(String, git2::Oid)>, git2::Error> {
+    let mut refs = vec![];
+    for reference in repo.references()? {
+        let reference = reference?;
+        let Some(name) = reference.name() else {
+            continue;
+        };
+        if name != GIT_SYNC_REF && !name.starts_with(GIT_BACKUP_REF_PREFIX) {
+            continue;
+        }
+        refs.push((name.to_string(), reference.peel_to_commit()?.id()));
+    }
+    Ok(refs)
+}
+
+/// Names of the references that can reach one of the `commits`
+#[cfg(not(target_os = "android"))]
+fn refs_reaching(
+    repo: &git2::Repository,
+    commits: &HashSet<git2::Oid>,
+) -> Result<Vec<String>, git2::Error> {
+    let mut names = vec![];
+    if commits.is_empty() {
+        return Ok(names);
+    }
+    for reference in repo.references()? {
+        let reference = reference?;
+        if reference.kind() == Some(git2::ReferenceType::Symbolic) {
+            continue;
+        }
+        let (Some(name), Ok(target)) =
+            (reference.name(), reference.peel_to_commit())
+        else {
+            continue;
+        };
+        let mut revwalk = repo.revwalk()?;
+        revwalk.push(target.id())?;
+        for oid in revwalk {
+            if commits.contains(&oid?) {
+                names.push(name.to_string());
+                break;
+            }
+        }
+    }
+    Ok(names)
+}
+
+/// Replace the branch on the remote and all mirrors with the local branch
+#[cfg(not(target_os = "android"))]
+fn force_push(
+    repo: &git2::Repository,
+    branch: &str,
+) -> Result<(), git2::Error> {
+    let remote = config_get().remote;
+    let refspec = format!("+refs/heads/{}", branch);
+    push_remote(repo, &remote, &refspec)?;
+    for name in mirror_names(repo)? {
+        if let Err(err) = push_remote(repo, &name, &refspec) {
+            warn!("Push to mirror '{}' failed: {}", name, err);
+        }
+    }
+
+    let remote_ref_name = format!("refs/remotes/{}/{}", remote, branch);
+    repo.reflog_delete(&remote_ref_name)
+}
+
+/// Check for staged or unstaged changes to tracked files
+fn has_uncommitted_changes(
+    repo: &git2::Repository,
+) -> Result<bool, git2::Error> {
+    let excluded = sparse_excluded_paths(&repo.index()?);
+    let mut status_opts = git2::StatusOptions::new();
+    status_opts.include_untracked(false);
+    let statuses = repo.statuses(Some(&mut status_opts))?;
+    Ok(!statuses.iter().all(|entry| {
+        entry.status().is_empty() || is_sparse_excluded(&excluded, &entry)
+    }))
+}
+
+#[cfg(not(target_os = "android"))]
+fn compacted_error() -> git2::Error {
+    git2::Error::new(
+        git2::ErrorCode::NotFastForward,
+        git2::ErrorClass::Reference,
+        "Remote history was compacted, the store needs to be cloned again",
+    )
+}
+
+/// Check if the history of `oid` starts with a root commit from
+/// `git_compact()` that the local branch does not have.
+#[cfg(not(target_os = "android"))]
+fn remote_compacted(
+    repo: &git2::Repository,
+    branch: &str,
+    oid: git2::Oid,
+) -> Result<bool, git2::Error> {
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(oid)?;
+    revwalk.hide(repo.revparse_single(branch)?.id())?;
+    for oid in revwalk {
+        let commit = repo.find_commit(oid?)?;
+        let message = commit.message().unwrap_or_default();
+        if commit.parent_count() == 0
+            && message.contains(&format!("\n{}: ", GIT_COMPACTED_TRAILER))
+        {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Walk the first-parent history from `head` until the last change of every
+/// entry in the tree of `head` has been found, sorted by path.
+fn last_changes(
+    repo: &git2::Repository,
+    head: &git2::Commit,
+) -> Result<Vec<GitLastChange>, git2::Error> {
+    let mut pending = HashSet::new();
+    head.tree()?
+        .walk(git2::TreeWalkMode::PreOrder, |root, entry| {
+            if entry.kind() == Some(git2::ObjectType::Blob) {
+                let name = entry.name().unwrap_or_default();
+                pending.insert(format!("{}{}", root, name));
+            }
+            git2::TreeWalkResult::Ok
+        })?;
+
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(head.id())?;
+    revwalk.simplify_first_parent()?;
+
+    let mut changes = vec![];
+    for oid in revwalk {
+        if pending.is_empty() {
+            break;
+        }
+        let commit = repo.find_commit(oid?)?;
+        // Everything in the root commit (or at a shallow boundary) is new
+        let parent_tree = match commit.parent(0) {
+            Ok(parent) => Some(parent.tree()?),
+            Err(_) => None,
+        };
+        let diff = repo.diff_tree_to_tree(
+            parent_tree.as_ref(),
+            Some(&commit.tree()?),
+            None,
+        )?;
+        for delta in diff.deltas() {
+            let Some(path) = delta.new_file().path() else {
+                continue;
+            };
+            let path = path.to_string_lossy().to_string();
+            if pending.remove(&path) {
+                let commit = GitCommitInfo::new(&commit);
+                changes.push(GitLastChange { path, commit });
+            }
+        }
+    }
+
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(changes)
 }
 
 fn remote_branch_oid(
     repo: &git2::Repository,
+    branch: &str,
 ) -> Result<git2::Oid, git2::Error> {
-    let spec = format!("{}/{}", GIT_REMOTE, GIT_BRANCH);
+    let spec = format!("{}/{}", config_get().remote, branch);
     let id = repo.revparse_single(&spec)?.id();
     Ok(id)
 }
 
+/// Compare the trees of two commits, renames are detected based on content
+#[cfg(not(target_os = "android"))]
+fn diff_summary(
+    repo: &git2::Repository,
+    old_head: git2::Oid,
+    new_head: git2::Oid,
+) -> Result<GitDiffSummary, git2::Error> {
+    let mut summary = GitDiffSummary {
+        old_head,
+        new_head,
+        added: vec![],
+        modified: vec![],
+        deleted: vec![],
+        renamed: vec![],
+        skipped: vec![],
+    };
+    if old_head == new_head {
+        return Ok(summary);
+    }
+
+    let old_tree = repo.find_commit(old_head)?.tree()?;
+    let new_tree = repo.find_commit(new_head)?.tree()?;
+    let mut diff =
+        repo.diff_tree_to_tree(Some(&old_tree), Some(&new_tree), None)?;
+    diff.find_similar(Some(git2::DiffFindOptions::new().renames(true)))?;
+
+    for delta in diff.deltas() {
+        let old_path = delta_path(delta.old_file());
+        let new_path = delta_path(delta.new_file());
+        match delta.status() {
+            git2::Delta::Added => summary.added.push(new_path),
+            git2::Delta::Modified => summary.modified.push(new_path),
+            git2::Delta::Deleted => summary.deleted.push(old_path),
+            git2::Delta::Renamed => summary.renamed.push((old_path, new_path)),
+            status => warn!("Unexpected delta: {:?} '{}'", status, new_path),
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Describe each change between the parent tree and the tree being committed,
+/// paths are shown without the `.age` extension.
+fn staged_changes(
+    repo: &git2::Repository,
+    parent_tree: Option<&git2::Tree>,
+    tree: &git2::Tree,
+) -> Result<Vec<String>, git2::Error> {
+    let mut diff = repo.diff_tree_to_tree(parent_tree, Some(tree), None)?;
+    diff.find_similar(Some(git2::DiffFindOptions::new().renames(true)))?;
+
+    let entry_name = |file: git2::DiffFile| {
+        let path = delta_path(file);
+        match path.strip_suffix(".age") {
+            Some(name) => name.to_string(),
+            None => path,
+        }
+    };
+
+    let mut changes = vec![];
+    for delta in diff.deltas() {
+        if delta_path(delta.new_file()) == GIT_NAME_INDEX {
+            continue;
+        }
+        let old_name = entry_name(delta.old_file());
+        let new_name = entry_name(delta.new_file());
+        let change = match delta.status() {
+            git2::Delta::Added => format!("Add '{}'", new_name),
+            git2::Delta::Deleted => format!("Remove '{}'", old_name),
+            git2::Delta::Renamed => {
+                format!("Rename '{}' to '{}'", old_name, new_name)
+            }
+            _ => format!("Update '{}'", new_name),
+        };
+        changes.push(change);
+    }
+    Ok(changes)
+}
+
+/// The message for a commit from `parent_tree` to `tree`, an empty `message`
+/// is generated from the changes. The message is encrypted if private
+/// messages are enabled.
+fn commit_message(
+    store: &Store,
+    repo: &git2::Repository,
+    parent_tree: Option<&git2::Tree>,
+    tree: &git2::Tree,
+    message: &str,
+) -> Result<String, git2::Error> {
+    let private = private_messages_enabled(repo)?;
+    if !message.is_empty() && !private {
+        return Ok(message.to_string());
+    }
+
+    let changes = staged_changes(repo, parent_tree, tree)?;
+    let message = if message.is_empty() {
+        changes_message(&changes)?
+    } else {
+        message.to_string()
+    };
+    if private {
+        private_message(store, changes.len(), &message)
+    } else {
+        Ok(message)
+    }
+}
+
+/// A single change is used as the summary, several changes are summarised
+/// by their count. The body always has one line per change.
+fn changes_message(changes: &[String]) -> Result<String, git2::Error> {
+    let summary = match changes.len() {
+        0 => {
+            error!("No staged changes to describe");
+            return Err(internal_error!());
+        }
+        1 => changes[0].clone(),
+        count => entries_summary(count),
+    };
+    Ok(format!("{}\n\n{}", summary, changes.join("\n")))
+}
+
+fn entries_summary(count: usize) -> String {
+    if count == 1 {
+        "Update 1 entry".to_string()
+    } else {
+        format!("Update {} entries", count)
+    }
+}
+
+/// Replace `message` with a summary that only reveals the number of changed
+/// entries, the original message is kept age-encrypted in the body.
+fn private_message(
+    store: &Store,
+    count: usize,
+    message: &str,
+) -> Result<String, git2::Error> {
+    let recipients = store_recipients(store)?;
+    let ciphertext = match age_encrypt_to_recipients_armored(
+        message.as_bytes(),
+        &recipients,
+    ) {
+        Ok(ciphertext) => ciphertext,
+        Err(err) => return Err(age_error("Encryption", err)),
+    };
+    Ok(format!(
+        "{}\n\n{}",
+        entries_summary(count),
+        ciphertext.trim_end()
+    ))
+}
+
+/// Decrypt the original message of a private commit, `None` is returned for
+/// ordinary commits and when the identity is locked.
+fn private_message_decrypt(
+    message: &str,
+    age_state: &AgeState,
+) -> Option<String> {
+    let start = message.find(GIT_PRIVATE_MESSAGE_HEADER)?;
+    match age_state.decrypt(&message.as_bytes()[start..]) {
+        Ok(plaintext) => String::from_utf8(plaintext).ok(),
+        Err(err) => {
+            debug!("Failed to decrypt commit message: {}", err);
+            None
+        }
+    }
+}
+
+fn private_messages_enabled(
+    repo: &git2::Repository,
+) -> Result<bool, git2::Error> {
+    let cfg = git2::Config::open(&repo.path().join("config"))?;
+    match cfg.get_bool(GIT_CONFIG_PRIVATE_MESSAGES) {
+        Ok(enabled) => Ok(enabled),
+        Err(err) if err.code() == git2::ErrorCode::NotFound => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+/// Mapping from the real name of each entry to the path of its encrypted file
+/// in stores with encrypted filenames.
+struct NameIndex {
+    /// Secret used to derive the path for a name
+    key: String,
+    paths: BTreeMap<String, String>,
+    /// Paths of removed entries and their names, kept to show the history
+    removed: BTreeMap<String, String>,
+}
+
+impl NameIndex {
+    fn new() -> Self {
+        Self {
+            key: age_random_secret(),
+            paths: BTreeMap::new(),
+            removed: BTreeMap::new(),
+        }
+    }
+
+    /// The first line holds the key, followed by one "<path> <name>" line
+    /// for each entry and one "removed <path> <name>" line for each entry
+    /// that has been removed.
+    fn parse(plaintext: &str) -> Result<Self, git2::Error> {
+        let mut lines = plaintext.lines();
+        let Some(key) = lines.next().and_then(|l| l.strip_prefix("key "))
+        else {
+            error!("Bad name index");
+            return Err(internal_error!());
+        };
+
+        let mut paths = BTreeMap::new();
+        let mut removed = BTreeMap::new();
+        for line in lines.filter(|l| !l.is_empty()) {
+            let (is_removed, line) = match line.strip_prefix("removed ") {
+                Some(line) => (true, line),
+                None => (false, line),
+            };
+            let Some((path, name)) = line.split_once(' ') else {
+                error!("Bad name index line: '{}'", line);
+                return Err(internal_error!());
+            };
+            if is_removed {
+                removed.insert(path.to_string(), name.to_string());
+            } else {
+                paths.insert(name.to_string(), path.to_string());
+            }
+        }
+        Ok(Self {
+            key: key.to_string(),
+            paths,
+            removed,
+        })
+    }
+
+    fn serialize(&self) -> String {
+        let mut plaintext = format!("key {}\n", self.key);
+        for (name, path) in &self.paths {
+            plaintext.push_str(&format!("{} {}\n", path, name));
+        }
+        for (path, name) in &self.removed {
+            plaintext.push_str(&format!("removed {} {}\n", path, name));
+        }
+        plaintext
+    }
+
+    /// Add `name` to the index, returns the path of its encrypted file
+    fn insert(&mut self, name: &str) -> Result<String, git2::Error> {
+        let data = format!("{}\n{}", self.key, name);
+        let hash =
+            git2::Oid::hash_object(git2::ObjectType::Blob, data.as_bytes())?;
+        let is_otp = name == "otp" || name.ends_with("/otp");
+        let path = if is_otp {
+            format!("{}-otp.age", hash)
+        } else {
+            format!("{}.age", hash)
+        };
+        self.removed.remove(&path);
+        self.paths.insert(name.to_string(), path.clone());
+        Ok(path)
+    }
+
+    /// Remove `name` from the index, returns the path of its encrypted file
+    fn remove(&mut self, name: &str) -> Option<String> {
+        let path = self.paths.remove(name)?;
+        self.removed.insert(path.clone(), name.to_string());
+        Some(path)
+    }
+
+    /// Returns the path with the real name of the entry, e.g.
+    /// "web/github.age", other paths are returned as is
+    fn translate_path(&self, path: &str) -> String {
+        let name = self
+            .paths
+            .iter()
+            .find_map(|(name, p)| (p == path).then_some(name))
+            .or_else(|| self.removed.get(path));
+        match name {
+            Some(name) => format!("{}.age", name),
+            None => path.to_string(),
+        }
+    }
+
+    /// Replace quoted entry paths in `text` with the real names
+    fn translate(&self, text: &str) -> String {
+        let paths = self.paths.iter().map(|(name, path)| (path, name));
+        let mut text = text.to_string();
+        for (path, name) in paths.chain(self.removed.iter()) {
+            let quoted = format!("'{}'", path.trim_end_matches(".age"));
+            if text.contains(&quoted) {
+                text = text.replace(&quoted, &format!("'{}'", name));
+            }
+        }
+        text
+    }
+}
+
+/// Returns `None` if the store does not use encrypted filenames
+fn name_index_load(
+    store: &Store,
+    age_state: &AgeState,
+) -> Result<Option<NameIndex>, git2::Error> {
+    let path = Path::new(&store.repo_path).join(GIT_NAME_INDEX);
+    let ciphertext = match std::fs::read(&path) {
+        Ok(ciphertext) => ciphertext,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(None)
+        }
+        Err(err) => return Err(os_error(&path, err)),
+    };
+    name_index_decrypt(&ciphertext, age_state).map(Some)
+}
+
+/// The name index in the tree of the commit `oid`, `None` if the commit has
+/// no index or if it can not be decrypted with `age_state`
+#[cfg(not(target_os = "android"))]
+fn name_index_load_commit(
+    repo: &git2::Repository,
+    oid: git2::Oid,
+    age_state: &AgeState,
+) -> Option<NameIndex> {
+    let tree = repo.find_commit(oid).ok()?.tree().ok()?;
+    let entry = tree.get_path(Path::new(GIT_NAME_INDEX)).ok()?;
+    let blob = repo.find_blob(entry.id()).ok()?;
+    name_index_decrypt(blob.content(), age_state).ok()
+}
+
+fn name_index_decrypt(
+    ciphertext: &[u8],
+    age_state: &AgeState,
+) -> Result<NameIndex, git2::Error> {
+    let plaintext = match age_state.decrypt(ciphertext) {
+        Ok(plaintext) => plaintext,
+        Err(err) => return Err(age_error("Decryption", err)),
+    };
+    let Ok(plaintext) = String::from_utf8(plaintext) else {
+        error!("Bad name index encoding");
+        return Err(internal_error!());
+    };
+    NameIndex::parse(&plaintext)
+}
+
+fn name_index_save(
+    store: &Store,
+    names: &NameIndex,
+) -> Result<(), git2::Error> {
+    let recipients = store_recipients(store)?;
+    let ciphertext = match age_encrypt_to_recipients_armored(
+        names.serialize().as_bytes(),
+        &recipients,
+    ) {
+        Ok(ciphertext) => ciphertext,
+        Err(err) => return Err(age_error("Encryption", err)),
+    };
+    let path = Path::new(&store.repo_path).join(GIT_NAME_INDEX);
+    match std::fs::write(&path, ciphertext) {
+        Ok(()) => Ok(()),
+        Err(err) => Err(os_error(&path, err)),
+    }
+}
+
+/// Validate an entry name, leading and trailing slashes are ignored
+fn entry_name(name: &str) -> Result<&str, git2::Error> {
+    let name = name.trim_matches('/');
+    let is_valid = !name.is_empty()
+        && !name.contains('\n')
+        && name
+            .split('/')
+            .all(|c| !c.is_empty() && !c.starts_with('.'));
+    if !is_valid {
+        return Err(git2::Error::new(
+            git2::ErrorCode::Invalid,
+            git2::ErrorClass::Invalid,
+            format!("Bad entry name: '{}'", name),
+        ));
+    }
+    Ok(name)
+}
+
+fn entry_not_found(name: &str) -> git2::Error {
+    git2::Error::new(
+        git2::ErrorCode::NotFound,
+        git2::ErrorClass::Invalid,
+        format!("No such entry: '{}'", name),
+    )
+}
+
+fn entry_exists(name: &str) -> git2::Error {
+    git2::Error::new(
+        git2::ErrorCode::Exists,
+        git2::ErrorClass::Invalid,
+        format!("Entry already exists: '{}'", name),
+    )
+}
+
+/// Encrypted files outside of hidden directories
+fn is_entry_path(path: &str) -> bool {
+    path.ends_with(".age") && path.split('/').all(|c| !c.starts_with('.'))
+}
+
+fn workdir_entry_paths(workdir: &Path, dir: &str, paths: &mut Vec<String>) {
+    let Ok(entries) = std::fs::read_dir(workdir.join(dir)) else {
+        return;
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let path = if dir.is_empty() {
+            name
+        } else {
+            format!("{}/{}", dir, name)
+        };
+        if entry.path().is_dir() {
+            if !path.starts_with('.') && !path.contains("/.") {
+                workdir_entry_paths(workdir, &path, paths);
+            }
+        } else if is_entry_path(&path) {
+            paths.push(path);
+        }
+    }
+}
+
+/// Move a file in the working tree, empty parent directories of `from` are
+/// removed.
+fn move_in_workdir(
+    workdir: &Path,
+    from: &str,
+    to: &str,
+) -> Result<(), git2::Error> {
+    let to_path = workdir.join(to);
+    if let Some(parent) = to_path.parent() {
+        if let Err(err) = std::fs::create_dir_all(parent) {
+            return Err(os_error(parent, err));
+        }
+    }
+    if let Err(err) = std::fs::rename(workdir.join(from), &to_path) {
+        return Err(os_error(&to_path, err));
+    }
+    remove_empty_parents(workdir, &workdir.join(from));
+    Ok(())
+}
+
+fn store_recipients(store: &Store) -> Result<String, git2::Error> {
+    let recipients_path = Path::new(&store.repo_path).join(".age-recipients");
+    match std::fs::read_to_string(&recipients_path) {
+        Ok(recipients) => Ok(recipients),
+        Err(err) => Err(os_error(&recipients_path, err)),
+    }
+}
+
+fn age_error(action: &str, err: AgeError) -> git2::Error {
+    git2::Error::new(
+        git2::ErrorCode::GenericError,
+        git2::ErrorClass::Invalid,
+        format!("{} failed: {}", action, err),
+    )
+}
+
+fn delta_path(file: git2::DiffFile) -> String {
+    match file.path() {
+        Some(path) => path.to_string_lossy().to_string(),
+        None => String::new(),
+    }
+}
+
 fn transfer_progress(progress: git2::Progress, _label: &str) -> bool {
     let total = progress.total_objects();
     let total_deltas = progress.total_deltas();
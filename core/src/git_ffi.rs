@@ -4,187 +4,511 @@ use std::ffi::CString;
 use std::os::raw::{c_char, c_int};
 use std::ptr::null;
 
+use crate::age::{age_state, age_try_lock};
+use crate::config::{config_get, Config};
 use crate::git::*;
+use crate::store::{store_get, store_set_branch, Store, STORE_NONE};
 use crate::*;
 
+/// Replace the runtime configuration, empty strings and non-positive
+/// timeouts use the default value, see `Config::new()`.
+#[no_mangle]
+pub extern "C" fn ffi_git_setup(
+    remote: *const c_char,
+    default_branch: *const c_char,
+    email_domain: *const c_char,
+    timeout_ms: c_int,
+    connect_timeout_ms: c_int,
+) -> c_int {
+    let Some(mut git_last_error) = git_try_lock(STORE_NONE) else {
+        return KAGE_ERROR_LOCK_TAKEN as c_int;
+    };
+    let remote = unsafe { CStr::from_ptr(remote).to_str() };
+    let default_branch = unsafe { CStr::from_ptr(default_branch).to_str() };
+    let email_domain = unsafe { CStr::from_ptr(email_domain).to_str() };
+
+    let (Ok(remote), Ok(default_branch), Ok(email_domain)) =
+        (remote, default_branch, email_domain)
+    else {
+        return -1;
+    };
+
+    let config = Config::new(
+        remote,
+        default_branch,
+        email_domain,
+        timeout_ms,
+        connect_timeout_ms,
+    );
+    git_call!(git_configure(config), git_last_error) as c_int
+}
+
+/// Return the runtime configuration as an array of "<key>\n<value>" strings,
+/// see `Config::to_vec()`. Each string must be passed back to rust and freed!
+#[no_mangle]
+pub extern "C" fn ffi_git_config() -> CStringArray {
+    cstring_array(config_get().to_vec())
+}
+
+/// Create a new store with a generated identity protected by `passphrase`,
+/// the initial commit is pushed if the store was registered with a remote.
+#[no_mangle]
+pub extern "C" fn ffi_git_init(
+    store: c_int,
+    passphrase: *const c_char,
+    username: *const c_char,
+) -> c_int {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return KAGE_ERROR_LOCK_TAKEN as c_int;
+    };
+    git_setup();
+
+    let passphrase = unsafe { CStr::from_ptr(passphrase).to_str() };
+    let username = unsafe { CStr::from_ptr(username).to_str() };
+
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return -1;
+    };
+    let (Ok(passphrase), Ok(username)) = (passphrase, username) else {
+        return -1;
+    };
+
+    git_call!(git_init(&store, passphrase, username), git_last_error) as c_int
+}
+
+/// Clone the remote of `store`, a `depth` of zero clones the full history.
+/// `sparse_dirs` is a newline separated list of directories to checkout, an
+/// empty string checks out everything. The objects of excluded directories
+/// are still downloaded, see `git_clone()`.
 #[no_mangle]
 pub extern "C" fn ffi_git_clone(
-    url: *const c_char,
-    into: *const c_char,
+    store: c_int,
+    depth: c_int,
+    sparse_dirs: *const c_char,
 ) -> c_int {
-    let Some(mut git_last_error) = git_try_lock() else {
+    let Some(mut git_last_error) = git_try_lock(store) else {
         return KAGE_ERROR_LOCK_TAKEN as c_int;
     };
     git_setup();
 
-    let url = unsafe { CStr::from_ptr(url).to_str() };
-    let into = unsafe { CStr::from_ptr(into).to_str() };
+    let sparse_dirs = unsafe { CStr::from_ptr(sparse_dirs).to_str() };
 
-    let (Ok(url), Ok(into)) = (url, into) else {
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return -1;
+    };
+    let Ok(sparse_dirs) = sparse_dirs else {
         return -1;
     };
+    let sparse_dirs: Vec<&str> = sparse_dirs.lines().collect();
 
-    git_call!(git_clone(url, into), git_last_error) as c_int
+    git_call!(git_clone(&store, depth, &sparse_dirs), git_last_error) as c_int
 }
 
 #[no_mangle]
-pub extern "C" fn ffi_git_pull(repo_path: *const c_char) -> c_int {
-    let Some(mut git_last_error) = git_try_lock() else {
+pub extern "C" fn ffi_git_pull(store: c_int) -> c_int {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return KAGE_ERROR_LOCK_TAKEN as c_int;
+    };
+    let Some(mut age_lock) = age_try_lock(store) else {
         return KAGE_ERROR_LOCK_TAKEN as c_int;
     };
     git_setup();
 
-    let repo_path = unsafe { CStr::from_ptr(repo_path).to_str() };
+    let Some(age_state) = age_state(&mut age_lock, store) else {
+        return -1;
+    };
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return -1;
+    };
+
+    git_call!(git_pull(&store, age_state), git_last_error) as c_int
+}
+
+/// Same as `ffi_git_pull()` but returns the entries that changed as an array
+/// of "<status>\n<path>" strings, see `GitDiffSummary::to_vec()`.
+/// Each string must be passed back to rust and freed!
+#[no_mangle]
+pub extern "C" fn ffi_git_pull_summary(store: c_int) -> CStringArray {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+    let Some(mut age_lock) = age_try_lock(store) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+    git_setup();
+
+    let Some(age_state) = age_state(&mut age_lock, store) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+
+    match git_pull(&store, age_state) {
+        Ok(summary) => cstring_array(summary.to_vec()),
+        Err(err) => {
+            error!("{}", err);
+            *git_last_error = Some(err);
+            CStringArray {
+                ptr: null(),
+                len: -1,
+            }
+        }
+    }
+}
+
+/// Fetch the remote branch without updating the local checkout, returns the
+/// incoming commits and entries that changed as an array of strings, see
+/// `GitFetchPreview::to_vec()`.
+/// Each string must be passed back to rust and freed!
+#[no_mangle]
+pub extern "C" fn ffi_git_fetch(store: c_int) -> CStringArray {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+    let Some(mut age_lock) = age_try_lock(store) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+    git_setup();
 
-    let Ok(repo_path) = repo_path else { return -1 };
+    let Some(age_state) = age_state(&mut age_lock, store) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
 
-    git_call!(git_pull(repo_path), git_last_error) as c_int
+    match git_fetch(&store, age_state) {
+        Ok(preview) => cstring_array(preview.to_vec()),
+        Err(err) => {
+            error!("{}", err);
+            *git_last_error = Some(err);
+            CStringArray {
+                ptr: null(),
+                len: -1,
+            }
+        }
+    }
 }
 
 #[no_mangle]
-pub extern "C" fn ffi_git_push(repo_path: *const c_char) -> c_int {
-    let Some(mut git_last_error) = git_try_lock() else {
+pub extern "C" fn ffi_git_push(store: c_int) -> c_int {
+    let Some(mut git_last_error) = git_try_lock(store) else {
         return KAGE_ERROR_LOCK_TAKEN as c_int;
     };
     git_setup();
 
-    let repo_path = unsafe { CStr::from_ptr(repo_path).to_str() };
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return -1;
+    };
+
+    git_call!(git_push(&store), git_last_error) as c_int
+}
+
+/// Same as `ffi_git_push()` but returns an array of "<remote>\n[<error>]"
+/// strings with the outcome for the primary remote and each mirror, see
+/// `GitPushResult`. Each string must be passed back to rust and freed!
+#[no_mangle]
+pub extern "C" fn ffi_git_push_results(store: c_int) -> CStringArray {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+    git_setup();
+
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+
+    match git_push(&store) {
+        Ok(arr) => cstring_array(arr.iter().map(|r| r.to_string()).collect()),
+        Err(err) => {
+            error!("{}", err);
+            *git_last_error = Some(err);
+            CStringArray {
+                ptr: null(),
+                len: -1,
+            }
+        }
+    }
+}
+
+/// Return an array of "<P|M>\n<name>\n<url>" strings for the primary remote
+/// and all mirrors, see `GitRemote`. Each string must be passed back to rust
+/// and freed!
+#[no_mangle]
+pub extern "C" fn ffi_git_remote_list(store: c_int) -> CStringArray {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+
+    match git_remote_list(&store) {
+        Ok(arr) => cstring_array(arr.iter().map(|r| r.to_string()).collect()),
+        Err(err) => {
+            error!("{}", err);
+            *git_last_error = Some(err);
+            CStringArray {
+                ptr: null(),
+                len: -1,
+            }
+        }
+    }
+}
+
+/// Return an array of "<*| ><L|-><R|->\n<name>" strings for the local and
+/// remote branches, see `GitBranch`. Each string must be passed back to rust
+/// and freed!
+#[no_mangle]
+pub extern "C" fn ffi_git_branch_list(store: c_int) -> CStringArray {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
 
-    let Ok(repo_path) = repo_path else { return -1 };
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
 
-    git_call!(git_push(repo_path), git_last_error) as c_int
+    match git_branch_list(&store) {
+        Ok(arr) => cstring_array(arr.iter().map(|b| b.to_string()).collect()),
+        Err(err) => {
+            error!("{}", err);
+            *git_last_error = Some(err);
+            CStringArray {
+                ptr: null(),
+                len: -1,
+            }
+        }
+    }
 }
 
-/// Stage an 'add' or a 'rm' operation
 #[no_mangle]
-pub extern "C" fn ffi_git_stage(
-    repo_path: *const c_char,
-    relative_path: *const c_char,
+pub extern "C" fn ffi_git_branch_create(
+    store: c_int,
+    name: *const c_char,
 ) -> c_int {
-    let Some(mut git_last_error) = git_try_lock() else {
+    let Some(mut git_last_error) = git_try_lock(store) else {
         return KAGE_ERROR_LOCK_TAKEN as c_int;
     };
-    let repo_path = unsafe { CStr::from_ptr(repo_path).to_str() };
-    let relative_path = unsafe { CStr::from_ptr(relative_path).to_str() };
+    let name = unsafe { CStr::from_ptr(name).to_str() };
 
-    let (Ok(repo_path), Ok(relative_path)) = (repo_path, relative_path) else {
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return -1;
+    };
+    let Ok(name) = name else {
         return -1;
     };
 
-    git_call!(git_stage(repo_path, relative_path), git_last_error) as c_int
+    git_call!(git_branch_create(&store, name), git_last_error) as c_int
 }
 
+/// Check out the branch `name` and make it the branch that `store` pulls
+/// from and pushes to.
 #[no_mangle]
-pub extern "C" fn ffi_git_reset(repo_path: *const c_char) -> c_int {
-    let Some(mut git_last_error) = git_try_lock() else {
+pub extern "C" fn ffi_git_branch_switch(
+    store: c_int,
+    name: *const c_char,
+) -> c_int {
+    let Some(mut git_last_error) = git_try_lock(store) else {
         return KAGE_ERROR_LOCK_TAKEN as c_int;
     };
     git_setup();
 
-    let repo_path = unsafe { CStr::from_ptr(repo_path).to_str() };
+    let name = unsafe { CStr::from_ptr(name).to_str() };
+    let handle = store;
 
-    let Ok(repo_path) = repo_path else { return -1 };
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return -1;
+    };
+    let Ok(name) = name else {
+        return -1;
+    };
 
-    git_call!(git_reset(repo_path), git_last_error) as c_int
+    // Never leave the working tree on a branch the store does not push to
+    if store_set_branch(handle, name).is_none() {
+        return -1;
+    }
+    let r = git_call!(git_branch_switch(&store, name), git_last_error);
+    if r != 0 {
+        store_set_branch(handle, &store.branch);
+    }
+    r as c_int
 }
 
 #[no_mangle]
-pub extern "C" fn ffi_git_config_set_user(
-    repo_path: *const c_char,
-    username: *const c_char,
+pub extern "C" fn ffi_git_mirror_add(
+    store: c_int,
+    name: *const c_char,
+    url: *const c_char,
 ) -> c_int {
-    let Some(mut git_last_error) = git_try_lock() else {
+    let Some(mut git_last_error) = git_try_lock(store) else {
         return KAGE_ERROR_LOCK_TAKEN as c_int;
     };
-    let repo_path = unsafe { CStr::from_ptr(repo_path).to_str() };
-    let username = unsafe { CStr::from_ptr(username).to_str() };
+    let name = unsafe { CStr::from_ptr(name).to_str() };
+    let url = unsafe { CStr::from_ptr(url).to_str() };
 
-    let (Ok(repo_path), Ok(username)) = (repo_path, username) else {
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return -1;
+    };
+    let (Ok(name), Ok(url)) = (name, url) else {
         return -1;
     };
 
-    git_call!(git_config_set_user(repo_path, username), git_last_error) as c_int
+    git_call!(git_mirror_add(&store, name, url), git_last_error) as c_int
 }
 
 #[no_mangle]
-pub extern "C" fn ffi_git_commit(
-    repo_path: *const c_char,
-    message: *const c_char,
+pub extern "C" fn ffi_git_mirror_remove(
+    store: c_int,
+    name: *const c_char,
 ) -> c_int {
-    let Some(mut git_last_error) = git_try_lock() else {
+    let Some(mut git_last_error) = git_try_lock(store) else {
         return KAGE_ERROR_LOCK_TAKEN as c_int;
     };
-    let repo_path = unsafe { CStr::from_ptr(repo_path).to_str() };
-    let message = unsafe { CStr::from_ptr(message).to_str() };
+    let name = unsafe { CStr::from_ptr(name).to_str() };
 
-    let (Ok(repo_path), Ok(message)) = (repo_path, message) else {
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return -1;
+    };
+    let Ok(name) = name else {
         return -1;
     };
 
-    git_call!(git_commit(repo_path, message), git_last_error) as c_int
+    git_call!(git_mirror_remove(&store, name), git_last_error) as c_int
 }
 
+/// Export the commits that are not reachable from `basis` into a bundle file
+/// at `path`, an empty `basis` exports the full history.
 #[no_mangle]
-pub extern "C" fn ffi_git_local_head_matches_remote(
-    repo_path: *const c_char,
+pub extern "C" fn ffi_git_bundle_export(
+    store: c_int,
+    path: *const c_char,
+    basis: *const c_char,
 ) -> c_int {
-    let Some(mut git_last_error) = git_try_lock() else {
+    let Some(mut git_last_error) = git_try_lock(store) else {
         return KAGE_ERROR_LOCK_TAKEN as c_int;
     };
-    let repo_path = unsafe { CStr::from_ptr(repo_path).to_str() };
+    let path = unsafe { CStr::from_ptr(path).to_str() };
+    let basis = unsafe { CStr::from_ptr(basis).to_str() };
 
-    let Ok(repo_path) = repo_path else { return -1 };
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return -1;
+    };
+    let (Ok(path), Ok(basis)) = (path, basis) else {
+        return -1;
+    };
 
-    match git_local_head_matches_remote(repo_path) {
-        Ok(r) => r as c_int,
+    git_call!(git_bundle_export(&store, path, basis), git_last_error) as c_int
+}
+
+/// Import a bundle file and fast-forward to it, returns the entries that
+/// changed in the same format as `ffi_git_pull_summary()`.
+/// Each string must be passed back to rust and freed!
+#[no_mangle]
+pub extern "C" fn ffi_git_bundle_import(
+    store: c_int,
+    path: *const c_char,
+) -> CStringArray {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+    let path = unsafe { CStr::from_ptr(path).to_str() };
+
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+    let Ok(path) = path else {
+        return CStringArray {
+            ptr: null(),
+            len: 0,
+        };
+    };
+
+    match git_bundle_import(&store, path) {
+        Ok(summary) => cstring_array(summary.to_vec()),
         Err(err) => {
             error!("{}", err);
             *git_last_error = Some(err);
-            git_last_error.as_ref().unwrap().raw_code() as c_int
+            CStringArray {
+                ptr: null(),
+                len: -1,
+            }
         }
     }
 }
 
-/// Return an array of commit messages as "<timtestamp>\n<summary>" strings.
+/// Recover from a rewritten remote history, returns the backup reference
+/// and the replayed and failed commits, see `GitRecovery::to_vec()`.
 /// Each string must be passed back to rust and freed!
 #[no_mangle]
-pub extern "C" fn ffi_git_log(repo_path: *const c_char) -> CStringArray {
-    let Some(mut git_last_error) = git_try_lock() else {
+pub extern "C" fn ffi_git_recover(store: c_int) -> CStringArray {
+    let Some(mut git_last_error) = git_try_lock(store) else {
         return CStringArray {
             ptr: null(),
             len: -1,
         };
     };
-    let repo_path = unsafe { CStr::from_ptr(repo_path).to_str() };
+    git_setup();
 
-    let Ok(repo_path) = repo_path else {
+    let Some(store) = load_store(store, &mut git_last_error) else {
         return CStringArray {
             ptr: null(),
-            len: 0,
+            len: -1,
         };
     };
 
-    match git_log(repo_path) {
-        Ok(arr) => {
-            let len = arr.len() as c_int;
-            let data = arr
-                .into_iter()
-                .map(|s| {
-                    let Ok(cs) = CString::new(s) else {
-                        return null();
-                    };
-                    cs.into_raw()
-                })
-                .collect::<Vec<_>>();
-
-            let ptr = data.as_ptr();
-
-            // Prevent the `data` vector from being deallocated when leaving
-            // scope, we need to free the contents of the array manually later!
-            std::mem::forget(data);
-
-            return CStringArray { ptr, len };
-        }
+    match git_recover(&store) {
+        Ok(recovery) => cstring_array(recovery.to_vec()),
         Err(err) => {
             error!("{}", err);
             *git_last_error = Some(err);
@@ -196,16 +520,1018 @@ pub extern "C" fn ffi_git_log(repo_path: *const c_char) -> CStringArray {
     }
 }
 
-/// Return a dynamically allocated string describing the last error that
-/// occurred. The string must be passed back to rust and freed!
-/// The internal `last_error` is cleared after being retrieved!
+/// Replace the repository with a new clone if it is corrupt, returns the
+/// problems that were found and the outcome, see `GitRepair::to_vec()`.
+/// Each string must be passed back to rust and freed!
 #[no_mangle]
-pub extern "C" fn ffi_git_strerror() -> *const c_char {
-    let Some(mut git_last_error) = git_try_lock() else {
-        return null();
-    };
-    let Some(err) = git_last_error.as_ref() else {
-        return null();
+pub extern "C" fn ffi_git_repair(store: c_int) -> CStringArray {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+    git_setup();
+
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+
+    match git_repair(&store) {
+        Ok(repair) => cstring_array(repair.to_vec()),
+        Err(err) => {
+            error!("{}", err);
+            *git_last_error = Some(err);
+            CStringArray {
+                ptr: null(),
+                len: -1,
+            }
+        }
+    }
+}
+
+/// Remove the newline separated `paths` and `blob_ids` from the history of
+/// the branch, the result is only force-pushed if `confirm_force_push` is
+/// non-zero. Returns the commit mapping, see `GitPurge::to_vec()`.
+/// Each string must be passed back to rust and freed!
+#[no_mangle]
+pub extern "C" fn ffi_git_purge(
+    store: c_int,
+    paths: *const c_char,
+    blob_ids: *const c_char,
+    confirm_force_push: c_int,
+) -> CStringArray {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+    git_setup();
+
+    let paths = unsafe { CStr::from_ptr(paths).to_str() };
+    let blob_ids = unsafe { CStr::from_ptr(blob_ids).to_str() };
+
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+    let (Ok(paths), Ok(blob_ids)) = (paths, blob_ids) else {
+        return CStringArray {
+            ptr: null(),
+            len: 0,
+        };
+    };
+    let paths: Vec<&str> = paths.lines().collect();
+    let blob_ids: Vec<&str> = blob_ids.lines().collect();
+
+    match git_purge(&store, &paths, &blob_ids, confirm_force_push != 0) {
+        Ok(purge) => cstring_array(purge.to_vec()),
+        Err(err) => {
+            error!("{}", err);
+            *git_last_error = Some(err);
+            CStringArray {
+                ptr: null(),
+                len: -1,
+            }
+        }
+    }
+}
+
+/// Squash the history up to `before` into one root commit after writing a
+/// backup bundle to `backup_path`, an empty `before` uses the newest commit
+/// that is not newer than the unix timestamp `before_time`. The result is
+/// only force-pushed if `confirm_force_push` is non-zero. See
+/// `GitCompact::to_vec()` for the returned array.
+/// Each string must be passed back to rust and freed!
+#[no_mangle]
+pub extern "C" fn ffi_git_compact(
+    store: c_int,
+    before: *const c_char,
+    before_time: i64,
+    backup_path: *const c_char,
+    confirm_force_push: c_int,
+) -> CStringArray {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+    git_setup();
+
+    let before = unsafe { CStr::from_ptr(before).to_str() };
+    let backup_path = unsafe { CStr::from_ptr(backup_path).to_str() };
+
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+    let (Ok(before), Ok(backup_path)) = (before, backup_path) else {
+        return CStringArray {
+            ptr: null(),
+            len: 0,
+        };
+    };
+
+    match git_compact(
+        &store,
+        before,
+        before_time,
+        backup_path,
+        confirm_force_push != 0,
+    ) {
+        Ok(compact) => cstring_array(compact.to_vec()),
+        Err(err) => {
+            error!("{}", err);
+            *git_last_error = Some(err);
+            CStringArray {
+                ptr: null(),
+                len: -1,
+            }
+        }
+    }
+}
+
+/// Write unsynced commits as an encrypted bundle into the sync directory,
+/// returns the number of exported commits.
+#[no_mangle]
+pub extern "C" fn ffi_git_sync_export(
+    store: c_int,
+    dir: *const c_char,
+) -> c_int {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return KAGE_ERROR_LOCK_TAKEN as c_int;
+    };
+    let dir = unsafe { CStr::from_ptr(dir).to_str() };
+
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return -1;
+    };
+    let Ok(dir) = dir else {
+        return -1;
+    };
+
+    git_call!(git_sync_export(&store, dir), git_last_error) as c_int
+}
+
+/// Apply the encrypted bundles from other devices in the sync directory, the
+/// identity of the store must be unlocked. Returns the entries that changed
+/// in the same format as `ffi_git_pull_summary()`, diverged bundles that were
+/// skipped are listed as "S\n<bundle name>".
+/// Each string must be passed back to rust and freed!
+#[no_mangle]
+pub extern "C" fn ffi_git_sync_import(
+    store: c_int,
+    dir: *const c_char,
+) -> CStringArray {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+    let Some(mut age_lock) = age_try_lock(store) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+    let dir = unsafe { CStr::from_ptr(dir).to_str() };
+
+    let Some(age_state) = age_state(&mut age_lock, store) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+    let Ok(dir) = dir else {
+        return CStringArray {
+            ptr: null(),
+            len: 0,
+        };
+    };
+
+    match git_sync_import(&store, age_state, dir) {
+        Ok(summary) => cstring_array(summary.to_vec()),
+        Err(err) => {
+            error!("{}", err);
+            *git_last_error = Some(err);
+            CStringArray {
+                ptr: null(),
+                len: -1,
+            }
+        }
+    }
+}
+
+/// Fetch up to `depth` commits of history into a shallow clone, a `depth` of
+/// zero fetches the full history.
+#[no_mangle]
+pub extern "C" fn ffi_git_deepen(store: c_int, depth: c_int) -> c_int {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return KAGE_ERROR_LOCK_TAKEN as c_int;
+    };
+    git_setup();
+
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return -1;
+    };
+
+    git_call!(git_deepen(&store, depth), git_last_error) as c_int
+}
+
+/// Set the directories to checkout from a newline separated list, an empty
+/// string restores a full checkout.
+#[no_mangle]
+pub extern "C" fn ffi_git_sparse_set(
+    store: c_int,
+    dirs: *const c_char,
+) -> c_int {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return KAGE_ERROR_LOCK_TAKEN as c_int;
+    };
+    let dirs = unsafe { CStr::from_ptr(dirs).to_str() };
+
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return -1;
+    };
+    let Ok(dirs) = dirs else {
+        return -1;
+    };
+    let dirs: Vec<&str> = dirs.lines().collect();
+
+    git_call!(git_sparse_set(&store, &dirs), git_last_error) as c_int
+}
+
+/// Return the directories that are checked out, an empty array means that
+/// everything is checked out. Each string must be passed back to rust and
+/// freed!
+#[no_mangle]
+pub extern "C" fn ffi_git_sparse_list(store: c_int) -> CStringArray {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+
+    match git_sparse_list(&store) {
+        Ok(arr) => cstring_array(arr),
+        Err(err) => {
+            error!("{}", err);
+            *git_last_error = Some(err);
+            CStringArray {
+                ptr: null(),
+                len: -1,
+            }
+        }
+    }
+}
+
+/// Stage an 'add' or a 'rm' operation
+#[no_mangle]
+pub extern "C" fn ffi_git_stage(
+    store: c_int,
+    relative_path: *const c_char,
+) -> c_int {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return KAGE_ERROR_LOCK_TAKEN as c_int;
+    };
+    let relative_path = unsafe { CStr::from_ptr(relative_path).to_str() };
+
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return -1;
+    };
+    let Ok(relative_path) = relative_path else {
+        return -1;
+    };
+
+    git_call!(git_stage(&store, relative_path), git_last_error) as c_int
+}
+
+#[no_mangle]
+pub extern "C" fn ffi_git_reset(store: c_int) -> c_int {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return KAGE_ERROR_LOCK_TAKEN as c_int;
+    };
+    git_setup();
+
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return -1;
+    };
+
+    git_call!(git_reset(&store), git_last_error) as c_int
+}
+
+/// Hard reset and remove all untracked and ignored files, nothing is removed
+/// if `dry_run` is non-zero. Returns the paths that were (or would be)
+/// removed. Each string must be passed back to rust and freed!
+#[no_mangle]
+pub extern "C" fn ffi_git_discard(
+    store: c_int,
+    dry_run: c_int,
+) -> CStringArray {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+
+    match git_discard(&store, dry_run != 0) {
+        Ok(paths) => cstring_array(paths),
+        Err(err) => {
+            error!("{}", err);
+            *git_last_error = Some(err);
+            CStringArray {
+                ptr: null(),
+                len: -1,
+            }
+        }
+    }
+}
+
+/// Create a commit that reverts the changes from `rev`
+#[no_mangle]
+pub extern "C" fn ffi_git_revert(store: c_int, rev: *const c_char) -> c_int {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return KAGE_ERROR_LOCK_TAKEN as c_int;
+    };
+    let rev = unsafe { CStr::from_ptr(rev).to_str() };
+
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return -1;
+    };
+    let Ok(rev) = rev else {
+        return -1;
+    };
+
+    git_call!(git_revert(&store, rev), git_last_error) as c_int
+}
+
+#[no_mangle]
+pub extern "C" fn ffi_git_config_set_user(
+    store: c_int,
+    username: *const c_char,
+) -> c_int {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return KAGE_ERROR_LOCK_TAKEN as c_int;
+    };
+    let username = unsafe { CStr::from_ptr(username).to_str() };
+
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return -1;
+    };
+    let Ok(username) = username else {
+        return -1;
+    };
+
+    git_call!(git_config_set_user(&store, username), git_last_error) as c_int
+}
+
+#[no_mangle]
+pub extern "C" fn ffi_git_config_set_private_messages(
+    store: c_int,
+    enabled: c_int,
+) -> c_int {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return KAGE_ERROR_LOCK_TAKEN as c_int;
+    };
+
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return -1;
+    };
+
+    git_call!(
+        git_config_set_private_messages(&store, enabled != 0),
+        git_last_error
+    ) as c_int
+}
+
+/// Commit the staged changes, an empty `message` generates one from the
+/// staged diff.
+#[no_mangle]
+pub extern "C" fn ffi_git_commit(
+    store: c_int,
+    message: *const c_char,
+) -> c_int {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return KAGE_ERROR_LOCK_TAKEN as c_int;
+    };
+    let message = unsafe { CStr::from_ptr(message).to_str() };
+
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return -1;
+    };
+    let Ok(message) = message else {
+        return -1;
+    };
+
+    git_call!(git_commit(&store, message), git_last_error) as c_int
+}
+
+/// Squash all unpushed commits into one, an empty `message` combines the
+/// messages of the squashed commits.
+#[no_mangle]
+pub extern "C" fn ffi_git_squash(
+    store: c_int,
+    message: *const c_char,
+) -> c_int {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return KAGE_ERROR_LOCK_TAKEN as c_int;
+    };
+    let message = unsafe { CStr::from_ptr(message).to_str() };
+
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return -1;
+    };
+    let Ok(message) = message else {
+        return -1;
+    };
+
+    git_call!(git_squash(&store, message), git_last_error) as c_int
+}
+
+/// Amend the latest unpushed commit with the staged changes, an empty
+/// `message` keeps the current message.
+#[no_mangle]
+pub extern "C" fn ffi_git_amend(store: c_int, message: *const c_char) -> c_int {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return KAGE_ERROR_LOCK_TAKEN as c_int;
+    };
+    let message = unsafe { CStr::from_ptr(message).to_str() };
+
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return -1;
+    };
+    let Ok(message) = message else {
+        return -1;
+    };
+
+    git_call!(git_amend(&store, message), git_last_error) as c_int
+}
+
+#[no_mangle]
+pub extern "C" fn ffi_git_local_head_matches_remote(store: c_int) -> c_int {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return KAGE_ERROR_LOCK_TAKEN as c_int;
+    };
+
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return -1;
+    };
+
+    match git_local_head_matches_remote(&store) {
+        Ok(r) => r as c_int,
+        Err(err) => {
+            error!("{}", err);
+            *git_last_error = Some(err);
+            git_last_error.as_ref().unwrap().raw_code() as c_int
+        }
+    }
+}
+
+/// Write the number of local commits that have not been pushed to `ahead` and
+/// the number of remote commits that have not been pulled to `behind`.
+/// The remote branch is fetched first if `fetch` is true.
+#[no_mangle]
+pub extern "C" fn ffi_git_ahead_behind(
+    store: c_int,
+    fetch: bool,
+    ahead: *mut c_int,
+    behind: *mut c_int,
+) -> c_int {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return KAGE_ERROR_LOCK_TAKEN as c_int;
+    };
+    git_setup();
+
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return -1;
+    };
+
+    match git_ahead_behind(&store, fetch) {
+        Ok(r) => {
+            unsafe {
+                *ahead = r.ahead as c_int;
+                *behind = r.behind as c_int;
+            }
+            0
+        }
+        Err(err) => {
+            error!("{}", err);
+            *git_last_error = Some(err);
+            git_last_error.as_ref().unwrap().raw_code() as c_int
+        }
+    }
+}
+
+/// Return an array of "<index><worktree>\n<path>" strings for all changed
+/// paths, renames are given as "<index><worktree>\n<old path>\n<new path>",
+/// see `GitStatusEntry`. Each string must be passed back to rust and freed!
+#[no_mangle]
+pub extern "C" fn ffi_git_status(store: c_int) -> CStringArray {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+    let Some(mut age_lock) = age_try_lock(store) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+
+    let Some(age_state) = age_state(&mut age_lock, store) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+
+    match git_status(&store, age_state) {
+        Ok(arr) => cstring_array(arr.iter().map(|e| e.to_string()).collect()),
+        Err(err) => {
+            error!("{}", err);
+            *git_last_error = Some(err);
+            CStringArray {
+                ptr: null(),
+                len: -1,
+            }
+        }
+    }
+}
+
+/// Returns size statistics as an array of "<key>\n<value>" strings, see
+/// `GitStats::to_vec()`. Each string must be passed back to rust and freed!
+#[no_mangle]
+pub extern "C" fn ffi_git_stats(store: c_int) -> CStringArray {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+
+    match git_stats(&store) {
+        Ok(stats) => cstring_array(stats.to_vec()),
+        Err(err) => {
+            error!("{}", err);
+            *git_last_error = Some(err);
+            CStringArray {
+                ptr: null(),
+                len: -1,
+            }
+        }
+    }
+}
+
+/// Repack the repository and prune unreachable objects that are older than
+/// `grace_period_s`, returns the statistics afterwards in the same format as
+/// `ffi_git_stats()`. Each string must be passed back to rust and freed!
+#[no_mangle]
+pub extern "C" fn ffi_git_maintenance(
+    store: c_int,
+    grace_period_s: c_int,
+) -> CStringArray {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+
+    match git_maintenance(&store, grace_period_s as i64) {
+        Ok(stats) => cstring_array(stats.to_vec()),
+        Err(err) => {
+            error!("{}", err);
+            *git_last_error = Some(err);
+            CStringArray {
+                ptr: null(),
+                len: -1,
+            }
+        }
+    }
+}
+
+/// Returns the last commit that modified each entry at or below `path` as
+/// an array of "<path>\n<timestamp>\n<oid>\n<author>\n<summary>" strings.
+/// Each string must be passed back to rust and freed!
+#[no_mangle]
+pub extern "C" fn ffi_git_last_changed(
+    store: c_int,
+    path: *const c_char,
+) -> CStringArray {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+    let Some(mut age_lock) = age_try_lock(store) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+    let path = unsafe { CStr::from_ptr(path).to_str() };
+
+    let Some(age_state) = age_state(&mut age_lock, store) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+    let Ok(path) = path else {
+        return CStringArray {
+            ptr: null(),
+            len: 0,
+        };
+    };
+
+    match git_last_changed(&store, age_state, path) {
+        Ok(arr) => cstring_array(arr.iter().map(|c| c.to_string()).collect()),
+        Err(err) => {
+            error!("{}", err);
+            *git_last_error = Some(err);
+            CStringArray {
+                ptr: null(),
+                len: -1,
+            }
+        }
+    }
+}
+
+/// Return an array of commit messages as "<timtestamp>\n<summary>" strings.
+/// Each string must be passed back to rust and freed!
+#[no_mangle]
+pub extern "C" fn ffi_git_log(store: c_int) -> CStringArray {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+
+    let Some(mut age_lock) = age_try_lock(store) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+
+    let Some(age_state) = age_state(&mut age_lock, store) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+
+    match git_log(&store, age_state) {
+        Ok(arr) => cstring_array(arr),
+        Err(err) => {
+            error!("{}", err);
+            *git_last_error = Some(err);
+            CStringArray {
+                ptr: null(),
+                len: -1,
+            }
+        }
+    }
+}
+
+/// Returns the entries with a name that contains `query` as an array of
+/// "<name>\n<path>" strings, an empty `query` returns all entries.
+/// Each string must be passed back to rust and freed!
+#[no_mangle]
+pub extern "C" fn ffi_git_entries(
+    store: c_int,
+    query: *const c_char,
+) -> CStringArray {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+    let Some(mut age_lock) = age_try_lock(store) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+    let query = unsafe { CStr::from_ptr(query).to_str() };
+
+    let Some(age_state) = age_state(&mut age_lock, store) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+    let Ok(query) = query else {
+        return CStringArray {
+            ptr: null(),
+            len: 0,
+        };
+    };
+
+    match git_entries(&store, age_state, query) {
+        Ok(arr) => cstring_array(arr.iter().map(|e| e.to_string()).collect()),
+        Err(err) => {
+            error!("{}", err);
+            *git_last_error = Some(err);
+            CStringArray {
+                ptr: null(),
+                len: -1,
+            }
+        }
+    }
+}
+
+/// Returns the path of the encrypted file for the entry `name` as the only
+/// element of an array, new names are added to the name index by
+/// `ffi_git_entry_stage()`.
+/// Each string must be passed back to rust and freed!
+#[no_mangle]
+pub extern "C" fn ffi_git_entry_path(
+    store: c_int,
+    name: *const c_char,
+) -> CStringArray {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+    let Some(mut age_lock) = age_try_lock(store) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+    let name = unsafe { CStr::from_ptr(name).to_str() };
+
+    let Some(age_state) = age_state(&mut age_lock, store) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+    let Ok(name) = name else {
+        return CStringArray {
+            ptr: null(),
+            len: 0,
+        };
+    };
+
+    match git_entry_path(&store, age_state, name) {
+        Ok(path) => cstring_array(vec![path]),
+        Err(err) => {
+            error!("{}", err);
+            *git_last_error = Some(err);
+            CStringArray {
+                ptr: null(),
+                len: -1,
+            }
+        }
+    }
+}
+
+/// Stage the file of the entry `name`, see `git_entry_stage()`
+#[no_mangle]
+pub extern "C" fn ffi_git_entry_stage(
+    store: c_int,
+    name: *const c_char,
+) -> c_int {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return KAGE_ERROR_LOCK_TAKEN as c_int;
+    };
+    let Some(mut age_lock) = age_try_lock(store) else {
+        return KAGE_ERROR_LOCK_TAKEN as c_int;
+    };
+    let name = unsafe { CStr::from_ptr(name).to_str() };
+
+    let Some(age_state) = age_state(&mut age_lock, store) else {
+        return -1;
+    };
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return -1;
+    };
+    let Ok(name) = name else {
+        return -1;
+    };
+
+    git_call!(git_entry_stage(&store, age_state, name), git_last_error) as c_int
+}
+
+/// Move the entry `old_name` to `new_name`, returns the old and the new path.
+/// Each string must be passed back to rust and freed!
+#[no_mangle]
+pub extern "C" fn ffi_git_entry_rename(
+    store: c_int,
+    old_name: *const c_char,
+    new_name: *const c_char,
+) -> CStringArray {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+    let Some(mut age_lock) = age_try_lock(store) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+    let old_name = unsafe { CStr::from_ptr(old_name).to_str() };
+    let new_name = unsafe { CStr::from_ptr(new_name).to_str() };
+
+    let Some(age_state) = age_state(&mut age_lock, store) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+    let (Ok(old_name), Ok(new_name)) = (old_name, new_name) else {
+        return CStringArray {
+            ptr: null(),
+            len: 0,
+        };
+    };
+
+    match git_entry_rename(&store, age_state, old_name, new_name) {
+        Ok((old_path, new_path)) => cstring_array(vec![old_path, new_path]),
+        Err(err) => {
+            error!("{}", err);
+            *git_last_error = Some(err);
+            CStringArray {
+                ptr: null(),
+                len: -1,
+            }
+        }
+    }
+}
+
+/// Remove the entry `name`, returns the removed path as the only element of
+/// an array.
+/// Each string must be passed back to rust and freed!
+#[no_mangle]
+pub extern "C" fn ffi_git_entry_remove(
+    store: c_int,
+    name: *const c_char,
+) -> CStringArray {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+    let Some(mut age_lock) = age_try_lock(store) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+    let name = unsafe { CStr::from_ptr(name).to_str() };
+
+    let Some(age_state) = age_state(&mut age_lock, store) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return CStringArray {
+            ptr: null(),
+            len: -1,
+        };
+    };
+    let Ok(name) = name else {
+        return CStringArray {
+            ptr: null(),
+            len: 0,
+        };
+    };
+
+    match git_entry_remove(&store, age_state, name) {
+        Ok(path) => cstring_array(vec![path]),
+        Err(err) => {
+            error!("{}", err);
+            *git_last_error = Some(err);
+            CStringArray {
+                ptr: null(),
+                len: -1,
+            }
+        }
+    }
+}
+
+/// Convert the store to encrypted filenames in one commit
+#[no_mangle]
+pub extern "C" fn ffi_git_entries_encrypt(store: c_int) -> c_int {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return KAGE_ERROR_LOCK_TAKEN as c_int;
+    };
+
+    let Some(store) = load_store(store, &mut git_last_error) else {
+        return -1;
+    };
+
+    git_call!(git_entries_encrypt(&store), git_last_error) as c_int
+}
+
+/// Return a dynamically allocated string describing the last error that
+/// occurred for `store`, errors from `ffi_git_setup()` are retrieved with a
+/// `store` of -1. The string must be passed back to rust and freed!
+/// The internal `last_error` is cleared after being retrieved!
+#[no_mangle]
+pub extern "C" fn ffi_git_strerror(store: c_int) -> *const c_char {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return null();
+    };
+    let Some(err) = git_last_error.as_ref() else {
+        return null();
     };
     let Ok(s) = CString::new(err.message()) else {
         return null();
@@ -214,3 +1540,39 @@ pub extern "C" fn ffi_git_strerror() -> *const c_char {
     *git_last_error = None;
     s.into_raw()
 }
+
+/// Look up the store for `handle`, an error is saved for `ffi_git_strerror()`
+/// if there is no such store.
+fn load_store(
+    handle: c_int,
+    git_last_error: &mut Option<git2::Error>,
+) -> Option<Store> {
+    let store = store_get(handle);
+    if store.is_none() {
+        *git_last_error = Some(git2::Error::from_str("Unknown store"));
+    }
+    store
+}
+
+/// Convert a vector of strings into a C array, the contents of the array need
+/// to be freed manually later!
+fn cstring_array(arr: Vec<String>) -> CStringArray {
+    let len = arr.len() as c_int;
+    let data = arr
+        .into_iter()
+        .map(|s| {
+            let Ok(cs) = CString::new(s) else {
+                return null();
+            };
+            cs.into_raw()
+        })
+        .collect::<Vec<_>>();
+
+    let ptr = data.as_ptr();
+
+    // Prevent the `data` vector from being deallocated when leaving
+    // scope, we need to free the contents of the array manually later!
+    std::mem::forget(data);
+
+    CStringArray { ptr, len }
+}
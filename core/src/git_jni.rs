@@ -1,18 +1,42 @@
 use jni::objects::{JClass, JObjectArray, JString};
-use jni::sys::{jint, jsize};
+use jni::sys::{jboolean, jint, jsize};
 use jni::JNIEnv;
 
+use crate::age::{age_state, age_try_lock};
+use crate::config::{config_get, Config};
 use crate::git::git_clone;
+use crate::git::git_configure;
+use crate::git::git_deepen;
 use crate::git::git_log;
+use crate::git::git_last_changed;
+use crate::git::{git_branch_create, git_branch_list, git_branch_switch};
+use crate::git::{git_entries, git_entry_path, git_entry_stage};
+use crate::git::{git_entry_remove, git_entry_rename};
+use crate::git::git_maintenance;
+use crate::git::git_stats;
 use crate::git::git_reset;
+use crate::git::git_discard;
 use crate::git::git_stage;
 use crate::git::git_commit;
+use crate::git::git_squash;
+use crate::git::git_amend;
 use crate::git::git_setup;
 use crate::git::git_try_lock;
 use crate::git::git_config_set_user;
+use crate::git::git_config_set_private_messages;
 use crate::git_call;
+use crate::store::{store_get, store_set_branch, STORE_NONE};
 use crate::KAGE_ERROR_LOCK_TAKEN;
 
+macro_rules! load_store {
+    ($handle:ident, $last_error:ident) => (
+        let Some($handle) = store_get($handle) else {
+            *$last_error = Some(git2::Error::from_str("Unknown store"));
+            return -1 as jint;
+        };
+    )
+}
+
 macro_rules! load_jstring {
     ($env:ident, $string:ident) => (
         let Ok($string) = $env.get_string(&$string) else {
@@ -24,92 +48,291 @@ macro_rules! load_jstring {
     )
 }
 
+#[no_mangle]
+pub extern "system" fn Java_one_kafva_kage_jni_Git_setup<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    remote: JString<'local>,
+    default_branch: JString<'local>,
+    email_domain: JString<'local>,
+    timeout_ms: jint,
+    connect_timeout_ms: jint,
+) -> jint {
+    let Some(mut git_last_error) = git_try_lock(STORE_NONE) else {
+        return KAGE_ERROR_LOCK_TAKEN as jint;
+    };
+
+    load_jstring!(env, remote);
+    load_jstring!(env, default_branch);
+    load_jstring!(env, email_domain);
+    let config = Config::new(
+        remote,
+        default_branch,
+        email_domain,
+        timeout_ms,
+        connect_timeout_ms
+    );
+    git_call!(git_configure(config), git_last_error) as jint
+}
+
+#[no_mangle]
+pub extern "system" fn Java_one_kafva_kage_jni_Git_config<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+) -> JObjectArray<'local> {
+    string_array(&mut env, config_get().to_vec())
+}
+
 #[no_mangle]
 pub extern "system" fn Java_one_kafva_kage_jni_Git_clone<'local>(
     mut env: JNIEnv<'local>,
     _class: JClass<'local>,
-    url: JString<'local>,
-    into: JString<'local>,
+    store: jint,
+    depth: jint,
+    sparse_dirs: JString<'local>,
 ) -> jint {
-    let Some(mut git_last_error) = git_try_lock() else {
+    let Some(mut git_last_error) = git_try_lock(store) else {
         return KAGE_ERROR_LOCK_TAKEN as jint;
     };
 
     git_setup();
 
-    load_jstring!(env, url);
-    load_jstring!(env, into);
-    git_call!(git_clone(url, into), git_last_error) as jint
+    load_store!(store, git_last_error);
+    load_jstring!(env, sparse_dirs);
+    let sparse_dirs: Vec<&str> = sparse_dirs.lines().collect();
+    git_call!(git_clone(&store, depth, &sparse_dirs), git_last_error) as jint
+}
+
+#[no_mangle]
+pub extern "system" fn Java_one_kafva_kage_jni_Git_deepen<'local>(
+    _env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    store: jint,
+    depth: jint,
+) -> jint {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return KAGE_ERROR_LOCK_TAKEN as jint;
+    };
+
+    git_setup();
+
+    load_store!(store, git_last_error);
+    git_call!(git_deepen(&store, depth), git_last_error) as jint
+}
+
+#[no_mangle]
+pub extern "system" fn Java_one_kafva_kage_jni_Git_branchList<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    store: jint,
+) -> JObjectArray<'local> {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return JObjectArray::default();
+    };
+
+    let Some(store) = store_get(store) else {
+        return JObjectArray::default();
+    };
+
+    match git_branch_list(&store) {
+        Ok(arr) => {
+            let arr = arr.iter().map(|b| b.to_string()).collect();
+            string_array(&mut env, arr)
+        }
+        Err(err) => {
+            error!("{}", err);
+            *git_last_error = Some(err);
+            JObjectArray::default()
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_one_kafva_kage_jni_Git_branchCreate<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    store: jint,
+    name: JString<'local>,
+) -> jint {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return KAGE_ERROR_LOCK_TAKEN as jint;
+    };
+
+    load_store!(store, git_last_error);
+    load_jstring!(env, name);
+    git_call!(git_branch_create(&store, name), git_last_error) as jint
+}
+
+#[no_mangle]
+pub extern "system" fn Java_one_kafva_kage_jni_Git_branchSwitch<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    store: jint,
+    name: JString<'local>,
+) -> jint {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return KAGE_ERROR_LOCK_TAKEN as jint;
+    };
+    git_setup();
+    let handle = store;
+
+    load_store!(store, git_last_error);
+    load_jstring!(env, name);
+    // Never leave the working tree on a branch the store does not push to
+    if store_set_branch(handle, name).is_none() {
+        return -1 as jint;
+    }
+    let r = git_call!(git_branch_switch(&store, name), git_last_error);
+    if r != 0 {
+        store_set_branch(handle, &store.branch);
+    }
+    r as jint
 }
 
 #[no_mangle]
 pub extern "system" fn Java_one_kafva_kage_jni_Git_setUser<'local>(
     mut env: JNIEnv<'local>,
     _class: JClass<'local>,
-    repo_path: JString<'local>,
+    store: jint,
     username: JString<'local>,
 ) -> jint {
-    let Some(mut git_last_error) = git_try_lock() else {
+    let Some(mut git_last_error) = git_try_lock(store) else {
         return KAGE_ERROR_LOCK_TAKEN as jint;
     };
 
-    load_jstring!(env, repo_path);
+    load_store!(store, git_last_error);
     load_jstring!(env, username);
-    git_call!(git_config_set_user(repo_path, username), git_last_error) as jint
+    git_call!(git_config_set_user(&store, username), git_last_error) as jint
+}
+
+#[no_mangle]
+pub extern "system" fn Java_one_kafva_kage_jni_Git_setPrivateMessages<'local>(
+    _env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    store: jint,
+    enabled: jboolean,
+) -> jint {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return KAGE_ERROR_LOCK_TAKEN as jint;
+    };
+
+    load_store!(store, git_last_error);
+    git_call!(
+        git_config_set_private_messages(&store, enabled != 0),
+        git_last_error
+    ) as jint
 }
 
 #[no_mangle]
 pub extern "system" fn Java_one_kafva_kage_jni_Git_stage<'local>(
     mut env: JNIEnv<'local>,
     _class: JClass<'local>,
-    repo_path: JString<'local>,
+    store: jint,
     relative_path: JString<'local>,
 ) -> jint {
-    let Some(mut git_last_error) = git_try_lock() else {
+    let Some(mut git_last_error) = git_try_lock(store) else {
         return KAGE_ERROR_LOCK_TAKEN as jint;
     };
 
-    load_jstring!(env, repo_path);
+    load_store!(store, git_last_error);
     load_jstring!(env, relative_path);
-    git_call!(git_stage(repo_path, relative_path), git_last_error) as jint
+    git_call!(git_stage(&store, relative_path), git_last_error) as jint
 }
 
 #[no_mangle]
 pub extern "system" fn Java_one_kafva_kage_jni_Git_reset<'local>(
-    mut env: JNIEnv<'local>,
+    _env: JNIEnv<'local>,
     _class: JClass<'local>,
-    repo_path: JString<'local>,
+    store: jint,
 ) -> jint {
-    let Some(mut git_last_error) = git_try_lock() else {
+    let Some(mut git_last_error) = git_try_lock(store) else {
         return KAGE_ERROR_LOCK_TAKEN as jint;
     };
 
-    load_jstring!(env, repo_path);
-    git_call!(git_reset(repo_path), git_last_error) as jint
+    load_store!(store, git_last_error);
+    git_call!(git_reset(&store), git_last_error) as jint
+}
+
+#[no_mangle]
+pub extern "system" fn Java_one_kafva_kage_jni_Git_discard<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    store: jint,
+    dry_run: jboolean,
+) -> JObjectArray<'local> {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return JObjectArray::default();
+    };
+
+    let Some(store) = store_get(store) else {
+        return JObjectArray::default();
+    };
+
+    match git_discard(&store, dry_run != 0) {
+        Ok(arr) => string_array(&mut env, arr),
+        Err(err) => {
+            error!("{}", err);
+            *git_last_error = Some(err);
+            JObjectArray::default()
+        }
+    }
 }
 
 #[no_mangle]
 pub extern "system" fn Java_one_kafva_kage_jni_Git_commit<'local>(
     mut env: JNIEnv<'local>,
     _class: JClass<'local>,
-    repo_path: JString<'local>,
+    store: jint,
+    message: JString<'local>,
+) -> jint {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return KAGE_ERROR_LOCK_TAKEN as jint;
+    };
+
+    load_store!(store, git_last_error);
+    load_jstring!(env, message);
+    git_call!(git_commit(&store, message), git_last_error) as jint
+}
+
+#[no_mangle]
+pub extern "system" fn Java_one_kafva_kage_jni_Git_squash<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    store: jint,
+    message: JString<'local>,
+) -> jint {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return KAGE_ERROR_LOCK_TAKEN as jint;
+    };
+
+    load_store!(store, git_last_error);
+    load_jstring!(env, message);
+    git_call!(git_squash(&store, message), git_last_error) as jint
+}
+
+#[no_mangle]
+pub extern "system" fn Java_one_kafva_kage_jni_Git_amend<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    store: jint,
     message: JString<'local>,
 ) -> jint {
-    let Some(mut git_last_error) = git_try_lock() else {
+    let Some(mut git_last_error) = git_try_lock(store) else {
         return KAGE_ERROR_LOCK_TAKEN as jint;
     };
 
-    load_jstring!(env, repo_path);
+    load_store!(store, git_last_error);
     load_jstring!(env, message);
-    git_call!(git_commit(repo_path, message), git_last_error) as jint
+    git_call!(git_amend(&store, message), git_last_error) as jint
 }
 
 #[no_mangle]
 pub extern "system" fn Java_one_kafva_kage_jni_Git_strerror<'local>(
     env: JNIEnv<'local>,
     _class: JClass<'local>,
+    store: jint,
 ) -> JString<'local> {
-    let Some(git_last_error) = git_try_lock() else {
+    let Some(git_last_error) = git_try_lock(store) else {
         return JString::default();
     };
     let Some(err) = git_last_error.as_ref() else {
@@ -127,50 +350,331 @@ pub extern "system" fn Java_one_kafva_kage_jni_Git_strerror<'local>(
 pub extern "system" fn Java_one_kafva_kage_jni_Git_log<'local>(
     mut env: JNIEnv<'local>,
     _class: JClass<'local>,
-    repo_path: JString<'local>,
+    store: jint,
 ) -> JObjectArray<'local> {
-    let Some(_git_last_error) = git_try_lock() else {
+    let Some(_git_last_error) = git_try_lock(store) else {
+        return JObjectArray::default();
+    };
+    let Some(mut age_lock) = age_try_lock(store) else {
+        return JObjectArray::default();
+    };
+
+    let Some(age_state) = age_state(&mut age_lock, store) else {
+        return JObjectArray::default();
+    };
+    let Some(store) = store_get(store) else {
+        return JObjectArray::default();
+    };
+
+    match git_log(&store, age_state) {
+        Ok(arr) => string_array(&mut env, arr),
+        Err(err) => {
+            error!("{}", err);
+            return JObjectArray::default();
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_one_kafva_kage_jni_Git_entries<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    store: jint,
+    query: JString<'local>,
+) -> JObjectArray<'local> {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return JObjectArray::default();
+    };
+    let Some(mut age_lock) = age_try_lock(store) else {
         return JObjectArray::default();
     };
 
-    let Ok(repo_path) = env.get_string(&repo_path) else {
+    let Some(age_state) = age_state(&mut age_lock, store) else {
+        return JObjectArray::default();
+    };
+    let Some(store) = store_get(store) else {
+        return JObjectArray::default();
+    };
+    let Ok(query) = env.get_string(&query) else {
         return JObjectArray::default();
     };
-    let Ok(repo_path) = repo_path.to_str() else {
+    let Ok(query) = query.to_str() else {
         return JObjectArray::default();
     };
 
-    match git_log(repo_path) {
+    match git_entries(&store, age_state, query) {
         Ok(arr) => {
-            let size = arr.len() as jsize;
-
-            let Ok(initial_value) = env.new_string("") else {
-                error!("Error creating empty Java string");
-                return JObjectArray::default();
-            };
-            let Ok(outarr) = env.new_object_array(size, "java/lang/String", initial_value)
-            else {
-                error!("Error creating Java object array");
-                return JObjectArray::default();
-            };
-
-            for (i,item) in arr.into_iter().enumerate() {
-                let Ok(s) = env.new_string(&item) else {
-                    error!("Error creating Java string from: '{}'", item);
-                    return JObjectArray::default();
-                };
-
-                let Ok(_) = env.set_object_array_element(&outarr, i as i32, s) else {
-                    error!("Error adding Java string to array: '{}'", item);
-                    return JObjectArray::default();
-                };
-            }
-
-            outarr
+            let arr = arr.iter().map(|e| e.to_string()).collect();
+            string_array(&mut env, arr)
         }
         Err(err) => {
             error!("{}", err);
-            return JObjectArray::default();
+            *git_last_error = Some(err);
+            JObjectArray::default()
         }
     }
 }
+
+#[no_mangle]
+pub extern "system" fn Java_one_kafva_kage_jni_Git_entryPath<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    store: jint,
+    name: JString<'local>,
+) -> JObjectArray<'local> {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return JObjectArray::default();
+    };
+    let Some(mut age_lock) = age_try_lock(store) else {
+        return JObjectArray::default();
+    };
+
+    let Some(age_state) = age_state(&mut age_lock, store) else {
+        return JObjectArray::default();
+    };
+    let Some(store) = store_get(store) else {
+        return JObjectArray::default();
+    };
+    let Ok(name) = env.get_string(&name) else {
+        return JObjectArray::default();
+    };
+    let Ok(name) = name.to_str() else {
+        return JObjectArray::default();
+    };
+
+    match git_entry_path(&store, age_state, name) {
+        Ok(path) => string_array(&mut env, vec![path]),
+        Err(err) => {
+            error!("{}", err);
+            *git_last_error = Some(err);
+            JObjectArray::default()
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_one_kafva_kage_jni_Git_entryStage<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    store: jint,
+    name: JString<'local>,
+) -> jint {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return KAGE_ERROR_LOCK_TAKEN as jint;
+    };
+    let Some(mut age_lock) = age_try_lock(store) else {
+        return KAGE_ERROR_LOCK_TAKEN as jint;
+    };
+
+    let Some(age_state) = age_state(&mut age_lock, store) else {
+        return -1;
+    };
+    load_store!(store, git_last_error);
+    load_jstring!(env, name);
+    git_call!(git_entry_stage(&store, age_state, name), git_last_error)
+        as jint
+}
+
+#[no_mangle]
+pub extern "system" fn Java_one_kafva_kage_jni_Git_entryRename<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    store: jint,
+    old_name: JString<'local>,
+    new_name: JString<'local>,
+) -> JObjectArray<'local> {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return JObjectArray::default();
+    };
+    let Some(mut age_lock) = age_try_lock(store) else {
+        return JObjectArray::default();
+    };
+
+    let Some(age_state) = age_state(&mut age_lock, store) else {
+        return JObjectArray::default();
+    };
+    let Some(store) = store_get(store) else {
+        return JObjectArray::default();
+    };
+    let Ok(old_name) = env.get_string(&old_name) else {
+        return JObjectArray::default();
+    };
+    let Ok(old_name) = old_name.to_str() else {
+        return JObjectArray::default();
+    };
+    let Ok(new_name) = env.get_string(&new_name) else {
+        return JObjectArray::default();
+    };
+    let Ok(new_name) = new_name.to_str() else {
+        return JObjectArray::default();
+    };
+
+    match git_entry_rename(&store, age_state, old_name, new_name) {
+        Ok((old_path, new_path)) => {
+            string_array(&mut env, vec![old_path, new_path])
+        }
+        Err(err) => {
+            error!("{}", err);
+            *git_last_error = Some(err);
+            JObjectArray::default()
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_one_kafva_kage_jni_Git_entryRemove<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    store: jint,
+    name: JString<'local>,
+) -> JObjectArray<'local> {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return JObjectArray::default();
+    };
+    let Some(mut age_lock) = age_try_lock(store) else {
+        return JObjectArray::default();
+    };
+
+    let Some(age_state) = age_state(&mut age_lock, store) else {
+        return JObjectArray::default();
+    };
+    let Some(store) = store_get(store) else {
+        return JObjectArray::default();
+    };
+    let Ok(name) = env.get_string(&name) else {
+        return JObjectArray::default();
+    };
+    let Ok(name) = name.to_str() else {
+        return JObjectArray::default();
+    };
+
+    match git_entry_remove(&store, age_state, name) {
+        Ok(path) => string_array(&mut env, vec![path]),
+        Err(err) => {
+            error!("{}", err);
+            *git_last_error = Some(err);
+            JObjectArray::default()
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_one_kafva_kage_jni_Git_lastChanged<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    store: jint,
+    path: JString<'local>,
+) -> JObjectArray<'local> {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return JObjectArray::default();
+    };
+    let Some(mut age_lock) = age_try_lock(store) else {
+        return JObjectArray::default();
+    };
+
+    let Some(age_state) = age_state(&mut age_lock, store) else {
+        return JObjectArray::default();
+    };
+    let Some(store) = store_get(store) else {
+        return JObjectArray::default();
+    };
+    let Ok(path) = env.get_string(&path) else {
+        return JObjectArray::default();
+    };
+    let Ok(path) = path.to_str() else {
+        return JObjectArray::default();
+    };
+
+    match git_last_changed(&store, age_state, path) {
+        Ok(arr) => {
+            let arr = arr.iter().map(|c| c.to_string()).collect();
+            string_array(&mut env, arr)
+        }
+        Err(err) => {
+            error!("{}", err);
+            *git_last_error = Some(err);
+            JObjectArray::default()
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_one_kafva_kage_jni_Git_stats<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    store: jint,
+) -> JObjectArray<'local> {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return JObjectArray::default();
+    };
+
+    let Some(store) = store_get(store) else {
+        return JObjectArray::default();
+    };
+
+    match git_stats(&store) {
+        Ok(stats) => string_array(&mut env, stats.to_vec()),
+        Err(err) => {
+            error!("{}", err);
+            *git_last_error = Some(err);
+            JObjectArray::default()
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_one_kafva_kage_jni_Git_maintenance<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    store: jint,
+    grace_period_s: jint,
+) -> JObjectArray<'local> {
+    let Some(mut git_last_error) = git_try_lock(store) else {
+        return JObjectArray::default();
+    };
+
+    let Some(store) = store_get(store) else {
+        return JObjectArray::default();
+    };
+
+    match git_maintenance(&store, grace_period_s as i64) {
+        Ok(stats) => string_array(&mut env, stats.to_vec()),
+        Err(err) => {
+            error!("{}", err);
+            *git_last_error = Some(err);
+            JObjectArray::default()
+        }
+    }
+}
+
+/// Convert a vector of strings into a Java string array
+fn string_array<'local>(
+    env: &mut JNIEnv<'local>,
+    arr: Vec<String>,
+) -> JObjectArray<'local> {
+    let size = arr.len() as jsize;
+
+    let Ok(initial_value) = env.new_string("") else {
+        error!("Error creating empty Java string");
+        return JObjectArray::default();
+    };
+    let Ok(outarr) = env.new_object_array(size, "java/lang/String", initial_value)
+    else {
+        error!("Error creating Java object array");
+        return JObjectArray::default();
+    };
+
+    for (i,item) in arr.into_iter().enumerate() {
+        let Ok(s) = env.new_string(&item) else {
+            error!("Error creating Java string from: '{}'", item);
+            return JObjectArray::default();
+        };
+
+        let Ok(_) = env.set_object_array_element(&outarr, i as i32, s) else {
+            error!("Error adding Java string to array: '{}'", item);
+            return JObjectArray::default();
+        };
+    }
+
+    outarr
+}
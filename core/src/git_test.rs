@@ -1,8 +1,13 @@
 use super::*;
+use crate::age::AgeState;
+use crate::config::Config;
 use crate::error;
 use crate::git::*;
+use crate::store::Store;
 use std::fs;
+use std::path::Path;
 use std::process::Command;
+use std::sync::Once;
 
 const GIT_USERNAME: &'static str = env!("KAGE_TEST_GIT_REPONAME");
 const GIT_REMOTE_CLONE_URL: &'static str =
@@ -14,77 +19,714 @@ const GIT_CLIENT_DIR: &'static str = env!("KAGE_TEST_GIT_CLIENT_DIR");
 #[test]
 /// Test that we can add, commit and push a new file and changes to it
 fn git_commit_file_test() {
-    git_setup();
+    configure();
     let remote_path = &format!("{}/commit_file_test.git", GIT_REMOTE_CLONE_URL);
     let repo_path = &format!("{}/commit_file_test", GIT_CLIENT_DIR);
     let now = current_time();
     let filename = &format!("file-{}", now);
     let file_path = format!("{}/{}", repo_path, filename);
 
-    clone(remote_path, repo_path);
+    let store = &clone(remote_path, repo_path);
 
     fs::write(&file_path, "Content").expect("write file failed");
 
     // Commit the file
-    assert_ok(git_stage(repo_path, &filename));
-    assert_ok(git_commit(repo_path, &format!("Add '{}'", filename)));
-    assert_ok(git_push(repo_path));
+    assert_ok(git_stage(store, &filename));
+    assert_ok(git_commit(store, &format!("Add '{}'", filename)));
+    assert_ok(git_push(store));
 
     fs::write(&file_path, "Modified").expect("write file failed");
 
-    assert_ok(git_stage(repo_path, &filename));
-    assert_ok(git_commit(repo_path, &format!("Modified '{}'", filename)));
-    assert_ok(git_push(repo_path));
+    assert_ok(git_stage(store, &filename));
+    assert_ok(git_commit(store, &format!("Modified '{}'", filename)));
+    assert_ok(git_push(store));
+}
+
+#[test]
+/// Test that pushes reach all mirrors and that a failing mirror does not fail
+/// the push
+fn git_push_mirror_test() {
+    configure();
+    let remote_path = &format!("{}/push_mirror_test.git", GIT_REMOTE_CLONE_URL);
+    let repo_path = &format!("{}/push_mirror_test", GIT_CLIENT_DIR);
+    let now = current_time();
+    let mirror_path = &format!("/tmp/.push_mirror_test-{}.git", now);
+    let missing_path = &format!("/tmp/.push_mirror_test-{}-missing.git", now);
+    let filename = &format!("file-{}", now);
+
+    let store = &clone(remote_path, repo_path);
+
+    let status = Command::new("git")
+        .arg("init")
+        .arg("-q")
+        .arg("--bare")
+        .arg(mirror_path)
+        .status()
+        .expect("command failed");
+    assert!(status.success());
+
+    assert_ok(git_mirror_add(store, "backup", mirror_path));
+    assert_ok(git_mirror_add(store, "missing", missing_path));
+
+    let remotes = assert_ok(git_remote_list(store));
+    assert_eq!(remotes.len(), 3);
+    assert!(remotes[0].primary);
+    assert_eq!(remotes[0].name, test_config().remote);
+    assert!(!remotes[1].primary);
+    assert_eq!(remotes[1].url, *mirror_path);
+
+    fs::write(format!("{}/{}", repo_path, filename), "Content")
+        .expect("write file failed");
+    assert_ok(git_stage(store, filename));
+    assert_ok(git_commit(store, "Test commit"));
+
+    // Only the unreachable mirror fails
+    let results = assert_ok(git_push(store));
+    assert_eq!(results.len(), 3);
+    assert!(results[0].error.is_none());
+    assert_eq!(results[1].remote, "backup");
+    assert!(results[1].error.is_none());
+    assert_eq!(results[2].remote, "missing");
+    assert!(results[2].error.is_some());
+
+    // The mirror has the same head as the primary
+    let mirror = git2::Repository::open(mirror_path).expect("open failed");
+    let mirror_head = mirror
+        .revparse_single(&test_config().default_branch)
+        .expect("revparse failed")
+        .id();
+    assert_eq!(mirror_head.to_string(), head_oid(repo_path));
+
+    // The primary remote is not a mirror
+    assert_err(git_mirror_remove(store, &test_config().remote));
+    assert_ok(git_mirror_remove(store, "missing"));
+    assert_eq!(assert_ok(git_push(store)).len(), 2);
+
+    rm_rf(mirror_path);
+}
+
+#[test]
+/// Test that a new store can be initialized and pushed to an empty remote
+fn git_init_test() {
+    configure();
+    let now = current_time();
+    let remote_path = &format!("/tmp/.init_test-{}.git", now);
+    let repo_path = &format!("/tmp/.init_test-{}", now);
+    let passphrase = "Passphrase";
+
+    let status = Command::new("git")
+        .arg("init")
+        .arg("-q")
+        .arg("--bare")
+        .arg(remote_path)
+        .status()
+        .expect("command failed");
+    assert!(status.success());
+
+    // Nothing is created without an identity path
+    let no_identity = &Store::new(repo_path, remote_path, "", "");
+    assert_err(git_init(no_identity, passphrase, GIT_USERNAME));
+    assert!(!Path::new(repo_path).exists());
+
+    let store = &test_store(remote_path, repo_path);
+    let recipient = assert_ok(git_init(store, passphrase, GIT_USERNAME));
+    assert!(recipient.starts_with("age1"));
+
+    // Both key files are part of the initial commit
+    assert!(tree_has_path(repo_path, ".age-identities"));
+    assert!(tree_has_path(repo_path, ".age-recipients"));
+    let recipients =
+        fs::read_to_string(format!("{}/.age-recipients", repo_path))
+            .expect("read file failed");
+    assert_eq!(recipients.trim(), recipient);
+
+    // The identity can only be unlocked with the passphrase
+    let identity =
+        fs::read_to_string(&store.identity_path).expect("read file failed");
+    let mut age_state = AgeState::default();
+    assert!(age_state.unlock_identity(&identity, "Wrong").is_err());
+    assert!(age_state.unlock_identity(&identity, passphrase).is_ok());
+
+    // The initial commit was pushed
+    let remote = git2::Repository::open(remote_path).expect("open failed");
+    let remote_ref = format!("refs/heads/{}", store.branch);
+    let remote_head = remote.refname_to_id(&remote_ref).expect("no ref");
+    assert_eq!(remote_head.to_string(), head_oid(repo_path));
+    assert!(assert_ok(git_local_head_matches_remote(store)));
+
+    // Subsequent commits have the initial commit as parent
+    fs::write(format!("{}/file", repo_path), "Content")
+        .expect("write file failed");
+    assert_ok(git_stage(store, "file"));
+    assert_ok(git_commit(store, "Add file"));
+    assert_ok(git_push(store));
+
+    // An existing repository is never overwritten
+    assert_err(git_init(store, passphrase, GIT_USERNAME));
+
+    rm_rf(repo_path);
+    rm_rf(remote_path);
+}
+
+#[test]
+/// Test that branches can be created, switched to and pushed
+fn git_branch_test() {
+    configure();
+    let remote_path = &format!("{}/branch_test.git", GIT_REMOTE_CLONE_URL);
+    let repo_path = &format!("{}/branch_test", GIT_CLIENT_DIR);
+    let other_client_path = &format!("{}/branch_test_other", GIT_CLIENT_DIR);
+    let now = current_time();
+    let branch = &format!("staging-{}", now);
+    let filename = &format!("file-{}", now);
+    let main = &test_config().default_branch;
+
+    let store = &clone(remote_path, repo_path);
+    let branches = assert_ok(git_branch_list(store));
+    let active: Vec<&GitBranch> =
+        branches.iter().filter(|b| b.active).collect();
+    assert_eq!(active.len(), 1);
+    assert_eq!(&active[0].name, main);
+    assert!(active[0].local && active[0].remote);
+
+    assert_ok(git_branch_create(store, branch));
+    assert_err(git_branch_create(store, branch));
+    assert_err(git_branch_create(store, "bad..name"));
+    let branches = assert_ok(git_branch_list(store));
+    let created = branches.iter().find(|b| &b.name == branch).unwrap();
+    assert_eq!(created.to_string(), format!(" L-\n{}", branch));
+
+    // Pulls and pushes use the branch of the store
+    assert_ok(git_branch_switch(store, branch));
+    // Stores registered without a branch use the checked out branch
+    let identity_path = format!("{}/.age-identities", repo_path);
+    let staging = &Store::new(repo_path, remote_path, "", &identity_path);
+    assert_eq!(&staging.branch, branch);
+    // Nothing to pull before the branch has been pushed
+    let preview = assert_ok(git_fetch(staging, &AgeState::default()));
+    assert!(preview.commits.is_empty());
+    let summary = assert_ok(git_pull(staging, &AgeState::default()));
+    assert_eq!(summary.old_head, summary.new_head);
+    fs::write(format!("{}/{}", repo_path, filename), "Content")
+        .expect("write file failed");
+    assert_ok(git_stage(staging, filename));
+    assert_ok(git_commit(staging, "Staging only"));
+    assert_ok(git_push(staging));
+    assert!(assert_ok(git_local_head_matches_remote(staging)));
+
+    // Uncommitted changes block switching
+    fs::write(format!("{}/{}", repo_path, filename), "Changed")
+        .expect("write file failed");
+    assert_err(git_branch_switch(staging, main));
+    assert_ok(git_reset(staging));
+
+    assert_ok(git_branch_switch(staging, main));
+    assert!(!Path::new(&format!("{}/{}", repo_path, filename)).exists());
+
+    // Other clients track the remote branch when switching to it
+    let other = &clone(remote_path, other_client_path);
+    let branches = assert_ok(git_branch_list(other));
+    let remote_only = branches.iter().find(|b| &b.name == branch).unwrap();
+    assert!(!remote_only.local && remote_only.remote);
+    assert_ok(git_branch_switch(other, branch));
+    let content =
+        fs::read_to_string(format!("{}/{}", other_client_path, filename))
+            .expect("read file failed");
+    assert_eq!(content, "Content");
+    assert_err(git_branch_switch(other, "missing"));
+
+    // Remove the branch from the shared remote
+    let status = Command::new("git")
+        .arg("-C")
+        .arg(repo_path)
+        .arg("push")
+        .arg("-q")
+        .arg("origin")
+        .arg("--delete")
+        .arg(branch)
+        .status()
+        .expect("command failed");
+    assert!(status.success());
+}
+
+#[test]
+/// Test that unpushed commits can be squashed and amended, but not pushed ones
+fn git_squash_test() {
+    configure();
+    let remote_path = &format!("{}/squash_test.git", GIT_REMOTE_CLONE_URL);
+    let repo_path = &format!("{}/squash_test", GIT_CLIENT_DIR);
+    let now = current_time();
+    let filename = &format!("file-{}", now);
+    let file_path = format!("{}/{}", repo_path, filename);
+
+    let store = &clone(remote_path, repo_path);
+    let remote_head = head_oid(repo_path);
+
+    // Nothing unpushed yet
+    assert_err(git_squash(store, ""));
+    assert_err(git_amend(store, "Amended"));
+
+    for i in 0..3 {
+        fs::write(&file_path, format!("{}", i)).expect("write file failed");
+        assert_ok(git_stage(store, filename));
+        assert_ok(git_commit(store, &format!("Edit {}", i)));
+    }
+    assert_eq!(assert_ok(git_squash(store, "")), 3);
+
+    let repo = git2::Repository::open(repo_path).expect("open failed");
+    let head = repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head.parent_id(0).unwrap().to_string(), remote_head);
+    assert_eq!(head.message(), Some("Edit 0\n\nEdit 1\n\nEdit 2"));
+    assert_eq!(fs::read_to_string(&file_path).unwrap(), "2");
+    assert!(assert_ok(git_status(store, &AgeState::default())).is_empty());
+
+    // Amend the squashed commit with a new change and message
+    fs::write(&file_path, "Amended").expect("write file failed");
+    assert_ok(git_stage(store, filename));
+    assert_ok(git_amend(store, "Amended"));
+
+    let head = repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head.parent_id(0).unwrap().to_string(), remote_head);
+    assert_eq!(head.message(), Some("Amended"));
+    let blob = head.tree().unwrap().get_path(Path::new(filename)).unwrap();
+    let blob = repo.find_blob(blob.id()).unwrap();
+    assert_eq!(blob.content(), b"Amended");
+
+    // Pushed commits are never rewritten
+    assert_ok(git_push(store));
+    assert_err(git_squash(store, "Squashed"));
+    assert_err(git_amend(store, "Amended again"));
+    assert!(assert_ok(git_local_head_matches_remote(store)));
+}
+
+#[test]
+/// Test that an empty commit message is generated from the staged changes
+fn git_commit_message_test() {
+    configure();
+    let remote_path =
+        &format!("{}/commit_message_test.git", GIT_REMOTE_CLONE_URL);
+    let repo_path = &format!("{}/commit_message_test", GIT_CLIENT_DIR);
+    let now = current_time();
+    let dirname = &format!("dir-{}", now);
+    let dir_path = format!("{}/{}", repo_path, dirname);
+
+    let store = &clone(remote_path, repo_path);
+    let repo = git2::Repository::open(repo_path).expect("open failed");
+    let head_message = || {
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        head.message().unwrap_or_default().to_string()
+    };
+
+    fs::create_dir_all(&dir_path).expect("create dir failed");
+    fs::write(format!("{}/a.age", dir_path), "a").expect("write file failed");
+    assert_ok(git_stage(store, dirname));
+    assert_ok(git_commit(store, ""));
+    assert_eq!(
+        head_message(),
+        format!("Add '{0}/a'\n\nAdd '{0}/a'", dirname)
+    );
+
+    fs::write(format!("{}/a.age", dir_path), "b").expect("write file failed");
+    assert_ok(git_stage(store, dirname));
+    assert_ok(git_commit(store, ""));
+    assert_eq!(
+        head_message(),
+        format!("Update '{0}/a'\n\nUpdate '{0}/a'", dirname)
+    );
+
+    fs::rename(format!("{}/a.age", dir_path), format!("{}/b.age", dir_path))
+        .expect("rename failed");
+    assert_ok(git_stage(store, dirname));
+    assert_ok(git_commit(store, ""));
+    assert_eq!(
+        head_message(),
+        format!(
+            "Rename '{0}/a' to '{0}/b'\n\nRename '{0}/a' to '{0}/b'",
+            dirname
+        )
+    );
+
+    // Several changes are summarised with one line per entry in the body
+    fs::remove_file(format!("{}/b.age", dir_path)).expect("remove failed");
+    fs::write(format!("{}/c.age", dir_path), "c").expect("write file failed");
+    fs::write(format!("{}/d.age", dir_path), "d").expect("write file failed");
+    assert_ok(git_stage(store, dirname));
+    assert_ok(git_commit(store, ""));
+    assert_eq!(
+        head_message(),
+        format!(
+            "Update 3 entries\n\nRemove '{0}/b'\nAdd '{0}/c'\nAdd '{0}/d'",
+            dirname
+        )
+    );
+
+    // An explicit message is used as is
+    fs::remove_file(format!("{}/c.age", dir_path)).expect("remove failed");
+    assert_ok(git_stage(store, dirname));
+    assert_ok(git_commit(store, "Custom message"));
+    assert_eq!(head_message(), "Custom message");
+
+    assert_ok(git_push(store));
+}
+
+#[test]
+/// Test that a store can be converted to encrypted filenames and that entries
+/// can be managed through their real names afterwards
+fn git_entries_test() {
+    configure();
+    let now = current_time();
+    let remote_path = &format!("/tmp/.entries_test-{}.git", now);
+    let repo_path = &format!("/tmp/.entries_test-{}", now);
+    let passphrase = "Passphrase";
+    let locked = &AgeState::default();
+
+    let status = Command::new("git")
+        .arg("init")
+        .arg("-q")
+        .arg("--bare")
+        .arg(remote_path)
+        .status()
+        .expect("command failed");
+    assert!(status.success());
+
+    let store = &test_store(remote_path, repo_path);
+    assert_ok(git_init(store, passphrase, GIT_USERNAME));
+    let identity =
+        fs::read_to_string(&store.identity_path).expect("read file failed");
+    let mut age_state = AgeState::default();
+    assert!(age_state.unlock_identity(&identity, passphrase).is_ok());
+    let age_state = &age_state;
+
+    for name in ["web/github", "web/gitlab/otp", "work/aws-root"] {
+        let path = assert_ok(git_entry_path(store, locked, name));
+        assert_eq!(path, format!("{}.age", name));
+        let path = Path::new(repo_path).join(path);
+        fs::create_dir_all(path.parent().unwrap()).expect("create dir failed");
+        fs::write(path, name).expect("write file failed");
+    }
+    assert_ok(git_stage(store, "web"));
+    assert_ok(git_stage(store, "work"));
+    assert_ok(git_commit(store, ""));
+
+    // Entries are listed from the working tree in ordinary stores
+    let entries = assert_ok(git_entries(store, locked, "GIT"));
+    let names: Vec<&str> = entries.iter().map(|e| e.name.as_str()).collect();
+    assert_eq!(names, ["web/github", "web/gitlab/otp"]);
+
+    assert_eq!(assert_ok(git_entries_encrypt(store)), 3);
+    assert_err(git_entries_encrypt(store));
+    assert!(!tree_has_path(repo_path, "web"));
+    assert!(!tree_has_path(repo_path, "work"));
+    assert!(tree_has_path(repo_path, ".age-index"));
+    assert!(assert_ok(git_status(store, locked)).is_empty());
+
+    // The real names are only available with an unlocked identity
+    assert_err(git_entries(store, locked, ""));
+    let index = fs::read_to_string(format!("{}/.age-index", repo_path))
+        .expect("read file failed");
+    assert!(!index.contains("github"));
+
+    let entries = assert_ok(git_entries(store, age_state, ""));
+    assert_eq!(entries.len(), 3);
+    for entry in &entries {
+        assert!(!entry.path.contains('/'));
+        assert!(tree_has_path(repo_path, &entry.path));
+        let content =
+            fs::read_to_string(format!("{}/{}", repo_path, entry.path))
+                .expect("read file failed");
+        assert_eq!(content, entry.name);
+    }
+    assert!(entries[1].path.ends_with("-otp.age"));
+
+    // New entries are only added to the index once they are staged
+    let path = assert_ok(git_entry_path(store, age_state, "web/new"));
+    assert_eq!(assert_ok(git_entry_path(store, age_state, "web/new")), path);
+    assert_err(git_entry_stage(store, age_state, "web/new"));
+    assert!(assert_ok(git_entries(store, age_state, "new")).is_empty());
+    fs::write(format!("{}/{}", repo_path, path), "new")
+        .expect("write file failed");
+    assert_ok(git_entry_stage(store, age_state, "web/new"));
+    let status = assert_ok(git_status(store, age_state));
+    assert!(status.iter().any(|e| e.path == "web/new.age"));
+    assert!(!status.iter().any(|e| e.path == path));
+    let status = assert_ok(git_status(store, locked));
+    assert!(status.iter().any(|e| e.path == path));
+    assert_ok(git_commit(store, ""));
+    assert!(assert_ok(git_status(store, locked)).is_empty());
+    let log = assert_ok(git_log(store, age_state));
+    assert!(log[0].ends_with("\nAdd 'web/new'"));
+    assert!(!assert_ok(git_log(store, locked))[0].contains("web/new"));
+
+    let changes = assert_ok(git_last_changed(store, age_state, "web"));
+    let paths: Vec<&str> = changes.iter().map(|c| c.path.as_str()).collect();
+    assert_eq!(paths.len(), 3);
+    assert!(paths.contains(&"web/new.age"));
+    assert!(assert_ok(git_last_changed(store, locked, "web")).is_empty());
+
+    let (old_path, new_path) =
+        assert_ok(git_entry_rename(store, age_state, "web/new", "web/renamed"));
+    assert_err(git_entry_rename(store, age_state, "web/new", "web/other"));
+    assert_ok(git_stage(store, &old_path));
+    assert_ok(git_stage(store, &new_path));
+    assert_ok(git_commit(store, ""));
+    let log = assert_ok(git_log(store, age_state));
+    assert!(log[0].ends_with("\nRename 'web/new' to 'web/renamed'"));
+
+    let path = assert_ok(git_entry_remove(store, age_state, "web/renamed"));
+    assert_err(git_entry_remove(store, age_state, "web/renamed"));
+    assert_ok(git_stage(store, &path));
+    assert_ok(git_commit(store, ""));
+    assert!(!tree_has_path(repo_path, &path));
+    let log = assert_ok(git_log(store, age_state));
+    assert!(log[0].ends_with("\nRemove 'web/renamed'"));
+
+    let entries = assert_ok(git_entries(store, age_state, "aws"));
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].name, "work/aws-root");
+
+    assert_ok(git_push(store));
+
+    // Incoming changes are shown with the real names
+    let other_path = &format!("/tmp/.entries_test-other-{}", now);
+    let other = &clone(remote_path, other_path);
+    let path = assert_ok(git_entry_path(store, age_state, "web/pulled"));
+    fs::write(format!("{}/{}", repo_path, path), "pulled")
+        .expect("write file failed");
+    assert_ok(git_entry_stage(store, age_state, "web/pulled"));
+    let path = assert_ok(git_entry_remove(store, age_state, "work/aws-root"));
+    assert_ok(git_stage(store, &path));
+    assert_ok(git_commit(store, ""));
+    assert_ok(git_push(store));
+
+    let preview = assert_ok(git_fetch(other, locked));
+    assert!(!preview
+        .changes
+        .added
+        .contains(&"web/pulled.age".to_string()));
+    let preview = assert_ok(git_fetch(other, age_state));
+    assert_eq!(preview.changes.added, ["web/pulled.age"]);
+    assert_eq!(preview.changes.deleted, ["work/aws-root.age"]);
+    let summary = assert_ok(git_pull(other, age_state));
+    assert_eq!(summary.added, ["web/pulled.age"]);
+    assert_eq!(summary.deleted, ["work/aws-root.age"]);
+
+    rm_rf(other_path);
+    rm_rf(repo_path);
+    rm_rf(remote_path);
+}
+
+#[test]
+/// Test that private commit messages only reveal the number of changed entries
+fn git_private_messages_test() {
+    configure();
+    let remote_path =
+        &format!("{}/private_messages_test.git", GIT_REMOTE_CLONE_URL);
+    let repo_path = &format!("{}/private_messages_test", GIT_CLIENT_DIR);
+    let now = current_time();
+    let dirname = &format!("dir-{}", now);
+    let dir_path = format!("{}/{}", repo_path, dirname);
+
+    let identity = ::age::x25519::Identity::generate();
+    let recipient = identity.to_public().to_string();
+    let age_state = AgeState {
+        identity: Some(identity),
+        last_error: None,
+    };
+
+    let store = &clone(remote_path, repo_path);
+    fs::write(format!("{}/.age-recipients", repo_path), &recipient)
+        .expect("write file failed");
+    assert_ok(git_config_set_private_messages(store, true));
+
+    fs::create_dir_all(&dir_path).expect("create dir failed");
+    fs::write(format!("{}/a.age", dir_path), "a").expect("write file failed");
+    fs::write(format!("{}/b.age", dir_path), "b").expect("write file failed");
+    assert_ok(git_stage(store, dirname));
+    assert_ok(git_commit(store, ""));
+
+    fs::write(format!("{}/a.age", dir_path), "c").expect("write file failed");
+    assert_ok(git_stage(store, dirname));
+    assert_ok(git_commit(store, "Custom message"));
+
+    // The entry names are not part of the stored commit messages
+    let repo = git2::Repository::open(repo_path).expect("open failed");
+    let head = repo.head().unwrap().peel_to_commit().unwrap();
+    let parent = head.parent(0).unwrap();
+    assert_eq!(head.summary(), Some("Update 1 entry"));
+    assert_eq!(parent.summary(), Some("Update 2 entries"));
+    for commit in [&head, &parent] {
+        let message = commit.message().unwrap();
+        assert!(!message.contains(dirname));
+        assert!(!message.contains("Custom message"));
+    }
+
+    // The real summaries are shown once the identity is unlocked
+    let log = assert_ok(git_log(store, &age_state));
+    assert!(log[0].ends_with("\nCustom message"));
+    assert!(log[1].ends_with("\nUpdate 2 entries"));
+    let log = assert_ok(git_log(store, &AgeState::default()));
+    assert!(log[0].ends_with("\nUpdate 1 entry"));
+
+    // The body with every change is encrypted as well
+    let start = parent.message().unwrap().find("-----BEGIN").unwrap();
+    let plaintext = age_state
+        .decrypt(&parent.message_bytes()[start..])
+        .expect("decrypt failed");
+    assert_eq!(
+        String::from_utf8(plaintext).unwrap(),
+        format!("Update 2 entries\n\nAdd '{0}/a'\nAdd '{0}/b'", dirname)
+    );
+
+    // Amended commits stay private, an empty message is generated again
+    fs::write(format!("{}/a.age", dir_path), "d").expect("write file failed");
+    assert_ok(git_stage(store, dirname));
+    assert_ok(git_amend(store, ""));
+    let log = assert_ok(git_log(store, &age_state));
+    assert!(log[0].ends_with(&format!("\nUpdate '{}/a'", dirname)));
+    assert_ok(git_amend(store, "Amended"));
+    let head = repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head.summary(), Some("Update 1 entry"));
+    assert!(!head.message().unwrap().contains("Amended"));
+    let log = assert_ok(git_log(store, &age_state));
+    assert!(log[0].ends_with("\nAmended"));
+
+    // Encrypted bodies are not joined when squashing
+    assert_eq!(assert_ok(git_squash(store, "")), 2);
+    let head = repo.head().unwrap().peel_to_commit().unwrap();
+    let message = head.message().unwrap();
+    assert_eq!(head.summary(), Some("Update 2 entries"));
+    assert_eq!(message.matches("-----BEGIN").count(), 1);
+    let start = message.find("-----BEGIN").unwrap();
+    let plaintext = age_state
+        .decrypt(&head.message_bytes()[start..])
+        .expect("decrypt failed");
+    assert_eq!(
+        String::from_utf8(plaintext).unwrap(),
+        format!("Update 2 entries\n\nAdd '{0}/a'\nAdd '{0}/b'", dirname)
+    );
+
+    // Ordinary messages once disabled
+    assert_ok(git_config_set_private_messages(store, false));
+    fs::remove_file(format!("{}/b.age", dir_path)).expect("remove failed");
+    assert_ok(git_stage(store, dirname));
+    assert_ok(git_commit(store, ""));
+    let head = repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(
+        head.message(),
+        Some(format!("Remove '{0}/b'\n\nRemove '{0}/b'", dirname).as_str())
+    );
+
+    assert_ok(git_push(store));
 }
 
 #[test]
 /// Test that we correctly identify if there are new local commits to push
 fn git_check_head_test() {
-    git_setup();
+    configure();
     let remote_path = &format!("{}/check_head_test.git", GIT_REMOTE_CLONE_URL);
     let repo_path = &format!("{}/check_head_test", GIT_CLIENT_DIR);
     let now = current_time();
     let filename = &format!("file-{}", now);
     let file_path = format!("{}/{}", repo_path, filename);
 
-    clone(remote_path, repo_path);
+    let store = &clone(remote_path, repo_path);
 
     fs::write(&file_path, "Content").expect("write file failed");
 
     // No difference
     let equal =
-        git_local_head_matches_remote(repo_path).expect("Git operation failed");
+        git_local_head_matches_remote(store).expect("Git operation failed");
     assert!(equal);
 
     // Stage the file
-    assert_ok(git_stage(repo_path, &filename));
+    assert_ok(git_stage(store, &filename));
 
     // Still no difference...
     let equal =
-        git_local_head_matches_remote(repo_path).expect("Git operation failed");
+        git_local_head_matches_remote(store).expect("Git operation failed");
     assert!(equal);
 
     // Commit the file
-    assert_ok(git_commit(repo_path, &format!("Add '{}'", filename)));
+    assert_ok(git_commit(store, &format!("Add '{}'", filename)));
 
     // Difference!
     let equal =
-        git_local_head_matches_remote(repo_path).expect("Git operation failed");
+        git_local_head_matches_remote(store).expect("Git operation failed");
     assert!(!equal);
 
     // Push the file
-    assert_ok(git_push(repo_path));
+    assert_ok(git_push(store));
 
     // Back to no difference
     let equal =
-        git_local_head_matches_remote(repo_path).expect("Git operation failed");
+        git_local_head_matches_remote(store).expect("Git operation failed");
     assert!(equal);
 }
 
+#[test]
+/// Test that we can count the number of commits to push and pull
+fn git_ahead_behind_test() {
+    configure();
+    let remote_path =
+        &format!("{}/ahead_behind_test.git", GIT_REMOTE_CLONE_URL);
+    let repo_path = &format!("{}/ahead_behind_test", GIT_CLIENT_DIR);
+    let now = current_time();
+    let external_client_path = &format!("/tmp/.ahead_behind_test-{}", now);
+    let filename = &format!("file-{}", now);
+    let externalfile = &format!("externalfile-{}", now);
+
+    let store = &clone(remote_path, repo_path);
+    clone(remote_path, external_client_path);
+
+    let r = assert_ok(git_ahead_behind(store, true));
+    assert_eq!(
+        r,
+        GitAheadBehind {
+            ahead: 0,
+            behind: 0
+        }
+    );
+
+    // Two local commits
+    for i in 0..2 {
+        fs::write(format!("{}/{}", repo_path, filename), format!("{}", i))
+            .expect("write file failed");
+        assert_ok(git_stage(store, filename));
+        assert_ok(git_commit(store, &format!("Commit {}", i)));
+    }
+
+    // One external commit
+    fs::write(format!("{}/{}", external_client_path, externalfile), "")
+        .expect("write file failed");
+    external_push_file(external_client_path, externalfile);
+
+    // The external commit is not known before fetching
+    let r = assert_ok(git_ahead_behind(store, false));
+    assert_eq!(
+        r,
+        GitAheadBehind {
+            ahead: 2,
+            behind: 0
+        }
+    );
+    assert!(!r.diverged());
+
+    let r = assert_ok(git_ahead_behind(store, true));
+    assert_eq!(
+        r,
+        GitAheadBehind {
+            ahead: 2,
+            behind: 1
+        }
+    );
+    assert!(r.diverged());
+
+    rm_rf(external_client_path);
+}
+
 #[test]
 /// Test that we can add, commit and push a multilevel folder with two files
 fn git_commit_folder_test() {
-    git_setup();
+    configure();
     let remote_path =
         &format!("{}/commit_folder_test.git", GIT_REMOTE_CLONE_URL);
     let repo_path = &format!("{}/commit_folder_test", GIT_CLIENT_DIR);
@@ -97,7 +739,7 @@ fn git_commit_folder_test() {
     let file_path1 = format!("{}/{}", lower_folder_path, filename1);
     let file_path2 = format!("{}/{}", lower_folder_path, filename2);
 
-    clone(remote_path, repo_path);
+    let store = &clone(remote_path, repo_path);
 
     fs::create_dir(&folder_path).expect("create directory failed");
     fs::create_dir(&lower_folder_path).expect("create directory failed");
@@ -105,41 +747,41 @@ fn git_commit_folder_test() {
     fs::write(&file_path2, "Content2").expect("write file failed");
 
     // Commit the folder
-    assert_ok(git_stage(repo_path, &folder));
-    assert_ok(git_commit(repo_path, &format!("Add '{}'", folder)));
-    assert_ok(git_push(repo_path));
+    assert_ok(git_stage(store, &folder));
+    assert_ok(git_commit(store, &format!("Add '{}'", folder)));
+    assert_ok(git_push(store));
 }
 
 #[test]
 /// Test that we can (add, commit) delete a file and push the changes
 fn git_delete_file_test() {
-    git_setup();
+    configure();
     let remote_path = &format!("{}/delete_file_test.git", GIT_REMOTE_CLONE_URL);
     let repo_path = &format!("{}/delete_file_test", GIT_CLIENT_DIR);
     let now = current_time();
     let filename = &format!("file-{}", now);
     let file_path = format!("{}/{}", repo_path, filename);
 
-    clone(remote_path, repo_path);
+    let store = &clone(remote_path, repo_path);
 
     fs::write(&file_path, "Content").expect("write file failed");
 
     // Commit the file
-    assert_ok(git_stage(repo_path, &filename));
-    assert_ok(git_commit(repo_path, &format!("Add '{}'", filename)));
-    assert_ok(git_push(repo_path));
+    assert_ok(git_stage(store, &filename));
+    assert_ok(git_commit(store, &format!("Add '{}'", filename)));
+    assert_ok(git_push(store));
 
     fs::remove_file(&file_path).expect("delete failed");
 
-    assert_ok(git_stage(repo_path, &filename));
-    assert_ok(git_commit(repo_path, &format!("Deleted '{}'", filename)));
-    assert_ok(git_push(repo_path));
+    assert_ok(git_stage(store, &filename));
+    assert_ok(git_commit(store, &format!("Deleted '{}'", filename)));
+    assert_ok(git_push(store));
 }
 
 #[test]
 /// Test that we can pull in external changes (with a local non-conflicting commit)
 fn git_pull_test() {
-    git_setup();
+    configure();
     let remote_path = &format!("{}/pull_test.git", GIT_REMOTE_CLONE_URL);
     let repo_path = &format!("{}/pull_test", GIT_CLIENT_DIR);
     let now = current_time();
@@ -147,16 +789,18 @@ fn git_pull_test() {
     let file_to_keep = &format!("our_file-{}", now);
     let file_to_keep_path = format!("{}/{}", repo_path, file_to_keep);
 
-    clone(remote_path, repo_path);
+    let store = &clone(remote_path, repo_path);
 
     // Create a commit that does not have any conflicts with the external commit
     fs::write(&file_to_keep_path, "To keep").expect("write file failed");
-    assert_ok(git_stage(repo_path, &file_to_keep));
-    assert_ok(git_commit(repo_path, "Test commit"));
-    assert_ok(git_push(repo_path));
+    assert_ok(git_stage(store, &file_to_keep));
+    assert_ok(git_commit(store, "Test commit"));
+    assert_ok(git_push(store));
 
     // Nothing to do
-    assert_ok(git_pull(repo_path));
+    let summary = assert_ok(git_pull(store, &AgeState::default()));
+    assert_eq!(summary.old_head, summary.new_head);
+    assert!(summary.added.is_empty());
 
     // Clone into a new location, add, commit and push from here
     let external_client_path = &format!("/tmp/.pull_test-{}", now);
@@ -173,7 +817,12 @@ fn git_pull_test() {
     external_push_file(external_client_path, externalfile);
 
     // Pull in external updates
-    assert_ok(git_pull(repo_path));
+    let summary = assert_ok(git_pull(store, &AgeState::default()));
+    assert_ne!(summary.old_head, summary.new_head);
+    assert_eq!(summary.added, vec![externalfile.to_string()]);
+    assert!(summary.modified.is_empty());
+    assert!(summary.deleted.is_empty());
+    assert!(summary.renamed.is_empty());
 
     // Check that we got them
     let data = fs::read(&externalfile_pulled_path).expect("read file failed");
@@ -185,10 +834,1021 @@ fn git_pull_test() {
     rm_rf(external_client_path);
 }
 
+#[test]
+/// Test that the pull summary lists modified, deleted and renamed entries
+fn git_pull_summary_test() {
+    configure();
+    let remote_path =
+        &format!("{}/pull_summary_test.git", GIT_REMOTE_CLONE_URL);
+    let repo_path = &format!("{}/pull_summary_test", GIT_CLIENT_DIR);
+    let now = current_time();
+    let external_client_path = &format!("/tmp/.pull_summary_test-{}", now);
+
+    let file_to_modify = &format!("file_to_modify-{}", now);
+    let file_to_remove = &format!("file_to_remove-{}", now);
+    let file_to_rename = &format!("file_to_rename-{}", now);
+    let file_renamed = &format!("folder-{}/file_renamed", now);
+
+    let store = &clone(remote_path, repo_path);
+    for file in [file_to_modify, file_to_remove, file_to_rename] {
+        fs::write(format!("{}/{}", repo_path, file), file)
+            .expect("write file failed");
+        assert_ok(git_stage(store, file));
+    }
+    assert_ok(git_commit(store, "Test commit"));
+    assert_ok(git_push(store));
+
+    // Modify, delete and rename files from an external checkout
+    clone(remote_path, external_client_path);
+    fs::write(
+        format!("{}/{}", external_client_path, file_to_modify),
+        "Modified",
+    )
+    .expect("write file failed");
+    fs::remove_file(format!("{}/{}", external_client_path, file_to_remove))
+        .expect("remove file failed");
+    fs::create_dir(format!("{}/folder-{}", external_client_path, now))
+        .expect("create directory failed");
+    fs::rename(
+        format!("{}/{}", external_client_path, file_to_rename),
+        format!("{}/{}", external_client_path, file_renamed),
+    )
+    .expect("rename failed");
+    external_push_file(external_client_path, ".");
+
+    let summary = assert_ok(git_pull(store, &AgeState::default()));
+    assert!(summary.added.is_empty());
+    assert_eq!(summary.modified, vec![file_to_modify.to_string()]);
+    assert_eq!(summary.deleted, vec![file_to_remove.to_string()]);
+    assert_eq!(
+        summary.renamed,
+        vec![(file_to_rename.to_string(), file_renamed.to_string())]
+    );
+
+    rm_rf(external_client_path);
+}
+
+#[test]
+/// Test that fetching shows incoming changes without applying them
+fn git_fetch_test() {
+    configure();
+    let remote_path = &format!("{}/fetch_test.git", GIT_REMOTE_CLONE_URL);
+    let repo_path = &format!("{}/fetch_test", GIT_CLIENT_DIR);
+    let now = current_time();
+    let external_client_path = &format!("/tmp/.fetch_test-{}", now);
+    let externalfile = &format!("externalfile-{}", now);
+    let externalfile_pulled_path = &format!("{}/{}", repo_path, externalfile);
+
+    let store = &clone(remote_path, repo_path);
+    clone(remote_path, external_client_path);
+
+    // Nothing incoming
+    let preview = assert_ok(git_fetch(store, &AgeState::default()));
+    assert!(preview.commits.is_empty());
+    assert!(preview.changes.added.is_empty());
+
+    fs::write(
+        format!("{}/{}", external_client_path, externalfile),
+        "External content",
+    )
+    .expect("write file failed");
+    external_push_file(external_client_path, externalfile);
+
+    let local_head = head_oid(repo_path);
+    let preview = assert_ok(git_fetch(store, &AgeState::default()));
+    assert_eq!(preview.commits.len(), 1);
+    assert_eq!(
+        preview.commits[0].summary,
+        format!("Adding {}", externalfile)
+    );
+    assert_eq!(preview.changes.added, vec![externalfile.to_string()]);
+
+    // The local checkout is unchanged
+    assert_eq!(head_oid(repo_path), local_head);
+    assert!(fs::metadata(externalfile_pulled_path).is_err());
+
+    // The remote branch was updated
+    let r = assert_ok(git_ahead_behind(store, false));
+    assert_eq!(
+        r,
+        GitAheadBehind {
+            ahead: 0,
+            behind: 1
+        }
+    );
+
+    let summary = assert_ok(git_pull(store, &AgeState::default()));
+    assert_eq!(summary.added, vec![externalfile.to_string()]);
+    assert!(fs::metadata(externalfile_pulled_path).is_ok());
+
+    rm_rf(external_client_path);
+}
+
+#[test]
+/// Test that commits can be synced between two clones through a bundle file
+fn git_bundle_test() {
+    configure();
+    let remote_path = &format!("{}/bundle_test.git", GIT_REMOTE_CLONE_URL);
+    let repo_path = &format!("{}/bundle_test", GIT_CLIENT_DIR);
+    let now = current_time();
+    let offline_client_path = &format!("/tmp/.bundle_test-{}", now);
+    let bundle_path = &format!("/tmp/.bundle_test-{}.bundle", now);
+    let files = [&format!("a-{}", now), &format!("b-{}", now)];
+
+    let store = &clone(remote_path, repo_path);
+    let offline = &clone(remote_path, offline_client_path);
+    let remote_head =
+        format!("{}/{}", test_config().remote, test_config().default_branch);
+
+    // Nothing to export
+    assert_err(git_bundle_export(store, bundle_path, &remote_head));
+
+    for file in files {
+        fs::write(format!("{}/{}", repo_path, file), "Content")
+            .expect("write file failed");
+        assert_ok(git_stage(store, file));
+        assert_ok(git_commit(store, &format!("Add {}", file)));
+    }
+    let exported =
+        assert_ok(git_bundle_export(store, bundle_path, &remote_head));
+    assert_eq!(exported, 2);
+
+    // The bundle is understood by git
+    let output = Command::new("git")
+        .arg("bundle")
+        .arg("verify")
+        .arg(bundle_path)
+        .current_dir(offline_client_path)
+        .output()
+        .expect("command failed");
+    assert!(output.status.success());
+
+    let summary = assert_ok(git_bundle_import(offline, bundle_path));
+    assert_eq!(summary.added.len(), 2);
+    assert_eq!(head_oid(offline_client_path), head_oid(repo_path));
+    for file in files {
+        assert!(
+            Path::new(&format!("{}/{}", offline_client_path, file)).exists()
+        );
+    }
+
+    // Importing the same bundle again is a no-op
+    let summary = assert_ok(git_bundle_import(offline, bundle_path));
+    assert_eq!(summary.old_head, summary.new_head);
+
+    // A bundle that builds on commits that the receiver lacks is rejected
+    fs::write(format!("{}/{}", repo_path, files[0]), "Modified")
+        .expect("write file failed");
+    assert_ok(git_stage(store, files[0]));
+    assert_ok(git_commit(store, "Modified"));
+
+    // A bundle that can not be fast-forwarded does not move the remote
+    // tracking branch
+    let tracking_ref = format!("refs/remotes/{}", remote_head);
+    let offline_repo = git2::Repository::open(offline_client_path).unwrap();
+    let tracked = offline_repo.refname_to_id(&tracking_ref).unwrap();
+    fs::write(format!("{}/{}", offline_client_path, files[1]), "Local")
+        .expect("write file failed");
+    assert_ok(git_stage(offline, files[1]));
+    assert_ok(git_commit(offline, "Local"));
+    assert_ok(git_bundle_export(store, bundle_path, &remote_head));
+    assert_err(git_bundle_import(offline, bundle_path));
+    assert_eq!(offline_repo.refname_to_id(&tracking_ref).unwrap(), tracked);
+
+    assert_ok(git_bundle_export(store, bundle_path, "HEAD~1"));
+
+    let fresh = &clone(remote_path, offline_client_path);
+    assert_err(git_bundle_import(fresh, bundle_path));
+
+    rm_rf(offline_client_path);
+    let _ = fs::remove_file(bundle_path);
+}
+
+#[test]
+/// Test that two clones can exchange commits through a sync directory
+fn git_sync_test() {
+    configure();
+    let remote_path = &format!("{}/sync_test.git", GIT_REMOTE_CLONE_URL);
+    let repo_path = &format!("{}/sync_test", GIT_CLIENT_DIR);
+    let now = current_time();
+    let other_client_path = &format!("/tmp/.sync_test-{}", now);
+    let sync_dir = &format!("/tmp/.sync_test-{}.d", now);
+    let files = [&format!("a-{}", now), &format!("b-{}", now)];
+
+    let identity = ::age::x25519::Identity::generate();
+    let recipient = identity.to_public().to_string();
+    let age_state = AgeState {
+        identity: Some(identity),
+        last_error: None,
+    };
+
+    let store = &clone(remote_path, repo_path);
+    let other = &clone(remote_path, other_client_path);
+    for path in [repo_path, other_client_path] {
+        fs::write(format!("{}/.age-recipients", path), &recipient)
+            .expect("write file failed");
+    }
+    rm_rf(sync_dir);
+    fs::create_dir_all(sync_dir).expect("create directory failed");
+
+    for file in files {
+        fs::write(format!("{}/{}", repo_path, file), "Content")
+            .expect("write file failed");
+        assert_ok(git_stage(store, file));
+        assert_ok(git_commit(store, &format!("Add {}", file)));
+    }
+    // The first export contains the full history
+    let exported = assert_ok(git_sync_export(store, sync_dir));
+    assert!(exported >= 2);
+    assert_eq!(assert_ok(git_sync_export(store, sync_dir)), 0);
+
+    // Neither the file name nor the content reveal any commit metadata
+    let entries: Vec<_> = fs::read_dir(sync_dir)
+        .expect("read directory failed")
+        .map(|entry| entry.expect("read entry failed").path())
+        .collect();
+    assert_eq!(entries.len(), 1);
+    let name = entries[0]
+        .file_name()
+        .unwrap()
+        .to_string_lossy()
+        .to_string();
+    assert!(!name.contains(&head_oid(repo_path)));
+    let content = fs::read(&entries[0]).expect("read file failed");
+    assert!(!content
+        .windows(files[0].len())
+        .any(|w| w == files[0].as_bytes()));
+
+    // The bundle can only be applied with an identity
+    assert_err(git_sync_import(other, &AgeState::default(), sync_dir));
+
+    let summary = assert_ok(git_sync_import(other, &age_state, sync_dir));
+    assert_eq!(summary.added.len(), 2);
+    assert_eq!(head_oid(other_client_path), head_oid(repo_path));
+
+    // Applied bundles are skipped
+    let summary = assert_ok(git_sync_import(other, &age_state, sync_dir));
+    assert_eq!(summary.old_head, summary.new_head);
+
+    // Changes flow back incrementally, our own bundles are never applied
+    fs::write(format!("{}/{}", other_client_path, files[0]), "Modified")
+        .expect("write file failed");
+    assert_ok(git_stage(other, files[0]));
+    assert_ok(git_commit(other, "Modified"));
+    assert_eq!(assert_ok(git_sync_export(other, sync_dir)), 1);
+
+    let summary = assert_ok(git_sync_import(store, &age_state, sync_dir));
+    assert_eq!(summary.modified, vec![files[0].to_string()]);
+    assert_eq!(head_oid(repo_path), head_oid(other_client_path));
+    assert_eq!(assert_ok(git_sync_export(store, sync_dir)), 0);
+
+    // A diverged bundle is skipped once instead of blocking later imports
+    fs::write(format!("{}/{}", repo_path, files[1]), "Local")
+        .expect("write file failed");
+    assert_ok(git_stage(store, files[1]));
+    assert_ok(git_commit(store, "Local"));
+    let local_head = head_oid(repo_path);
+    fs::write(format!("{}/{}", other_client_path, files[1]), "Other")
+        .expect("write file failed");
+    assert_ok(git_stage(other, files[1]));
+    assert_ok(git_commit(other, "Other"));
+    assert_eq!(assert_ok(git_sync_export(other, sync_dir)), 1);
+
+    let summary = assert_ok(git_sync_import(store, &age_state, sync_dir));
+    assert_eq!(summary.skipped.len(), 1);
+    assert_eq!(head_oid(repo_path), local_head);
+    let summary = assert_ok(git_sync_import(store, &age_state, sync_dir));
+    assert!(summary.skipped.is_empty());
+
+    // Bundles that were removed from the sync directory are forgotten
+    let applied = |path: &str| {
+        let cfg =
+            git2::Config::open(Path::new(&format!("{}/.git/config", path)))
+                .expect("open config failed");
+        let mut names = vec![];
+        cfg.multivar("kage.syncapplied", None)
+            .expect("read config failed")
+            .for_each(|entry| names.push(entry.value().unwrap().to_string()))
+            .expect("read config failed");
+        names
+    };
+    assert_eq!(applied(repo_path).len(), 3);
+    rm_rf(sync_dir);
+    fs::create_dir_all(sync_dir).expect("create directory failed");
+    assert_ok(git_sync_import(store, &age_state, sync_dir));
+    assert!(applied(repo_path).is_empty());
+
+    rm_rf(other_client_path);
+    rm_rf(sync_dir);
+}
+
+#[test]
+/// Test that local commits survive a force-push to the remote
+fn git_recover_test() {
+    configure();
+    let remote_path = &format!("{}/recover_test.git", GIT_REMOTE_CLONE_URL);
+    let repo_path = &format!("{}/recover_test", GIT_CLIENT_DIR);
+    let now = current_time();
+    let external_client_path = &format!("/tmp/.recover_test-{}", now);
+    let shared_file = &format!("shared-{}", now);
+    let local_file = &format!("local-{}", now);
+    let git = |args: &[&str]| {
+        let status = Command::new("git")
+            .args(args)
+            .current_dir(external_client_path)
+            .status()
+            .expect("command failed");
+        assert!(status.success());
+    };
+
+    let store = &clone(remote_path, repo_path);
+    clone(remote_path, external_client_path);
+    fs::write(
+        format!("{}/{}", external_client_path, shared_file),
+        "Remote",
+    )
+    .expect("write file failed");
+    external_push_file(external_client_path, shared_file);
+    assert_ok(git_pull(store, &AgeState::default()));
+
+    // Nothing to recover from
+    assert_err(git_recover(store));
+    let old_head = head_oid(repo_path);
+
+    // One local commit that replays cleanly and one that conflicts
+    fs::write(format!("{}/{}", repo_path, local_file), "Local")
+        .expect("write file failed");
+    assert_ok(git_stage(store, local_file));
+    assert_ok(git_commit(store, "Add local file"));
+    fs::write(format!("{}/{}", repo_path, shared_file), "Local")
+        .expect("write file failed");
+    assert_ok(git_stage(store, shared_file));
+    assert_ok(git_commit(store, "Modify shared file"));
+    let local_head = head_oid(repo_path);
+
+    // Rewrite the remote history
+    git(&["reset", "-q", "--hard", "HEAD~1"]);
+    fs::write(
+        format!("{}/{}", external_client_path, shared_file),
+        "Rewritten",
+    )
+    .expect("write file failed");
+    git(&["add", shared_file]);
+    git(&["commit", "-q", "-m", "Rewritten"]);
+    git(&["push", "-q", "-f", &test_config().remote, &store.branch]);
+
+    let Err(err) = git_pull(store, &AgeState::default()) else {
+        panic!("Unexpected successful pull");
+    };
+    assert_eq!(err.code(), git2::ErrorCode::NotFastForward);
+    assert_eq!(err.message(), "Remote history was rewritten");
+    assert_eq!(head_oid(repo_path), local_head);
+
+    // A fast-forward of the rewritten remote does not hide the rewrite
+    let later_file = &format!("later-{}", now);
+    fs::write(format!("{}/{}", external_client_path, later_file), "Later")
+        .expect("write file failed");
+    external_push_file(external_client_path, later_file);
+    let rewritten_head = head_oid(external_client_path);
+    let Err(err) = git_pull(store, &AgeState::default()) else {
+        panic!("Unexpected successful pull");
+    };
+    assert_eq!(err.message(), "Remote history was rewritten");
+
+    let recovery = assert_ok(git_recover(store));
+    assert_eq!(recovery.replayed.len(), 1);
+    assert_eq!(recovery.replayed[0].summary, "Add local file");
+    assert_eq!(recovery.failed.len(), 1);
+    assert_eq!(recovery.failed[0].summary, "Modify shared file");
+
+    // The local commit is replayed on top of the rewritten remote
+    let repo = git2::Repository::open(repo_path).expect("open failed");
+    let head = repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head.parent_id(0).unwrap().to_string(), rewritten_head);
+    assert!(tree_has_path(repo_path, local_file));
+    let data = fs::read_to_string(format!("{}/{}", repo_path, shared_file))
+        .expect("read file failed");
+    assert_eq!(data, "Rewritten");
+
+    // The original commits are kept in the backup
+    let backup = repo.refname_to_id(&recovery.backup_ref).unwrap();
+    assert_eq!(backup.to_string(), local_head);
+    assert!(repo
+        .graph_descendant_of(backup, old_head.parse().unwrap())
+        .unwrap());
+
+    // Everything is back to normal
+    assert_err(git_recover(store));
+    assert_ok(git_pull(store, &AgeState::default()));
+    assert_ok(git_push(store));
+
+    rm_rf(external_client_path);
+}
+
+#[test]
+/// Test that a corrupt repository is replaced while keeping unpushed commits
+fn git_repair_test() {
+    configure();
+    let remote_path = &format!("{}/repair_test.git", GIT_REMOTE_CLONE_URL);
+    let repo_path = &format!("{}/repair_test", GIT_CLIENT_DIR);
+    let now = current_time();
+    let filename = &format!("file-{}", now);
+    let lost_filename = &format!("lost-{}", now);
+
+    let store = &clone(remote_path, repo_path);
+    fs::write(&store.identity_path, "Identity").expect("write file failed");
+    assert!(assert_ok(git_repair(store)).problems.is_empty());
+
+    fs::write(format!("{}/{}", repo_path, filename), "Content")
+        .expect("write file failed");
+    assert_ok(git_stage(store, filename));
+    assert_ok(git_commit(store, "Unpushed"));
+    let local_head = head_oid(repo_path);
+
+    // Broken index, the unpushed commit is re-applied onto the new clone
+    fs::write(format!("{}/.git/index", repo_path), "Garbage")
+        .expect("write file failed");
+    assert_err(git_stage(store, filename));
+
+    let repair = assert_ok(git_repair(store));
+    assert!(!repair.problems.is_empty());
+    assert_eq!(repair.reapplied, 1);
+    assert_eq!(head_oid(repo_path), local_head);
+    assert!(tree_has_path(repo_path, filename));
+    // Only the untracked identity differs from HEAD
+    let status = assert_ok(git_status(store, &AgeState::default()));
+    assert!(status.iter().all(|e| e.path == ".age-identities"));
+    assert!(Path::new(&store.identity_path).exists());
+    assert!(Path::new(&repair.backup_path).exists());
+    assert!(assert_ok(git_repair(store)).problems.is_empty());
+    assert_ok(git_push(store));
+    rm_rf(&repair.backup_path);
+
+    // Missing object, the unpushed commit can not be saved
+    fs::write(format!("{}/{}", repo_path, lost_filename), "Lost")
+        .expect("write file failed");
+    assert_ok(git_stage(store, lost_filename));
+    assert_ok(git_commit(store, "Lost"));
+    let repo = git2::Repository::open(repo_path).expect("open failed");
+    let blob = repo.blob("Lost".as_bytes()).unwrap().to_string();
+    fs::remove_file(format!(
+        "{}/.git/objects/{}/{}",
+        repo_path,
+        &blob[..2],
+        &blob[2..]
+    ))
+    .expect("remove file failed");
+
+    let repair = assert_ok(git_repair(store));
+    assert!(repair.problems.iter().any(|p| p.contains(&blob)));
+    assert!(repair.saved_ref.is_empty());
+    assert_eq!(repair.reapplied, 0);
+    assert!(assert_ok(git_local_head_matches_remote(store)));
+    assert!(tree_has_path(repo_path, filename));
+    assert!(!tree_has_path(repo_path, lost_filename));
+    rm_rf(&repair.backup_path);
+
+    // A swap that was interrupted after the broken repository was moved away
+    // is finished, clones from other interrupted repairs are removed
+    let backup_path = &format!("{}.broken-1", repo_path);
+    let tmp_path = &format!("{}.repair-1", repo_path);
+    let stale_path = &format!("{}.repair-0", repo_path);
+    fs::rename(repo_path, backup_path).expect("rename failed");
+    clone(remote_path, tmp_path);
+    fs::create_dir_all(stale_path).expect("create dir failed");
+    assert!(assert_ok(git_repair(store)).problems.is_empty());
+    assert!(assert_ok(git_local_head_matches_remote(store)));
+    assert!(!Path::new(tmp_path).exists());
+    assert!(!Path::new(stale_path).exists());
+    rm_rf(backup_path);
+}
+
+#[test]
+/// Test that loose objects are packed and unreachable ones pruned
+fn git_maintenance_test() {
+    configure();
+    let remote_path = &format!("{}/maintenance_test.git", GIT_REMOTE_CLONE_URL);
+    let repo_path = &format!("{}/maintenance_test", GIT_CLIENT_DIR);
+    let now = current_time();
+    let files = [&format!("a-{}.age", now), &format!("b-{}.age", now)];
+
+    let store = &clone(remote_path, repo_path);
+    for file in files {
+        fs::write(format!("{}/{}", repo_path, file), "Content")
+            .expect("write file failed");
+        assert_ok(git_stage(store, file));
+        assert_ok(git_commit(store, &format!("Add {}", file)));
+    }
+    let repo = git2::Repository::open(repo_path).expect("open failed");
+    let unreachable = repo
+        .blob(format!("Unreachable {}", now).as_bytes())
+        .unwrap();
+
+    let stats = assert_ok(git_stats(store));
+    assert!(stats.loose_objects >= 5);
+    assert_eq!(stats.age_entries, 2);
+    assert!(stats.worktree_size > 0);
+
+    // Unreachable objects are kept during the grace period
+    let stats = assert_ok(git_maintenance(store, 3600));
+    assert_eq!(stats.loose_objects, 1);
+    assert_eq!(stats.packs, 1);
+    assert!(stats.packed_objects >= 5);
+    assert!(repo.find_blob(unreachable).is_ok());
+
+    let stats = assert_ok(git_maintenance(store, 0));
+    assert_eq!(stats.loose_objects, 0);
+    assert_eq!(stats.loose_size, 0);
+    assert_eq!(stats.packs, 1);
+    assert_eq!(stats.age_entries, 2);
+    let repo = git2::Repository::open(repo_path).expect("open failed");
+    assert!(repo.find_blob(unreachable).is_err());
+
+    // The repository is still intact
+    assert!(assert_ok(git_repair(store)).problems.is_empty());
+
+    // Staged blobs, commits that are only in a reflog and kept packs are
+    // never pruned, the multi-pack-index is removed
+    let staged = &format!("staged-{}.age", now);
+    fs::write(format!("{}/{}", repo_path, staged), "Staged")
+        .expect("write file failed");
+    assert_ok(git_stage(store, staged));
+    let staged_blob = repo
+        .index()
+        .unwrap()
+        .get_path(Path::new(staged), 0)
+        .unwrap()
+        .id;
+    let head = repo.head().unwrap().peel_to_commit().unwrap();
+    let branch_ref = format!("refs/heads/{}", store.branch);
+    repo.reference(&branch_ref, head.parent_id(0).unwrap(), true, "Rewind")
+        .unwrap();
+    let pack = fs::read_dir(format!("{}/.git/objects/pack", repo_path))
+        .expect("read directory failed")
+        .map(|entry| entry.expect("read entry failed").path())
+        .find(|path| path.extension().is_some_and(|ext| ext == "pack"))
+        .unwrap();
+    fs::write(pack.with_extension("keep"), "").expect("write file failed");
+    let status = Command::new("git")
+        .args(["multi-pack-index", "write"])
+        .current_dir(repo_path)
+        .status()
+        .expect("command failed");
+    assert!(status.success());
+
+    // Annotated tags, including tags of tags, are kept
+    let sig = repo.signature().unwrap();
+    let tag_name = &format!("maintenance-{}", now);
+    let tag = repo
+        .tag(tag_name, head.as_object(), &sig, "Tag", false)
+        .unwrap();
+    let tag = repo.find_object(tag, None).unwrap();
+    let chained_name = &format!("maintenance-chained-{}", now);
+    let chained = repo
+        .tag(chained_name, &tag, &sig, "Chained", false)
+        .unwrap();
+
+    let stats = assert_ok(git_maintenance(store, 0));
+    assert_eq!(stats.packs, 2);
+    assert!(pack.exists());
+    assert!(!Path::new(&format!(
+        "{}/.git/objects/pack/multi-pack-index",
+        repo_path
+    ))
+    .exists());
+    let repo = git2::Repository::open(repo_path).expect("open failed");
+    assert!(repo.find_blob(staged_blob).is_ok());
+    assert!(repo.find_commit(head.id()).is_ok());
+    let tag_ref = format!("refs/tags/{}", tag_name);
+    let tag_oid = repo.refname_to_id(&tag_ref).unwrap();
+    assert!(repo.find_tag(tag_oid).is_ok());
+    assert!(repo.find_tag(chained).is_ok());
+    let chained_ref = format!("refs/tags/{}", chained_name);
+    assert!(repo
+        .revparse_single(&chained_ref)
+        .unwrap()
+        .peel_to_commit()
+        .is_ok());
+
+    repo.reference(&branch_ref, head.id(), true, "Restore")
+        .unwrap();
+    assert_ok(git_push(store));
+}
+
+#[test]
+/// Test that paths and blobs are removed from every commit on the branch
+fn git_purge_test() {
+    configure();
+    let remote_path = &format!("{}/purge_test.git", GIT_REMOTE_CLONE_URL);
+    let repo_path = &format!("{}/purge_test", GIT_CLIENT_DIR);
+    let now = current_time();
+    let verify_client_path = &format!("/tmp/.purge_test-{}", now);
+    let secret = &format!("dir-{}/secret", now);
+    let copy = &format!("copy-{}", now);
+    let other = &format!("other-{}", now);
+    let leaked = format!("Leaked {}", now);
+
+    let store = &clone(remote_path, repo_path);
+    fs::create_dir_all(format!("{}/dir-{}", repo_path, now)).unwrap();
+    fs::write(format!("{}/{}", repo_path, secret), &leaked)
+        .expect("write file failed");
+    assert_ok(git_stage(store, secret));
+    assert_ok(git_commit(store, "Add secret"));
+    fs::write(format!("{}/{}", repo_path, other), "Other")
+        .expect("write file failed");
+    assert_ok(git_stage(store, other));
+    assert_ok(git_commit(store, "Add other"));
+    fs::write(format!("{}/{}", repo_path, copy), &leaked)
+        .expect("write file failed");
+    fs::write(format!("{}/{}", repo_path, other), "Modified")
+        .expect("write file failed");
+    assert_ok(git_stage(store, copy));
+    assert_ok(git_stage(store, other));
+    assert_ok(git_commit(store, "Add copy"));
+    assert_ok(git_push(store));
+
+    let repo = git2::Repository::open(repo_path).expect("open failed");
+    let leaked_blob =
+        git2::Oid::hash_object(git2::ObjectType::Blob, leaked.as_bytes())
+            .unwrap()
+            .to_string();
+    let contains_leak = |repo_path: &str| {
+        let repo = git2::Repository::open(repo_path).expect("open failed");
+        let mut revwalk = repo.revwalk().unwrap();
+        revwalk.push_head().unwrap();
+        revwalk.any(|oid| {
+            let tree = repo.find_commit(oid.unwrap()).unwrap().tree().unwrap();
+            let mut found = false;
+            tree.walk(git2::TreeWalkMode::PreOrder, |_, entry| {
+                found |= entry.id().to_string() == leaked_blob;
+                git2::TreeWalkResult::Ok
+            })
+            .unwrap();
+            found
+        })
+    };
+    assert!(contains_leak(repo_path));
+
+    // Backup and sync references are rewritten with the branch
+    let backup_ref = format!("refs/kage/backup/{}-{}", store.branch, now);
+    let old_head = repo.head().unwrap().target().unwrap();
+    repo.reference(&backup_ref, old_head, true, "Backup")
+        .unwrap();
+    repo.reference("refs/kage/sync", old_head, true, "Sync")
+        .unwrap();
+
+    // Purge the directory by path and the copy by content, locally only
+    let dir = format!("dir-{}", now);
+    let purge = assert_ok(git_purge(store, &[&dir], &[&leaked_blob], false));
+    assert!(!purge.pushed);
+    let new_head = repo.head().unwrap().target().unwrap();
+    assert_eq!(repo.refname_to_id(&backup_ref).unwrap(), new_head);
+    assert_eq!(repo.refname_to_id("refs/kage/sync").unwrap(), new_head);
+    // The remote tracking branch still has the leaked blob
+    let tracking_ref =
+        format!("refs/remotes/{}/{}", test_config().remote, store.branch);
+    assert_eq!(purge.retained, vec![tracking_ref]);
+    // The commit that only added the secret is dropped
+    let dropped = purge.mapping.iter().filter(|(old, new)| {
+        repo.find_commit(*old).unwrap().parent_id(0).ok() == Some(*new)
+    });
+    assert_eq!(dropped.count(), 1);
+    assert_eq!(purge.rewritten, 3);
+    assert!(!contains_leak(repo_path));
+    assert!(!Path::new(&format!("{}/{}", repo_path, secret)).exists());
+    assert!(!Path::new(&format!("{}/{}", repo_path, copy)).exists());
+    let data = fs::read_to_string(format!("{}/{}", repo_path, other)).unwrap();
+    assert_eq!(data, "Modified");
+    assert!(!assert_ok(git_local_head_matches_remote(store)));
+    assert!(Path::new(&format!("{}/.git/kage-purge-map", repo_path)).exists());
+
+    // Nothing left to rewrite, force-push the purged history
+    let purge = assert_ok(git_purge(store, &[&dir], &[&leaked_blob], true));
+    assert!(purge.pushed);
+    assert_eq!(purge.rewritten, 0);
+    assert!(purge.mapping.iter().all(|(old, new)| old == new));
+    assert!(purge.retained.is_empty());
+
+    // Nothing references the leaked blob anymore
+    let leaked_oid = git2::Oid::from_str(&leaked_blob).unwrap();
+    assert!(repo.find_blob(leaked_oid).is_ok());
+    assert_ok(git_maintenance(store, 0));
+    let repo = git2::Repository::open(repo_path).expect("open failed");
+    assert!(repo.find_blob(leaked_oid).is_err());
+
+    clone(remote_path, verify_client_path);
+    assert_eq!(head_oid(verify_client_path), head_oid(repo_path));
+    assert!(!contains_leak(verify_client_path));
+
+    rm_rf(verify_client_path);
+}
+
+#[test]
+/// Test that old history is squashed into a root commit and that other
+/// clients are told to clone again
+fn git_compact_test() {
+    configure();
+    let remote_path = &format!("{}/compact_test.git", GIT_REMOTE_CLONE_URL);
+    let repo_path = &format!("{}/compact_test", GIT_CLIENT_DIR);
+    let now = current_time();
+    let other_client_path = &format!("/tmp/.compact_test-{}", now);
+    let backup_path = &format!("/tmp/.compact_test-{}.bundle", now);
+
+    let store = &clone(remote_path, repo_path);
+    let other = &clone(remote_path, other_client_path);
+    let mut commits = vec![];
+    for i in 0..3 {
+        let file = format!("file-{}-{}", i, now);
+        fs::write(format!("{}/{}", repo_path, file), "Content")
+            .expect("write file failed");
+        assert_ok(git_stage(store, &file));
+        assert_ok(git_commit(store, &format!("Add {}", file)));
+        commits.push(head_oid(repo_path));
+    }
+    assert_ok(git_push(store));
+    assert_ok(git_pull(other, &AgeState::default()));
+
+    let repo = git2::Repository::open(repo_path).expect("open failed");
+    let old_head = repo.head().unwrap().peel_to_commit().unwrap();
+    let cutoff = repo.revparse_single(&commits[1]).unwrap();
+    let cutoff = cutoff.peel_to_commit().unwrap();
+
+    // The backup reference is rewritten, the sync reference is older than
+    // the cutoff and can not be rewritten
+    let backup_ref = format!("refs/kage/backup/{}-{}", store.branch, now);
+    repo.reference(&backup_ref, old_head.id(), true, "Backup")
+        .unwrap();
+    let oldest = git2::Oid::from_str(&commits[0]).unwrap();
+    repo.reference("refs/kage/sync", oldest, true, "Sync")
+        .unwrap();
+
+    assert_err(git_compact(store, "", 0, backup_path, false));
+    let compact =
+        assert_ok(git_compact(store, &commits[1], 0, backup_path, true));
+    assert!(compact.pushed);
+    assert!(compact.squashed >= 3);
+    assert_eq!(compact.kept, 1);
+    assert_eq!(compact.retained, vec!["refs/kage/sync".to_string()]);
+
+    // The root commit has the tree of the cutoff and the newest commit is kept
+    let head = repo.head().unwrap().peel_to_commit().unwrap();
+    let root = repo.find_commit(compact.root).unwrap();
+    assert_eq!(root.parent_count(), 0);
+    assert_eq!(root.tree_id(), cutoff.tree_id());
+    assert_eq!(head.parent_id(0).unwrap(), compact.root);
+    assert_eq!(head.tree_id(), old_head.tree_id());
+    assert_eq!(head.message(), old_head.message());
+    assert!(assert_ok(git_local_head_matches_remote(store)));
+    assert_eq!(repo.refname_to_id(&backup_ref).unwrap(), head.id());
+
+    // The old history is removed once nothing references it
+    repo.find_reference("refs/kage/sync")
+        .unwrap()
+        .delete()
+        .unwrap();
+    assert_ok(git_maintenance(store, 0));
+    let repo = git2::Repository::open(repo_path).expect("open failed");
+    assert!(repo.find_commit(oldest).is_err());
+
+    // Nothing left to compact
+    assert_err(git_compact(store, "HEAD~1", 0, backup_path, false));
+
+    // The backup contains the full history
+    let output = Command::new("git")
+        .arg("bundle")
+        .arg("verify")
+        .arg(backup_path)
+        .current_dir(other_client_path)
+        .output()
+        .expect("command failed");
+    assert!(output.status.success());
+
+    let Err(err) = git_pull(other, &AgeState::default()) else {
+        panic!("Unexpected successful pull");
+    };
+    assert!(err.message().contains("compacted"));
+    let Err(err) = git_recover(other) else {
+        panic!("Unexpected successful recovery");
+    };
+    assert!(err.message().contains("compacted"));
+
+    rm_rf(other_client_path);
+    let _ = fs::remove_file(backup_path);
+}
+
+#[test]
+/// Test that the cutoff from a timestamp is searched on all parents of merges
+fn git_compact_merge_test() {
+    configure();
+    let now = current_time();
+    let repo_path = &format!("/tmp/.compact_merge_test-{}", now);
+    let backup_path = &format!("/tmp/.compact_merge_test-{}.bundle", now);
+
+    let store = &test_store("", repo_path);
+    assert_ok(git_init(store, "Passphrase", GIT_USERNAME));
+    let repo = git2::Repository::open(repo_path).expect("open failed");
+    let initial = repo.head().unwrap().peel_to_commit().unwrap();
+    let time = initial.time().seconds();
+
+    let commit = |name: &str, time: i64, parents: &[&git2::Commit]| {
+        let parent_tree = parents[0].tree().unwrap();
+        let mut builder = repo.treebuilder(Some(&parent_tree)).unwrap();
+        let blob = repo.blob(name.as_bytes()).unwrap();
+        builder.insert(name, blob, 0o100644).unwrap();
+        let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+        let sig = git2::Signature::new(
+            GIT_USERNAME,
+            "kage@localhost",
+            &git2::Time::new(time, 0),
+        )
+        .unwrap();
+        let oid = repo.commit(None, &sig, &sig, name, &tree, parents).unwrap();
+        repo.find_commit(oid).unwrap()
+    };
+
+    // The newest commit before the timestamp is on the merged side branch
+    let main = commit("main", time + 10, &[&initial]);
+    let side = commit("side", time + 20, &[&initial]);
+    let merge = commit("merge", time + 30, &[&main, &side]);
+    let newest = commit("newest", time + 40, &[&merge]);
+    let branch_ref = format!("refs/heads/{}", store.branch);
+    repo.reference(&branch_ref, newest.id(), true, "Test")
+        .unwrap();
+    repo.checkout_head(Some(git2::build::CheckoutBuilder::new().force()))
+        .unwrap();
+
+    let compact =
+        assert_ok(git_compact(store, "", time + 25, backup_path, false));
+    let root = repo.find_commit(compact.root).unwrap();
+    assert_eq!(root.tree_id(), side.tree_id());
+    assert_eq!(compact.squashed, 2);
+    assert_eq!(compact.kept, 3);
+    let head = repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head.tree_id(), newest.tree_id());
+
+    rm_rf(repo_path);
+    let _ = fs::remove_file(backup_path);
+}
+
+#[test]
+/// Test that a shallow clone stays shallow when pulling and can be deepened
+fn git_shallow_clone_test() {
+    configure();
+    let remote_path =
+        &format!("{}/shallow_clone_test.git", GIT_REMOTE_CLONE_URL);
+    let repo_path = &format!("{}/shallow_clone_test", GIT_CLIENT_DIR);
+    let now = current_time();
+    let external_client_path = &format!("/tmp/.shallow_clone_test-{}", now);
+    let filename = &format!("file-{}", now);
+
+    // Create a few commits from another clone
+    let external = &clone(remote_path, external_client_path);
+    for i in 0..3 {
+        fs::write(
+            format!("{}/{}", external_client_path, filename),
+            format!("{}", i),
+        )
+        .expect("write file failed");
+        external_push_file(external_client_path, filename);
+    }
+    let full_len = assert_ok(git_log(external, &AgeState::default())).len();
+    assert!(full_len >= 4);
+
+    let store = &test_store(remote_path, repo_path);
+    rm_rf(repo_path);
+    assert_ok(git_clone(store, 1, &[]));
+    assert_ok(git_config_set_user(store, GIT_USERNAME));
+    assert!(is_shallow(repo_path));
+    assert_eq!(assert_ok(git_log(store, &AgeState::default())).len(), 1);
+
+    // Pull in a new commit
+    fs::write(format!("{}/{}", external_client_path, filename), "New")
+        .expect("write file failed");
+    external_push_file(external_client_path, filename);
+
+    let summary = assert_ok(git_pull(store, &AgeState::default()));
+    assert_eq!(summary.modified, vec![filename.to_string()]);
+    assert!(is_shallow(repo_path));
+    assert_eq!(assert_ok(git_log(store, &AgeState::default())).len(), 2);
+
+    // Deepen the history to three commits
+    assert_ok(git_deepen(store, 3));
+    assert!(is_shallow(repo_path));
+    assert_eq!(assert_ok(git_log(store, &AgeState::default())).len(), 3);
+
+    // Fetch the complete history
+    assert_ok(git_deepen(store, 0));
+    assert!(!is_shallow(repo_path));
+    assert_eq!(
+        assert_ok(git_log(store, &AgeState::default())).len(),
+        full_len + 1
+    );
+
+    // Pushing from a previously shallow clone works
+    fs::write(format!("{}/{}", repo_path, filename), "Ours")
+        .expect("write file failed");
+    assert_ok(git_stage(store, filename));
+    assert_ok(git_commit(store, "Test commit"));
+    assert_ok(git_push(store));
+
+    rm_rf(external_client_path);
+}
+
+#[test]
+/// Test that only the sparse directories are checked out and that other
+/// entries are kept intact when committing
+fn git_sparse_checkout_test() {
+    configure();
+    let remote_path =
+        &format!("{}/sparse_checkout_test.git", GIT_REMOTE_CLONE_URL);
+    let repo_path = &format!("{}/sparse_checkout_test", GIT_CLIENT_DIR);
+    let now = current_time();
+    let external_client_path = &format!("/tmp/.sparse_checkout_test-{}", now);
+    let work_folder = &format!("work-{}", now);
+    let personal_folder = &format!("personal-{}", now);
+    let work_file = &format!("{}/a/file", work_folder);
+    let personal_file = &format!("{}/a/file", personal_folder);
+    let root_file = &format!("file-{}", now);
+
+    // Create entries in two folders from another clone
+    clone(remote_path, external_client_path);
+    for file in [work_file, personal_file, root_file] {
+        let path = format!("{}/{}", external_client_path, file);
+        fs::create_dir_all(Path::new(&path).parent().unwrap())
+            .expect("create directory failed");
+        fs::write(&path, "Content").expect("write file failed");
+    }
+    external_push_file(external_client_path, ".");
+
+    let store = &test_store(remote_path, repo_path);
+    rm_rf(repo_path);
+    assert_ok(git_clone(store, 0, &[work_folder]));
+    assert_ok(git_config_set_user(store, GIT_USERNAME));
+    assert_eq!(
+        assert_ok(git_sparse_list(store)),
+        vec![work_folder.to_string()]
+    );
+
+    let work_path = format!("{}/{}", repo_path, work_file);
+    let personal_path = format!("{}/{}", repo_path, personal_file);
+    let personal_folder_path = format!("{}/{}", repo_path, personal_folder);
+    assert!(fs::metadata(&work_path).is_ok());
+    assert!(fs::metadata(format!("{}/{}", repo_path, root_file)).is_ok());
+    assert!(fs::metadata(&personal_folder_path).is_err());
+    assert!(assert_ok(git_status(store, &AgeState::default())).is_empty());
+
+    // Commit changes to the whole tree, entries outside of the sparse
+    // directories are kept.
+    fs::write(&work_path, "Modified").expect("write file failed");
+    assert_ok(git_stage(store, "."));
+    assert_ok(git_commit(store, "Test commit"));
+    assert_ok(git_push(store));
+    assert!(tree_has_path(repo_path, personal_file));
+    assert!(tree_has_path(repo_path, work_file));
+
+    // Pull in external changes to both folders
+    let status = Command::new("git")
+        .arg("pull")
+        .arg("-q")
+        .current_dir(external_client_path)
+        .status()
+        .expect("command failed");
+    assert!(status.success());
+    for file in [work_file, personal_file] {
+        fs::write(format!("{}/{}", external_client_path, file), "External")
+            .expect("write file failed");
+    }
+    external_push_file(external_client_path, ".");
+
+    let summary = assert_ok(git_pull(store, &AgeState::default()));
+    assert_eq!(summary.modified.len(), 2);
+    let data = fs::read(&work_path).expect("read file failed");
+    assert_eq!(data, "External".as_bytes());
+    assert!(fs::metadata(&personal_folder_path).is_err());
+
+    assert_ok(git_reset(store));
+    assert!(fs::metadata(&personal_folder_path).is_err());
+
+    // Restore a full checkout
+    assert_ok(git_sparse_set(store, &[]));
+    assert!(assert_ok(git_sparse_list(store)).is_empty());
+    let data = fs::read(&personal_path).expect("read file failed");
+    assert_eq!(data, "External".as_bytes());
+    assert!(assert_ok(git_status(store, &AgeState::default())).is_empty());
+
+    // Exclude the work folder
+    assert_ok(git_sparse_set(store, &[personal_folder]));
+    assert!(fs::metadata(&work_path).is_err());
+    assert!(fs::metadata(&personal_path).is_ok());
+    assert!(assert_ok(git_status(store, &AgeState::default())).is_empty());
+
+    rm_rf(external_client_path);
+}
+
 #[test]
 /// Test that we can reset to the previous head commit in a local checkout
 fn git_reset_test() {
-    git_setup();
+    configure();
     let remote_path = &format!("{}/reset_test.git", GIT_REMOTE_CLONE_URL);
     let repo_path = &format!("{}/reset_test", GIT_CLIENT_DIR);
     let now = current_time();
@@ -205,17 +1865,17 @@ fn git_reset_test() {
 
     let original_data = "To modify";
 
-    clone(remote_path, repo_path);
+    let store = &clone(remote_path, repo_path);
 
     // Create a commit with all three files
     fs::write(&file_to_keep_path, "To keep").expect("write file failed");
     fs::write(&file_to_remove_path, "To remove").expect("write file failed");
     fs::write(&file_to_modify_path, original_data).expect("write file failed");
-    assert_ok(git_stage(repo_path, &file_to_keep));
-    assert_ok(git_stage(repo_path, &file_to_remove));
-    assert_ok(git_stage(repo_path, &file_to_modify));
-    assert_ok(git_commit(repo_path, "Test commit"));
-    assert_ok(git_push(repo_path));
+    assert_ok(git_stage(store, &file_to_keep));
+    assert_ok(git_stage(store, &file_to_remove));
+    assert_ok(git_stage(store, &file_to_modify));
+    assert_ok(git_commit(store, "Test commit"));
+    assert_ok(git_push(store));
 
     // Stage some new changes
     fs::write(&file_to_not_keep_path, "To not keep")
@@ -225,9 +1885,9 @@ fn git_reset_test() {
         .expect("write file failed");
 
     // The 'file_to_not_keep' is only removed from the repo with `reset` if it has been staged
-    assert_ok(git_stage(repo_path, &file_to_not_keep));
-    assert_ok(git_stage(repo_path, &file_to_remove));
-    assert_ok(git_stage(repo_path, &file_to_modify));
+    assert_ok(git_stage(store, &file_to_not_keep));
+    assert_ok(git_stage(store, &file_to_remove));
+    assert_ok(git_stage(store, &file_to_modify));
 
     assert!(fs::metadata(&file_to_keep_path).is_ok());
     assert!(fs::metadata(&file_to_not_keep_path).is_ok());
@@ -235,7 +1895,7 @@ fn git_reset_test() {
     assert!(fs::metadata(&file_to_modify_path).is_ok());
 
     // Reset the changes
-    assert_ok(git_reset(repo_path));
+    assert_ok(git_reset(store));
 
     // Verify that changes were restored
     assert!(fs::metadata(&file_to_keep_path).is_ok());
@@ -245,10 +1905,67 @@ fn git_reset_test() {
     assert_eq!(data, original_data.as_bytes())
 }
 
+#[test]
+/// Test that untracked and ignored files are discarded, except for key files
+fn git_discard_test() {
+    configure();
+    let remote_path = &format!("{}/discard_test.git", GIT_REMOTE_CLONE_URL);
+    let repo_path = &format!("{}/discard_test", GIT_CLIENT_DIR);
+    let tracked_path = &format!("{}/.discard_test", repo_path);
+
+    let store = &clone(remote_path, repo_path);
+    let original_data = fs::read(tracked_path).expect("read file failed");
+
+    fs::create_dir_all(format!("{}/empty", repo_path)).unwrap();
+    fs::create_dir_all(format!("{}/nested/dir", repo_path)).unwrap();
+    fs::create_dir_all(format!("{}/team", repo_path)).unwrap();
+    fs::write(format!("{}/.git/info/exclude", repo_path), "*.ignored\n")
+        .expect("write file failed");
+    for path in [
+        "untracked",
+        "staged",
+        "file.ignored",
+        "nested/dir/file",
+        ".age-identities",
+        "team/.age-recipients",
+        "team/other",
+    ] {
+        fs::write(format!("{}/{}", repo_path, path), "Content")
+            .expect("write file failed");
+    }
+    assert_ok(git_stage(store, "staged"));
+    fs::write(tracked_path, "Modified").expect("write file failed");
+
+    let expected = vec![
+        "empty/",
+        "file.ignored",
+        "nested/",
+        "staged",
+        "team/other",
+        "untracked",
+    ];
+
+    // Nothing is changed during a dry-run
+    assert_eq!(assert_ok(git_discard(store, true)), expected);
+    assert!(Path::new(&format!("{}/untracked", repo_path)).exists());
+    assert_eq!(fs::read(tracked_path).unwrap(), b"Modified");
+
+    assert_eq!(assert_ok(git_discard(store, false)), expected);
+    for path in expected {
+        assert!(!Path::new(&format!("{}/{}", repo_path, path)).exists());
+    }
+    assert_eq!(fs::read(tracked_path).unwrap(), original_data);
+    assert!(Path::new(&format!("{}/.age-identities", repo_path)).exists());
+    assert!(Path::new(&format!("{}/team/.age-recipients", repo_path)).exists());
+
+    // Nothing left to discard
+    assert!(assert_ok(git_discard(store, true)).is_empty());
+}
+
 #[test]
 /// Test that we can remove folder and add file in the same commit.
 fn git_stage_multiple_test() {
-    git_setup();
+    configure();
     let remote_path =
         &format!("{}/stage_multiple_test.git", GIT_REMOTE_CLONE_URL);
     let repo_path = &format!("{}/stage_multiple_test", GIT_CLIENT_DIR);
@@ -268,7 +1985,7 @@ fn git_stage_multiple_test() {
     let file3_path = format!("{}/{}", folder2_path, file3);
     let file4_path = format!("{}/{}", folder1_path, file4);
 
-    clone(remote_path, repo_path);
+    let store = &clone(remote_path, repo_path);
 
     fs::create_dir(&folder1_path).expect("create directory failed");
     fs::create_dir(&folder2_path).expect("create directory failed");
@@ -277,33 +1994,104 @@ fn git_stage_multiple_test() {
     fs::write(&file3_path, "Third").expect("write file failed");
 
     // Commit all of folder1
-    assert_ok(git_stage(repo_path, &folder1));
-    assert_ok(git_commit(repo_path, &format!("Add '{}'", folder1)));
-    assert_ok(git_push(repo_path));
+    assert_ok(git_stage(store, &folder1));
+    assert_ok(git_commit(store, &format!("Add '{}'", folder1)));
+    assert_ok(git_push(store));
 
     // Remove subfolder (folder2) and create a new file under folder1
     rm_rf(&folder2_path);
     fs::write(&file4_path, "Fourth").expect("write file failed");
 
-    assert_ok(git_stage(repo_path, &folder1));
+    assert_ok(git_stage(store, &folder1));
     assert_ok(git_commit(
-        repo_path,
+        store,
         &format!("Remove '{}' and add '{}'", folder2, file4),
     ));
-    assert_ok(git_push(repo_path));
+    assert_ok(git_push(store));
 
     // Remove folder
     rm_rf(&folder1_path);
 
-    assert_ok(git_stage(repo_path, &folder1));
-    assert_ok(git_commit(repo_path, &format!("Remove '{}'", folder1)));
-    assert_ok(git_push(repo_path));
+    assert_ok(git_stage(store, &folder1));
+    assert_ok(git_commit(store, &format!("Remove '{}'", folder1)));
+    assert_ok(git_push(store));
+}
+
+#[test]
+/// Test that staged and unstaged changes are reported
+fn git_status_test() {
+    configure();
+    let remote_path = &format!("{}/status_test.git", GIT_REMOTE_CLONE_URL);
+    let repo_path = &format!("{}/status_test", GIT_CLIENT_DIR);
+    let now = current_time();
+
+    let file_to_modify = &format!("file_to_modify-{}", now);
+    let file_to_remove = &format!("file_to_remove-{}", now);
+    let file_to_rename = &format!("file_to_rename-{}", now);
+    let file_renamed = &format!("file_renamed-{}", now);
+    let file_staged = &format!("file_staged-{}", now);
+    let folder_untracked = &format!("folder-{}", now);
+    let file_untracked = &format!("{}/file_untracked", folder_untracked);
+
+    let store = &clone(remote_path, repo_path);
+    for file in [file_to_modify, file_to_remove, file_to_rename] {
+        fs::write(format!("{}/{}", repo_path, file), file)
+            .expect("write file failed");
+        assert_ok(git_stage(store, file));
+    }
+    assert_ok(git_commit(store, "Test commit"));
+
+    // Nothing changed
+    let arr = assert_ok(git_status(store, &AgeState::default()));
+    assert!(arr.is_empty());
+
+    // Unstaged modification
+    fs::write(format!("{}/{}", repo_path, file_to_modify), "Modified")
+        .expect("write file failed");
+    // Staged deletion
+    fs::remove_file(format!("{}/{}", repo_path, file_to_remove))
+        .expect("remove file failed");
+    assert_ok(git_stage(store, file_to_remove));
+    // Staged rename
+    fs::rename(
+        format!("{}/{}", repo_path, file_to_rename),
+        format!("{}/{}", repo_path, file_renamed),
+    )
+    .expect("rename failed");
+    assert_ok(git_stage(store, file_to_rename));
+    assert_ok(git_stage(store, file_renamed));
+    // Staged new file with unstaged modifications
+    fs::write(format!("{}/{}", repo_path, file_staged), "Staged")
+        .expect("write file failed");
+    assert_ok(git_stage(store, file_staged));
+    fs::write(format!("{}/{}", repo_path, file_staged), "Modified")
+        .expect("write file failed");
+    // Untracked file in an untracked folder
+    fs::create_dir(format!("{}/{}", repo_path, folder_untracked))
+        .expect("create directory failed");
+    fs::write(format!("{}/{}", repo_path, file_untracked), "Untracked")
+        .expect("write file failed");
+
+    let arr = assert_ok(git_status(store, &AgeState::default()));
+    let mut arr: Vec<String> = arr.iter().map(|e| e.to_string()).collect();
+    arr.sort();
+    let mut expected = vec![
+        format!(" M\n{}", file_to_modify),
+        format!("D \n{}", file_to_remove),
+        format!("R \n{}\n{}", file_to_rename, file_renamed),
+        format!("AM\n{}", file_staged),
+        format!("??\n{}", file_untracked),
+    ];
+    expected.sort();
+    assert_eq!(arr, expected);
+
+    assert_ok(git_reset(store));
 }
 
 #[test]
 /// Commit messages can be retrieved
 fn git_log_test() {
-    git_setup();
+    configure();
     let remote_path = &format!("{}/log_test.git", GIT_REMOTE_CLONE_URL);
     let repo_path = &format!("{}/log_test", GIT_CLIENT_DIR);
     let now = current_time();
@@ -312,18 +2100,18 @@ fn git_log_test() {
     let msg1 = &format!("Add '{}'", filename);
     let msg2 = &format!("Modified '{}'", filename);
 
-    clone(remote_path, repo_path);
+    let store = &clone(remote_path, repo_path);
     fs::write(&file_path, "Content").expect("write file failed");
-    assert_ok(git_stage(repo_path, &filename));
-    assert_ok(git_commit(repo_path, msg1));
-    assert_ok(git_push(repo_path));
+    assert_ok(git_stage(store, &filename));
+    assert_ok(git_commit(store, msg1));
+    assert_ok(git_push(store));
     fs::write(&file_path, "Modified").expect("write file failed");
-    assert_ok(git_stage(repo_path, &filename));
-    assert_ok(git_commit(repo_path, msg2));
-    assert_ok(git_push(repo_path));
+    assert_ok(git_stage(store, &filename));
+    assert_ok(git_commit(store, msg2));
+    assert_ok(git_push(store));
 
     // Retrieve the `git log`
-    let r = git_log(repo_path);
+    let r = git_log(store, &AgeState::default());
 
     if let Some(err) = r.as_ref().err() {
         error!("{}", err);
@@ -338,12 +2126,144 @@ fn git_log_test() {
     assert_eq!(arr[1].split("\n").last().unwrap(), msg1);
 }
 
+#[test]
+/// Test that the last commit that modified each entry is found
+fn git_last_changed_test() {
+    configure();
+    let remote_path =
+        &format!("{}/last_changed_test.git", GIT_REMOTE_CLONE_URL);
+    let repo_path = &format!("{}/last_changed_test", GIT_CLIENT_DIR);
+    let now = current_time();
+    let external_client_path = &format!("/tmp/.last_changed_test-{}", now);
+    let dir = &format!("dir-{}", now);
+    let first = &format!("{}/first", dir);
+    let second = &format!("{}/second", dir);
+
+    let store = &clone(remote_path, repo_path);
+    fs::create_dir_all(format!("{}/{}", repo_path, dir)).unwrap();
+    for file in [first, second] {
+        fs::write(format!("{}/{}", repo_path, file), "Content")
+            .expect("write file failed");
+        assert_ok(git_stage(store, file));
+    }
+    assert_ok(git_commit(store, "Add both"));
+    assert_ok(git_push(store));
+
+    let changes = assert_ok(git_last_changed(store, &AgeState::default(), dir));
+    assert_eq!(changes.len(), 2);
+    assert!(changes.iter().all(|c| c.commit.summary == "Add both"));
+
+    // Modify one of the entries from another client
+    clone(remote_path, external_client_path);
+    fs::write(format!("{}/{}", external_client_path, second), "Modified")
+        .expect("write file failed");
+    external_push_file(external_client_path, second);
+    assert_ok(git_pull(store, &AgeState::default()));
+
+    let changes = assert_ok(git_last_changed(
+        store,
+        &AgeState::default(),
+        &format!("{}/", dir),
+    ));
+    assert_eq!(changes.len(), 2);
+    assert_eq!(changes[0].path, *first);
+    assert_eq!(changes[0].commit.summary, "Add both");
+    assert_eq!(changes[0].commit.author, GIT_USERNAME);
+    assert_eq!(changes[1].path, *second);
+    assert_eq!(changes[1].commit.summary, format!("Adding {}", second));
+    assert_eq!(changes[1].commit.oid.to_string(), head_oid(repo_path));
+
+    // Single entries and the whole tree
+    let changes =
+        assert_ok(git_last_changed(store, &AgeState::default(), first));
+    assert_eq!(changes.len(), 1);
+    let changes = assert_ok(git_last_changed(store, &AgeState::default(), ""));
+    assert!(changes.len() >= 3);
+    assert!(assert_ok(git_last_changed(
+        store,
+        &AgeState::default(),
+        "missing"
+    ))
+    .is_empty());
+
+    rm_rf(external_client_path);
+}
+
+#[test]
+/// Test that we can revert commits that add, modify and delete files
+fn git_revert_test() {
+    configure();
+    let remote_path = &format!("{}/revert_test.git", GIT_REMOTE_CLONE_URL);
+    let repo_path = &format!("{}/revert_test", GIT_CLIENT_DIR);
+    let now = current_time();
+    let filename = &format!("file-{}", now);
+    let file_path = format!("{}/{}", repo_path, filename);
+    let conflict_filename = &format!("conflict_file-{}", now);
+    let conflict_file_path = format!("{}/{}", repo_path, conflict_filename);
+
+    let store = &clone(remote_path, repo_path);
+
+    fs::write(&file_path, "Content").expect("write file failed");
+    assert_ok(git_stage(store, &filename));
+    assert_ok(git_commit(store, &format!("Add '{}'", filename)));
+    let add_oid = head_oid(repo_path);
+
+    fs::write(&file_path, "Modified").expect("write file failed");
+    assert_ok(git_stage(store, &filename));
+    assert_ok(git_commit(store, &format!("Modified '{}'", filename)));
+    let modify_oid = head_oid(repo_path);
+
+    // Revert the modification
+    assert_ok(git_revert(store, &modify_oid));
+    let data = fs::read(&file_path).expect("read file failed");
+    assert_eq!(data, "Content".as_bytes());
+
+    // Revert the addition
+    assert_ok(git_revert(store, &add_oid));
+    assert!(fs::metadata(&file_path).is_err());
+
+    // Revert the revert of the addition
+    let revert_oid = head_oid(repo_path);
+    assert_ok(git_revert(store, &revert_oid));
+    let data = fs::read(&file_path).expect("read file failed");
+    assert_eq!(data, "Content".as_bytes());
+
+    let arr =
+        git_log(store, &AgeState::default()).expect("Git operation failed");
+    assert!(
+        arr[0].ends_with(&format!("Revert \"Revert \"Add '{}'\"\"", filename))
+    );
+    assert_ok(git_push(store));
+
+    // Reverting the addition of a file that has since been modified conflicts
+    fs::write(&conflict_file_path, "Content").expect("write file failed");
+    assert_ok(git_stage(store, &conflict_filename));
+    assert_ok(git_commit(store, &format!("Add '{}'", conflict_filename)));
+    let add_oid = head_oid(repo_path);
+
+    fs::write(&conflict_file_path, "Modified").expect("write file failed");
+    assert_ok(git_stage(store, &conflict_filename));
+    assert_ok(git_commit(store, "Modified"));
+    let modify_oid = head_oid(repo_path);
+
+    assert_err(git_revert(store, &add_oid));
+    assert_eq!(head_oid(repo_path), modify_oid);
+    let data = fs::read(&conflict_file_path).expect("read file failed");
+    assert_eq!(data, "Modified".as_bytes());
+
+    // Uncommitted changes are not overwritten
+    fs::write(&conflict_file_path, "Uncommitted").expect("write file failed");
+    assert_err(git_revert(store, &modify_oid));
+    let data = fs::read(&conflict_file_path).expect("read file failed");
+    assert_eq!(data, "Uncommitted".as_bytes());
+}
+
 // Error cases /////////////////////////////////////////////////////////////////
 
 #[test]
 // Push/pull to a remote with untracked changes fails with expected errors
 fn git_bad_conflict_test() {
-    git_setup();
+    configure();
     let remote_path =
         &format!("{}/bad_conflict_test.git", GIT_REMOTE_CLONE_URL);
     let repo_path = &format!("{}/bad_conflict_test", GIT_CLIENT_DIR);
@@ -356,21 +2276,21 @@ fn git_bad_conflict_test() {
     let file_our_path = &format!("{}/{}", repo_path, file);
 
     // Clone into two locations
-    clone(remote_path, repo_path);
+    let store = &clone(remote_path, repo_path);
     clone(remote_path, external_client_path);
 
     // Create conflicting commits in each checkout
     fs::write(&file_our_path, "My content").expect("write file failed");
-    assert_ok(git_stage(repo_path, &file));
-    assert_ok(git_commit(repo_path, "My commit"));
+    assert_ok(git_stage(store, &file));
+    assert_ok(git_commit(store, "My commit"));
 
     fs::write(&file_external_client_path, "External content")
         .expect("write file failed");
     external_push_file(external_client_path, file);
 
     // Try to push/pull after the external update has occurred
-    assert_err(git_push(repo_path));
-    assert_err(git_pull(repo_path));
+    assert_err(git_push(store));
+    assert_err(git_pull(store, &AgeState::default()));
 
     // Clean up external checkout
     rm_rf(external_client_path);
@@ -379,51 +2299,55 @@ fn git_bad_conflict_test() {
 #[test]
 // Trying to create a commit from an empty folder fails with expected errors
 fn git_bad_commit_folder_test() {
-    git_setup();
+    configure();
     let remote_path =
         &format!("{}/bad_commit_folder_test.git", GIT_REMOTE_CLONE_URL);
     let repo_path = &format!("{}/bad_commit_folder_test", GIT_CLIENT_DIR);
     let now = current_time();
 
-    clone(remote_path, repo_path);
+    let store = &clone(remote_path, repo_path);
 
     let folder = &format!("folder-{}", now);
     let folder_path = format!("{}/{}", repo_path, folder);
 
-    assert_ok(git_stage(repo_path, &folder_path)); // NOOP
-    assert_err(git_commit(repo_path, "Empty commit")); // Do not allow empty commits
-    assert_ok(git_push(repo_path));
+    assert_ok(git_stage(store, &folder_path)); // NOOP
+    assert_err(git_commit(store, "Empty commit")); // Do not allow empty commits
+    assert_ok(git_push(store));
 }
 
 #[test]
 /// Test that a clone operation times out when the remote host is unreachable
 fn git_bad_clone_test() {
-    git_setup();
+    configure();
     let repo_path = &format!("{}/bad_remote", GIT_CLIENT_DIR);
 
     // Unsupported protocol
     rm_rf(repo_path);
-    assert_err(git_clone("https://127.0.0.1/bad_host", repo_path));
+    let store = &test_store("https://127.0.0.1/bad_host", repo_path);
+    assert_err(git_clone(store, 0, &[]));
 
     // Unreachable host, with port
     rm_rf(repo_path);
-    assert_err(git_clone("git://169.254.111.111:9988/bad_host", repo_path));
+    let store = &test_store("git://169.254.111.111:9988/bad_host", repo_path);
+    assert_err(git_clone(store, 0, &[]));
 
     // Unreachable host, no port
     rm_rf(repo_path);
-    assert_err(git_clone("git://169.254.111.111/bad_host", repo_path));
+    let store = &test_store("git://169.254.111.111/bad_host", repo_path);
+    assert_err(git_clone(store, 0, &[]));
 }
 
 ////////////////////////////////////////////////////////////////////////////////
 
-fn assert_ok(result: Result<(), git2::Error>) {
+fn assert_ok<T>(result: Result<T, git2::Error>) -> T {
     if let Some(err) = result.as_ref().err() {
         error!("{}", err);
     }
-    assert!(result.is_ok())
+    assert!(result.is_ok());
+    result.unwrap()
 }
 
-fn assert_err(result: Result<(), git2::Error>) {
+fn assert_err<T>(result: Result<T, git2::Error>) {
     if let Some(_) = result.as_ref().ok() {
         error!("Unexpected successful result");
     }
@@ -446,12 +2370,53 @@ fn rm_rf(path: &str) {
     }
 }
 
-fn clone(url: &str, into: &str) {
+static CONFIGURE: Once = Once::new();
+
+/// The configuration is global and tests run in parallel, it is therefore
+/// only applied once rather than being replaced by each test.
+fn configure() {
+    CONFIGURE.call_once(|| assert_ok(git_configure(test_config())));
+}
+
+/// Shorter timeouts than the defaults so that unreachable remotes fail fast
+fn test_config() -> Config {
+    Config {
+        timeout_ms: 1000,
+        connect_timeout_ms: 1000,
+        ..Config::default()
+    }
+}
+
+fn test_store(url: &str, into: &str) -> Store {
+    let identity_path = format!("{}/.age-identities", into);
+    Store::new(into, url, &test_config().default_branch, &identity_path)
+}
+
+fn clone(url: &str, into: &str) -> Store {
     // Remove previous checkout if needed
     rm_rf(into);
 
-    assert_ok(git_clone(url, into));
-    assert_ok(git_config_set_user(into, GIT_USERNAME));
+    let store = test_store(url, into);
+    assert_ok(git_clone(&store, 0, &[]));
+    assert_ok(git_config_set_user(&store, GIT_USERNAME));
+    store
+}
+
+fn head_oid(repo_path: &str) -> String {
+    let repo = git2::Repository::open(repo_path).expect("open failed");
+    let oid = repo.head().unwrap().target().unwrap();
+    oid.to_string()
+}
+
+fn is_shallow(repo_path: &str) -> bool {
+    let repo = git2::Repository::open(repo_path).expect("open failed");
+    repo.is_shallow()
+}
+
+fn tree_has_path(repo_path: &str, path: &str) -> bool {
+    let repo = git2::Repository::open(repo_path).expect("open failed");
+    let tree = repo.head().unwrap().peel_to_tree().unwrap();
+    tree.get_path(Path::new(path)).is_ok()
 }
 
 fn current_time() -> u128 {
@@ -484,8 +2449,8 @@ fn external_push_file(repo_path: &str, filepath: &str) {
     let status = Command::new("git")
         .arg("push")
         .arg("-q")
-        .arg(GIT_REMOTE)
-        .arg(GIT_BRANCH)
+        .arg(test_config().remote)
+        .arg(test_config().default_branch)
         .current_dir(repo_path)
         .status()
         .expect("command failed");
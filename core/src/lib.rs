@@ -6,9 +6,23 @@ mod log_android;
 
 mod util;
 
+// Config
+mod config;
+#[cfg(test)]
+mod config_test;
+
 #[cfg(not(target_os = "android"))]
 mod ffi;
 
+// Store
+mod store;
+#[cfg(not(target_os = "android"))]
+mod store_ffi;
+#[cfg(target_os = "android")]
+mod store_jni;
+#[cfg(test)]
+mod store_test;
+
 // Age
 mod age;
 mod age_error;
@@ -0,0 +1,223 @@
+//! Registry of the password stores that the library knows about. Each store
+//! is identified by a handle that is passed to all git and age operations,
+//! this allows several stores to be used side by side.
+
+use std::collections::{HashMap, HashSet};
+use std::ops::{Deref, DerefMut};
+use std::sync::LazyLock;
+use std::sync::Mutex;
+
+use crate::config::config_get;
+use crate::git::git_last_changed_evict;
+
+pub type StoreHandle = i32;
+
+/// Handle for state that does not belong to a store, e.g. errors from
+/// `git_configure()`. Registered stores never use this handle.
+pub const STORE_NONE: StoreHandle = -1;
+
+static STORES: LazyLock<Mutex<StoreRegistry>> =
+    LazyLock::new(|| Mutex::new(StoreRegistry::default()));
+
+/// A password store, i.e. a git repository with age encrypted entries
+#[derive(Debug, Clone)]
+pub struct Store {
+    /// Path to the local clone
+    pub repo_path: String,
+    /// URL to clone from
+    pub remote_url: String,
+    /// Branch to pull from and push to
+    pub branch: String,
+    /// Passphrase encrypted identity used for decryption
+    pub identity_path: String,
+}
+
+impl Store {
+    /// The checked out branch of an existing clone, or otherwise the
+    /// configured default branch, is used if `branch` is empty
+    pub fn new(
+        repo_path: &str,
+        remote_url: &str,
+        branch: &str,
+        identity_path: &str,
+    ) -> Self {
+        Self {
+            repo_path: repo_path.to_string(),
+            remote_url: remote_url.to_string(),
+            branch: if branch.is_empty() {
+                checked_out_branch(repo_path)
+                    .unwrap_or_else(|| config_get().default_branch)
+            } else {
+                branch.to_string()
+            },
+            identity_path: identity_path.to_string(),
+        }
+    }
+}
+
+fn checked_out_branch(repo_path: &str) -> Option<String> {
+    let repo = git2::Repository::open(repo_path).ok()?;
+    let head = repo.head().ok()?;
+    if !head.is_branch() {
+        return None;
+    }
+    head.shorthand().map(|name| name.to_string())
+}
+
+#[derive(Default)]
+struct StoreRegistry {
+    stores: HashMap<StoreHandle, Store>,
+    next_handle: StoreHandle,
+}
+
+/// Add a store to the registry and return its handle. Registering a store
+/// with the same `repo_path` as an existing store replaces the existing store
+/// and keeps its handle.
+pub fn store_register(store: Store) -> Option<StoreHandle> {
+    let Ok(mut registry) = STORES.lock() else {
+        error!("Store registry unavailable");
+        return None;
+    };
+
+    let existing = registry
+        .stores
+        .iter()
+        .find(|(_, s)| s.repo_path == store.repo_path)
+        .map(|(handle, _)| *handle);
+
+    let handle = match existing {
+        Some(handle) => handle,
+        None => {
+            let handle = registry.next_handle;
+            registry.next_handle += 1;
+            handle
+        }
+    };
+    debug!("Registered store {}: '{}'", handle, store.repo_path);
+    registry.stores.insert(handle, store);
+    Some(handle)
+}
+
+/// Remove a store from the registry, returns the removed store if it existed.
+/// Cached results for the store are dropped.
+pub fn store_unregister(handle: StoreHandle) -> Option<Store> {
+    let Ok(mut registry) = STORES.lock() else {
+        error!("Store registry unavailable");
+        return None;
+    };
+    let store = registry.stores.remove(&handle)?;
+    drop(registry);
+
+    git_last_changed_evict(&store.repo_path);
+    Some(store)
+}
+
+/// Change the branch that is pulled from and pushed to for `handle`, this
+/// should be done before the branch is checked out with
+/// `git_branch_switch()` and reverted if the checkout fails. Returns `None`
+/// if there is no such store.
+pub fn store_set_branch(handle: StoreHandle, branch: &str) -> Option<()> {
+    let Ok(mut registry) = STORES.lock() else {
+        error!("Store registry unavailable");
+        return None;
+    };
+    let store = registry.stores.get_mut(&handle)?;
+    store.branch = branch.to_string();
+    debug!("Store {} switched to '{}'", handle, branch);
+    Some(())
+}
+
+/// Returns a copy of the store for `handle`, the registry lock is only held
+/// during the lookup so that operations on different stores do not block
+/// each other.
+pub fn store_get(handle: StoreHandle) -> Option<Store> {
+    let Ok(registry) = STORES.lock() else {
+        error!("Store registry unavailable");
+        return None;
+    };
+    let store = registry.stores.get(&handle).cloned();
+    if store.is_none() {
+        error!("Unknown store: {}", handle);
+    }
+    store
+}
+
+/// Library state that is kept separately for each store. The state of a
+/// store is taken out of the map while it is in use, so that operations on
+/// different stores never wait for each other.
+pub struct StoreStates<T> {
+    inner: Mutex<StoreStatesInner<T>>,
+}
+
+struct StoreStatesInner<T> {
+    states: HashMap<StoreHandle, T>,
+    in_use: HashSet<StoreHandle>,
+}
+
+impl<T> StoreStates<T> {
+    pub fn new() -> Self {
+        Self {
+            inner: Mutex::new(StoreStatesInner {
+                states: HashMap::new(),
+                in_use: HashSet::new(),
+            }),
+        }
+    }
+
+    /// Take the state for `handle`, `None` is returned if the state is
+    /// already in use by another thread. The shared lock is only held while
+    /// the state is moved in or out of the map.
+    pub fn try_lock(&'static self, handle: StoreHandle) -> Option<StoreLock<T>> {
+        let Ok(mut inner) = self.inner.lock() else {
+            error!("Store state unavailable");
+            return None;
+        };
+        if !inner.in_use.insert(handle) {
+            error!("Mutex lock already taken: {}", handle);
+            return None;
+        }
+        let state = inner.states.remove(&handle);
+        Some(StoreLock {
+            states: self,
+            handle,
+            state,
+        })
+    }
+}
+
+/// Exclusive access to the state of one store, the state is put back once
+/// the lock is dropped. Setting the state to `None` removes it.
+pub struct StoreLock<T: 'static> {
+    states: &'static StoreStates<T>,
+    handle: StoreHandle,
+    state: Option<T>,
+}
+
+impl<T> Deref for StoreLock<T> {
+    type Target = Option<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.state
+    }
+}
+
+impl<T> DerefMut for StoreLock<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.state
+    }
+}
+
+impl<T> Drop for StoreLock<T> {
+    fn drop(&mut self) {
+        // The in-use flag must always be cleared, even if another thread
+        // panicked while holding the shared lock.
+        let mut inner = match self.states.inner.lock() {
+            Ok(inner) => inner,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        inner.in_use.remove(&self.handle);
+        if let Some(state) = self.state.take() {
+            inner.states.insert(self.handle, state);
+        }
+    }
+}
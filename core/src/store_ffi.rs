@@ -0,0 +1,45 @@
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int};
+
+use crate::age::age_try_lock;
+use crate::store::*;
+use crate::*;
+
+/// Register a store and return its handle, -1 is returned on failure. The
+/// checked out branch of an existing clone, or otherwise the configured
+/// default branch, is used if `branch` is empty.
+#[no_mangle]
+pub extern "C" fn ffi_store_register(
+    repo_path: *const c_char,
+    remote_url: *const c_char,
+    branch: *const c_char,
+    identity_path: *const c_char,
+) -> c_int {
+    let repo_path = unsafe { CStr::from_ptr(repo_path).to_str() };
+    let remote_url = unsafe { CStr::from_ptr(remote_url).to_str() };
+    let branch = unsafe { CStr::from_ptr(branch).to_str() };
+    let identity_path = unsafe { CStr::from_ptr(identity_path).to_str() };
+
+    let (Ok(repo_path), Ok(remote_url), Ok(branch), Ok(identity_path)) =
+        (repo_path, remote_url, branch, identity_path)
+    else {
+        return -1;
+    };
+
+    let store = Store::new(repo_path, remote_url, branch, identity_path);
+    store_register(store).unwrap_or(-1) as c_int
+}
+
+/// Remove a store from the registry, the identity of the store is locked.
+#[no_mangle]
+pub extern "C" fn ffi_store_unregister(store: c_int) -> c_int {
+    let Some(mut age_lock) = age_try_lock(store) else {
+        return KAGE_ERROR_LOCK_TAKEN as c_int;
+    };
+    *age_lock = None;
+
+    match store_unregister(store) {
+        Some(_) => 0,
+        None => -1,
+    }
+}
@@ -0,0 +1,53 @@
+use jni::objects::{JClass, JString};
+use jni::sys::jint;
+use jni::JNIEnv;
+
+use crate::age::age_try_lock;
+use crate::store::*;
+use crate::KAGE_ERROR_LOCK_TAKEN;
+
+macro_rules! load_jstring {
+    ($env:ident, $string:ident) => (
+        let Ok($string) = $env.get_string(&$string) else {
+            return -1 as jint;
+        };
+        let Ok($string) = $string.to_str() else {
+            return -1 as jint;
+        };
+    )
+}
+
+#[no_mangle]
+pub extern "system" fn Java_one_kafva_kage_jni_Store_register<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    repo_path: JString<'local>,
+    remote_url: JString<'local>,
+    branch: JString<'local>,
+    identity_path: JString<'local>,
+) -> jint {
+    load_jstring!(env, repo_path);
+    load_jstring!(env, remote_url);
+    load_jstring!(env, branch);
+    load_jstring!(env, identity_path);
+
+    let store = Store::new(repo_path, remote_url, branch, identity_path);
+    store_register(store).unwrap_or(-1) as jint
+}
+
+#[no_mangle]
+pub extern "system" fn Java_one_kafva_kage_jni_Store_unregister<'local>(
+    _env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    store: jint,
+) -> jint {
+    let Some(mut age_lock) = age_try_lock(store) else {
+        return KAGE_ERROR_LOCK_TAKEN as jint;
+    };
+    *age_lock = None;
+
+    match store_unregister(store) {
+        Some(_) => 0,
+        None => -1,
+    }
+}
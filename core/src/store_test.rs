@@ -0,0 +1,70 @@
+use crate::store::*;
+
+#[test]
+/// Test that stores can be registered, replaced and unregistered
+fn store_register_test() {
+    let personal = Store::new(
+        "/tmp/.store_register_test/personal",
+        "git://127.0.0.1/personal.git",
+        "main",
+        "/tmp/.store_register_test/personal/.age-identities",
+    );
+    let team = Store::new(
+        "/tmp/.store_register_test/team",
+        "git://127.0.0.1/team.git",
+        "develop",
+        "/tmp/.store_register_test/team/.age-identities",
+    );
+
+    let personal_handle = store_register(personal.clone()).unwrap();
+    let team_handle = store_register(team.clone()).unwrap();
+    assert_ne!(personal_handle, team_handle);
+
+    let s = store_get(team_handle).unwrap();
+    assert_eq!(s.repo_path, team.repo_path);
+    assert_eq!(s.branch, "develop");
+
+    // Registering the same path again keeps the handle
+    let mut renamed = personal.clone();
+    renamed.branch = "renamed".to_string();
+    assert_eq!(store_register(renamed).unwrap(), personal_handle);
+    assert_eq!(store_get(personal_handle).unwrap().branch, "renamed");
+
+    assert!(store_set_branch(personal_handle, "staging").is_some());
+    assert_eq!(store_get(personal_handle).unwrap().branch, "staging");
+    assert_eq!(store_get(team_handle).unwrap().branch, "develop");
+
+    assert!(store_unregister(personal_handle).is_some());
+    assert!(store_set_branch(personal_handle, "staging").is_none());
+    assert!(store_get(personal_handle).is_none());
+    assert!(store_unregister(personal_handle).is_none());
+
+    // Other stores are unaffected
+    assert_eq!(store_get(team_handle).unwrap().remote_url, team.remote_url);
+    store_unregister(team_handle);
+}
+
+#[test]
+/// Test that the state of one store can be locked while another store is in
+/// use and that the state is kept between locks
+fn store_states_test() {
+    static STATES: std::sync::LazyLock<StoreStates<String>> =
+        std::sync::LazyLock::new(StoreStates::new);
+
+    let mut personal = STATES.try_lock(0).unwrap();
+    assert!(personal.is_none());
+    *personal = Some("personal".to_string());
+
+    // The same store cannot be locked twice, other stores are unaffected
+    assert!(STATES.try_lock(0).is_none());
+    let team = STATES.try_lock(1).unwrap();
+    assert!(team.is_none());
+    drop(team);
+    drop(personal);
+
+    let mut personal = STATES.try_lock(0).unwrap();
+    assert_eq!(personal.as_deref(), Some("personal"));
+    *personal = None;
+    drop(personal);
+    assert!(STATES.try_lock(0).unwrap().is_none());
+}
@@ -4,16 +4,17 @@ import System
 // periphery: ignore
 @_silgen_name("ffi_age_unlock_identity")
 func ffi_age_unlock_identity(
-    encryptedIdentity: UnsafePointer<CChar>,
+    _ store: CInt,
     passphrase: UnsafePointer<CChar>
 ) -> CInt
 
 @_silgen_name("ffi_age_lock_identity")
-func ffi_age_lock_identity() -> CInt
+func ffi_age_lock_identity(_ store: CInt) -> CInt
 
 // periphery: ignore
 @_silgen_name("ffi_age_encrypt")
 func ffi_age_encrypt(
+    _ store: CInt,
     plaintext: UnsafePointer<CChar>,
     recipient: UnsafePointer<CChar>,
     outpath: UnsafePointer<CChar>
@@ -21,29 +22,23 @@ func ffi_age_encrypt(
 
 // periphery: ignore
 @_silgen_name("ffi_age_decrypt")
-func ffi_age_decrypt(encryptedFilepath: UnsafePointer<CChar>)
-    -> UnsafeMutablePointer<CChar>?
+func ffi_age_decrypt(
+    _ store: CInt,
+    encryptedFilepath: UnsafePointer<CChar>
+) -> UnsafeMutablePointer<CChar>?
 
 @_silgen_name("ffi_age_strerror")
-func ffi_age_strerror() -> UnsafeMutablePointer<CChar>?
+func ffi_age_strerror(_ store: CInt) -> UnsafeMutablePointer<CChar>?
 
 ////////////////////////////////////////////////////////////////////////////////
 
 enum Age {
 
-    static func unlockIdentity(
-        _ encryptedIdentityPath: FilePath,
-        passphrase: String
-    ) throws {
-        let encryptedIdentity = try String(
-            contentsOfFile: encryptedIdentityPath.string,
-            encoding: .utf8)
-        let encryptedIdentityC = try encryptedIdentity.toCString()
+    static func unlockIdentity(passphrase: String) throws {
+        let store = try Store.get()
         let passphraseC = try passphrase.toCString()
 
-        let r = ffi_age_unlock_identity(
-            encryptedIdentity: encryptedIdentityC,
-            passphrase: passphraseC)
+        let r = ffi_age_unlock_identity(store, passphrase: passphraseC)
 
         if r != 0 {
             try throwError(code: r)
@@ -52,7 +47,8 @@ enum Age {
     }
 
     static func lockIdentity() throws {
-        let r = ffi_age_lock_identity()
+        let store = try Store.get()
+        let r = ffi_age_lock_identity(store)
         if r != 0 {
             try throwError(code: r)
         }
@@ -60,8 +56,9 @@ enum Age {
     }
 
     static func decrypt(_ at: FilePath) throws -> String {
+        let store = try Store.get()
         let pathC = try at.string.toCString()
-        let plaintextC = ffi_age_decrypt(encryptedFilepath: pathC)
+        let plaintextC = ffi_age_decrypt(store, encryptedFilepath: pathC)
 
         guard let plaintextC else {
             try throwError(code: -1)
@@ -92,8 +89,10 @@ enum Age {
             .trimmingCharacters(in: .whitespacesAndNewlines)
             .toCString()
         let outpathC = try outPath.string.toCString()
+        let store = try Store.get()
 
         let r = ffi_age_encrypt(
+            store,
             plaintext: plaintextC,
             recipient: recepientC,
             outpath: outpathC)
@@ -103,7 +102,8 @@ enum Age {
     }
 
     static private func throwError(code: CInt) throws {
-        let s = ffi_age_strerror()
+        let store = try Store.get()
+        let s = ffi_age_strerror(store)
         guard let s else {
             throw AppError.ageError("code \(code)")
         }
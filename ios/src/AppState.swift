@@ -21,10 +21,7 @@ class AppState: ObservableObject {
     }
 
     func unlockIdentity(passphrase: String) throws {
-        let encryptedIdentity = GIT_DIR.appending(".age-identities")
-        try Age.unlockIdentity(
-            encryptedIdentity,
-            passphrase: passphrase)
+        try Age.unlockIdentity(passphrase: passphrase)
         identityUnlockedAt = .now
     }
 
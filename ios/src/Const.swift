@@ -11,6 +11,9 @@ let GIT_DIR: FilePath = FileManager.default.appDataDirectory
 let MAX_TREE_DEPTH: Int = 15
 let MAX_PASSWORD_LENGTH: Int = 1024
 let AUTO_LOCK_SECONDS: TimeInterval = 120.0
+/// Number of commits to fetch when cloning, the full history is rarely needed
+/// on a phone
+let GIT_CLONE_DEPTH: CInt = 100
 
 let TEXT_COLOR = Color(UIColor.label)
 let TEXT_FIELD_BG_COLOR = Color(UIColor.tertiarySystemFill)
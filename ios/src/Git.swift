@@ -5,56 +5,114 @@ struct CStringArray {
     let len: CInt
 }
 
+// periphery: ignore
+@_silgen_name("ffi_git_setup")
+func ffi_git_setup(
+    remote: UnsafePointer<CChar>,
+    defaultBranch: UnsafePointer<CChar>,
+    emailDomain: UnsafePointer<CChar>,
+    timeoutMs: CInt,
+    connectTimeoutMs: CInt
+) -> CInt
+
+// periphery: ignore
+@_silgen_name("ffi_git_config")
+func ffi_git_config() -> CStringArray
+
 // periphery: ignore
 @_silgen_name("ffi_git_clone")
 func ffi_git_clone(
-    _ url: UnsafePointer<CChar>,
-    into: UnsafePointer<CChar>
+    _ store: CInt,
+    depth: CInt,
+    sparseDirs: UnsafePointer<CChar>
 ) -> CInt
 
+// periphery: ignore
+@_silgen_name("ffi_git_deepen")
+func ffi_git_deepen(_ store: CInt, depth: CInt) -> CInt
+
 // periphery: ignore
 @_silgen_name("ffi_git_reset")
-func ffi_git_reset(_ repo: UnsafePointer<CChar>) -> CInt
+func ffi_git_reset(_ store: CInt) -> CInt
 
 // periphery: ignore
 @_silgen_name("ffi_git_config_set_user")
 func ffi_git_config_set_user(
-    _ repo: UnsafePointer<CChar>,
+    _ store: CInt,
     username: UnsafePointer<CChar>
 ) -> CInt
 
 // periphery: ignore
 @_silgen_name("ffi_git_stage")
 func ffi_git_stage(
-    _ repo: UnsafePointer<CChar>,
+    _ store: CInt,
     relativePath: UnsafePointer<CChar>
 ) -> CInt
 
 // periphery: ignore
 @_silgen_name("ffi_git_commit")
 func ffi_git_commit(
-    _ repo: UnsafePointer<CChar>,
+    _ store: CInt,
     message: UnsafePointer<CChar>
 ) -> CInt
 
 // periphery: ignore
 @_silgen_name("ffi_git_push")
-func ffi_git_push(_ repo: UnsafePointer<CChar>) -> CInt
+func ffi_git_push(_ store: CInt) -> CInt
 
 // periphery: ignore
 @_silgen_name("ffi_git_local_head_matches_remote")
-func ffi_git_local_head_matches_remote(_ repo: UnsafePointer<CChar>) -> CInt
+func ffi_git_local_head_matches_remote(_ store: CInt) -> CInt
 
 // periphery: ignore
 @_silgen_name("ffi_git_log")
-func ffi_git_log(_ repo: UnsafePointer<CChar>) -> CStringArray
+func ffi_git_log(_ store: CInt) -> CStringArray
 
 @_silgen_name("ffi_git_strerror")
-func ffi_git_strerror() -> UnsafeMutablePointer<CChar>?
+func ffi_git_strerror(_ store: CInt) -> UnsafeMutablePointer<CChar>?
+
+// periphery: ignore
+@_silgen_name("ffi_store_register")
+func ffi_store_register(
+    _ repo: UnsafePointer<CChar>,
+    remoteUrl: UnsafePointer<CChar>,
+    branch: UnsafePointer<CChar>,
+    identityPath: UnsafePointer<CChar>
+) -> CInt
 
 ////////////////////////////////////////////////////////////////////////////////
 
-private let repo = GIT_DIR
+/// The password store in `GIT_DIR`, registered with the library on first use
+enum Store {
+    private static var handle: CInt?
+
+    /// (Re-)register the store, the handle stays the same when the store is
+    /// registered again with a new remote.
+    static func register(remote: String = "") throws -> CInt {
+        let repoC = try GIT_DIR.string.toCString()
+        let remoteC = try remote.toCString()
+        let identityPath = GIT_DIR.appending(".age-identities")
+        let identityPathC = try identityPath.string.toCString()
+
+        let r = ffi_store_register(
+            repoC,
+            remoteUrl: remoteC,
+            branch: "",
+            identityPath: identityPathC)
+        if r < 0 {
+            throw AppError.gitError("Failed to register store")
+        }
+        handle = r
+        return r
+    }
+
+    static func get() throws -> CInt {
+        if let handle {
+            return handle
+        }
+        return try register()
+    }
+}
 
 enum Git {
     /// Stage and commit a new file or folder
@@ -88,50 +146,49 @@ enum Git {
     }
 
     static func clone(remote: String) throws {
-        let repoC = try repo.string.toCString()
-        let urlC = try remote.toCString()
+        let store = try Store.register(remote: remote)
 
         LOG.debug("Cloning from: \(remote)")
 
-        let r = ffi_git_clone(urlC, into: repoC)
+        let r = ffi_git_clone(store, depth: GIT_CLONE_DEPTH, sparseDirs: "")
         if r != 0 {
-            try throwError(code: r)
+            try throwError(store, code: r)
         }
     }
 
     static func push() throws {
-        let repoC = try repo.string.toCString()
-        let r = ffi_git_push(repoC)
+        let store = try Store.get()
+        let r = ffi_git_push(store)
         if r != 0 {
-            try throwError(code: r)
+            try throwError(store, code: r)
         }
         LOG.debug("Push successful")
     }
 
     static func localHeadMatchesRemote() throws -> Bool {
-        let repoC = try repo.string.toCString()
-        let r = ffi_git_local_head_matches_remote(repoC)
+        let store = try Store.get()
+        let r = ffi_git_local_head_matches_remote(store)
         if r != 0 && r != 1 {
-            try throwError(code: r)
+            try throwError(store, code: r)
         }
         return r == 1
     }
 
     static func reset() throws {
         LOG.warning("Resetting to local HEAD")
-        let repoC = try repo.string.toCString()
-        let r = ffi_git_reset(repoC)
+        let store = try Store.get()
+        let r = ffi_git_reset(store)
         if r != 0 {
-            try throwError(code: r)
+            try throwError(store, code: r)
         }
     }
 
     static func configSetUser(username: String) throws {
-        let repoC = try repo.string.toCString()
+        let store = try Store.get()
         let usernameC = try username.toCString()
-        let r = ffi_git_config_set_user(repoC, username: usernameC)
+        let r = ffi_git_config_set_user(store, username: usernameC)
         if r != 0 {
-            try throwError(code: r)
+            try throwError(store, code: r)
         }
     }
 
@@ -139,10 +196,10 @@ enum Git {
         LOG.debug("Fetching commit messages")
         var messages = [CommitInfo]()
 
-        let repoC = try repo.string.toCString()
-        let arr = ffi_git_log(repoC)
+        let store = try Store.get()
+        let arr = ffi_git_log(store)
         if arr.len < 0 {
-            try throwError(code: arr.len)
+            try throwError(store, code: arr.len)
         }
 
         for i in 0..<Int(arr.len) {
@@ -167,28 +224,28 @@ enum Git {
     }
 
     static private func commit(message: String) throws {
-        let repoC = try repo.string.toCString()
+        let store = try Store.get()
         let messageC = try message.toCString()
 
-        let r = ffi_git_commit(repoC, message: messageC)
+        let r = ffi_git_commit(store, message: messageC)
         if r != 0 {
-            try throwError(code: r)
+            try throwError(store, code: r)
         }
     }
 
     static private func stage(relativePath: String) throws {
-        let repoC = try repo.string.toCString()
+        let store = try Store.get()
         let relativePathC = try relativePath.toCString()
 
-        let r = ffi_git_stage(repoC, relativePath: relativePathC)
+        let r = ffi_git_stage(store, relativePath: relativePathC)
         if r != 0 {
-            try throwError(code: r)
+            try throwError(store, code: r)
         }
         LOG.debug("Staged '\(relativePath)'")
     }
 
-    static private func throwError(code: CInt) throws {
-        let s = ffi_git_strerror()
+    static private func throwError(_ store: CInt, code: CInt) throws {
+        let s = ffi_git_strerror(store)
         guard let s else {
             throw AppError.gitError("code \(code)")
         }